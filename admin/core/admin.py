@@ -1,11 +1,11 @@
 from django.contrib import admin
-from .models import SMTPProfile, Template, Webhook, EmailLog
+from .models import SMTPProfile, Template, Webhook, WebhookDelivery, EmailLog, EmailEventLog, EmailJob, IdempotencyKey, SMTPRoutingRule
 
 
 @admin.register(SMTPProfile)
 class SMTPProfileAdmin(admin.ModelAdmin):
-    list_display = ('smtp_server', 'company', 'smtp_username', 'smtp_port', 'is_default', 'created_at')
-    list_filter = ('is_default', 'smtp_port', 'created_at')
+    list_display = ('smtp_server', 'company', 'smtp_username', 'smtp_port', 'tls_mode', 'priority', 'weight', 'is_default', 'created_at')
+    list_filter = ('is_default', 'smtp_port', 'tls_mode', 'created_at')
     search_fields = ('smtp_server', 'company__company_name', 'smtp_username')
     raw_id_fields = ('company',)
 
@@ -20,17 +20,62 @@ class TemplateAdmin(admin.ModelAdmin):
 
 @admin.register(Webhook)
 class WebhookAdmin(admin.ModelAdmin):
-    list_display = ('name', 'company', 'url', 'status', 'is_active', 'created_at')
+    list_display = ('name', 'company', 'url', 'status', 'is_active', 'consecutive_failures', 'created_at')
     list_filter = ('is_active', 'status', 'created_at')
     search_fields = ('name', 'company__company_name', 'url')
     raw_id_fields = ('company',)
-    readonly_fields = ('last_delivered', 'success_rate')
+    readonly_fields = ('last_delivered', 'success_rate', 'secret')
 
 
 @admin.register(EmailLog)
 class EmailLogAdmin(admin.ModelAdmin):
-    list_display = ('from_email', 'to_email', 'subject', 'status', 'company', 'created_at')
-    list_filter = ('status', 'created_at')
-    search_fields = ('from_email', 'to_email', 'subject')
+    list_display = ('from_email', 'to_email', 'recipient_type', 'subject', 'status', 'attempts', 'company', 'created_at')
+    list_filter = ('status', 'recipient_type', 'created_at')
+    search_fields = ('from_email', 'to_email', 'subject', 'message_id')
+    raw_id_fields = ('company', 'smtp_profile')
+    readonly_fields = ('created_at', 'attempts', 'last_smtp_code', 'next_retry_at', 'last_error')
+
+
+@admin.register(EmailEventLog)
+class EmailEventLogAdmin(admin.ModelAdmin):
+    list_display = ('message_id', 'event', 'smtp_code', 'company', 'created_at')
+    list_filter = ('event', 'created_at')
+    search_fields = ('message_id', 'company__company_name')
     raw_id_fields = ('company',)
     readonly_fields = ('created_at',)
+
+
+@admin.register(EmailJob)
+class EmailJobAdmin(admin.ModelAdmin):
+    list_display = ('message_id', 'company', 'status', 'attempts', 'available_at', 'locked_by')
+    list_filter = ('status', 'available_at')
+    search_fields = ('message_id', 'company__company_name')
+    raw_id_fields = ('company', 'smtp_profile')
+    readonly_fields = ('created_at', 'updated_at', 'locked_at')
+
+
+@admin.register(IdempotencyKey)
+class IdempotencyKeyAdmin(admin.ModelAdmin):
+    list_display = ('key', 'company', 'message_id', 'created_at')
+    list_filter = ('created_at',)
+    search_fields = ('key', 'message_id', 'company__company_name')
+    raw_id_fields = ('company',)
+    readonly_fields = ('request_hash', 'response', 'created_at')
+
+
+@admin.register(WebhookDelivery)
+class WebhookDeliveryAdmin(admin.ModelAdmin):
+    list_display = ('event_id', 'webhook', 'event', 'attempt', 'status', 'response_status', 'latency_ms', 'created_at')
+    list_filter = ('status', 'event', 'created_at')
+    search_fields = ('event_id', 'webhook__name', 'company__company_name')
+    raw_id_fields = ('webhook', 'company')
+    readonly_fields = ('created_at', 'completed_at', 'locked_at')
+
+
+@admin.register(SMTPRoutingRule)
+class SMTPRoutingRuleAdmin(admin.ModelAdmin):
+    list_display = ('name', 'company', 'smtp_profile', 'priority', 'is_active', 'created_at')
+    list_filter = ('is_active', 'created_at')
+    search_fields = ('name', 'company__company_name', 'recipient_domain', 'from_address', 'tag', 'category')
+    raw_id_fields = ('company', 'smtp_profile', 'template', 'api_key')
+    readonly_fields = ('created_at', 'updated_at')
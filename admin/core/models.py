@@ -1,7 +1,8 @@
 from django.db import models
 from django.contrib.postgres.fields import ArrayField
-from users.constants import Status, EmailStatus
-from users.models import Company
+from django.utils import timezone
+from users.constants import EmailEvent, EmailStatus, QueueStatus, RecipientType, TlsMode, WebhookEvent, WebhookStatus, WebhookDeliveryStatus
+from users.models import APIKey, Company
 
 # Create your models here.
 
@@ -13,6 +14,19 @@ class SMTPProfile(models.Model):
     smtp_server = models.CharField(max_length=255)
     smtp_port = models.IntegerField(default=587)
     is_default = models.BooleanField(default=False)
+    tls_mode = models.CharField(
+        max_length=20,
+        choices=TlsMode.choices(),
+        default=TlsMode.STARTTLS.value,
+        db_default=TlsMode.STARTTLS.value,
+    )
+    # PEM bundle trusted in addition to the public roots, for relays with a private CA
+    ca_certificate = models.TextField(blank=True, null=True)
+    # Name to verify the server certificate against when it differs from smtp_server
+    tls_hostname = models.CharField(max_length=255, blank=True, null=True)
+    # Failover order: lower priorities are tried first, weight splits traffic within a priority
+    priority = models.IntegerField(default=0, db_default=0)
+    weight = models.IntegerField(default=1, db_default=1)
     created_at = models.DateTimeField(auto_now_add=True)
     updated_at = models.DateTimeField(auto_now=True)
 
@@ -52,14 +66,20 @@ class Webhook(models.Model):
     is_active = models.BooleanField(default=True)
     last_delivered = models.DateTimeField(blank=True, null=True)
     success_rate = models.FloatField(blank=True, null=True)
-    events = ArrayField(models.CharField(max_length=255), blank=True, null=True)
+    events = ArrayField(
+        models.CharField(max_length=255, choices=WebhookEvent.choices()),
+        blank=True,
+        null=True,
+    )
     status = models.CharField(
         max_length=255,
         blank=True,
         null=True,
-        choices=Status.choices(),
-        default=Status.ACTIVE.value,
+        choices=WebhookStatus.choices(),
+        default=WebhookStatus.ACTIVE.value,
     )
+    secret = models.CharField(max_length=255, blank=True, null=True)
+    consecutive_failures = models.IntegerField(default=0, db_default=0)
 
     def __str__(self):
         return self.name
@@ -84,6 +104,20 @@ class EmailLog(models.Model):
         default=EmailStatus.SUCCESS.value,
     )
     created_at = models.DateTimeField(auto_now_add=True)
+    attempts = models.IntegerField(default=0, db_default=0)
+    last_smtp_code = models.IntegerField(blank=True, null=True)
+    next_retry_at = models.DateTimeField(blank=True, null=True)
+    last_error = models.TextField(blank=True, null=True)
+    message_id = models.CharField(max_length=255, blank=True, null=True, db_index=True)
+    recipient_type = models.CharField(
+        max_length=10,
+        choices=RecipientType.choices(),
+        default=RecipientType.TO.value,
+        db_default=RecipientType.TO.value,
+    )
+    scheduled_at = models.DateTimeField(blank=True, null=True)
+    # Profile that handled the most recent delivery attempt
+    smtp_profile = models.ForeignKey(SMTPProfile, on_delete=models.SET_NULL, blank=True, null=True)
 
     def __str__(self):
         return f"{self.from_email} -> {self.to_email} : {self.subject[:50]} 📧"
@@ -92,3 +126,137 @@ class EmailLog(models.Model):
         db_table = "emaillog"
         verbose_name = "Email Log"
         verbose_name_plural = "Email Logs"
+        indexes = [
+            # Log listing pages by (created_at, id) within a company
+            models.Index(fields=["company", "-created_at", "-id"], name="emaillog_company_page_idx"),
+        ]
+
+
+class EmailEventLog(models.Model):
+    message_id = models.CharField(max_length=255, db_index=True)
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    event = models.CharField(max_length=50, choices=EmailEvent.choices())
+    smtp_code = models.IntegerField(blank=True, null=True)
+    detail = models.TextField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+
+    def __str__(self):
+        return f"{self.message_id} {self.event}"
+
+    class Meta:
+        db_table = "email_events"
+        verbose_name = "Email Event"
+        verbose_name_plural = "Email Events"
+
+
+class EmailJob(models.Model):
+    message_id = models.CharField(max_length=255, unique=True)
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    # Cleared when the profile is deleted; the job then goes out through the company's other profiles
+    smtp_profile = models.ForeignKey(SMTPProfile, on_delete=models.SET_NULL, blank=True, null=True)
+    payload = models.JSONField()
+    status = models.CharField(
+        max_length=50,
+        choices=QueueStatus.choices(),
+        default=QueueStatus.PENDING.value,
+        db_index=True,
+    )
+    attempts = models.IntegerField(default=0)
+    available_at = models.DateTimeField(default=timezone.now, db_index=True)
+    locked_at = models.DateTimeField(blank=True, null=True)
+    locked_by = models.CharField(max_length=255, blank=True, null=True)
+    last_error = models.TextField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+    updated_at = models.DateTimeField(auto_now=True)
+    api_key = models.ForeignKey(APIKey, on_delete=models.SET_NULL, blank=True, null=True)
+
+    def __str__(self):
+        return f"{self.message_id} ({self.status})"
+
+    class Meta:
+        db_table = "email_queue"
+        verbose_name = "Email Job"
+        verbose_name_plural = "Email Jobs"
+
+
+class IdempotencyKey(models.Model):
+    """A client-supplied Idempotency-Key and the response it produced"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    key = models.CharField(max_length=255)
+    request_hash = models.CharField(max_length=64)
+    message_id = models.CharField(max_length=255, blank=True, null=True)
+    response = models.JSONField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True, db_index=True)
+
+    def __str__(self):
+        return f"{self.key} -> {self.message_id}"
+
+    class Meta:
+        db_table = "idempotency_keys"
+        verbose_name = "Idempotency Key"
+        verbose_name_plural = "Idempotency Keys"
+        constraints = [
+            models.UniqueConstraint(fields=["company", "key"], name="idempotency_keys_company_key_uniq"),
+        ]
+
+
+class WebhookDelivery(models.Model):
+    """One attempt at delivering an event to a webhook; retries and replays add new rows"""
+
+    webhook = models.ForeignKey(Webhook, on_delete=models.CASCADE)
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    event_id = models.CharField(max_length=255, db_index=True)
+    event = models.CharField(max_length=50, choices=WebhookEvent.choices())
+    payload = models.JSONField()
+    attempt = models.IntegerField(default=1)
+    status = models.CharField(
+        max_length=50,
+        choices=WebhookDeliveryStatus.choices(),
+        default=WebhookDeliveryStatus.PENDING.value,
+        db_index=True,
+    )
+    response_status = models.IntegerField(blank=True, null=True)
+    response_body = models.TextField(blank=True, null=True)
+    error = models.TextField(blank=True, null=True)
+    latency_ms = models.IntegerField(blank=True, null=True)
+    next_attempt_at = models.DateTimeField(default=timezone.now, db_index=True)
+    locked_at = models.DateTimeField(blank=True, null=True)
+    completed_at = models.DateTimeField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+
+    def __str__(self):
+        return f"{self.event_id} -> {self.webhook} #{self.attempt} ({self.status})"
+
+    class Meta:
+        db_table = "webhook_deliveries"
+        verbose_name = "Webhook Delivery"
+        verbose_name_plural = "Webhook Deliveries"
+
+
+class SMTPRoutingRule(models.Model):
+    """Sends matching messages through a specific SMTP profile instead of the default.
+    Every criterion that is set must match; rules are checked in priority order"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    smtp_profile = models.ForeignKey(SMTPProfile, on_delete=models.CASCADE)
+    name = models.CharField(max_length=255)
+    priority = models.IntegerField(default=0, db_default=0)
+    # Matches when every recipient is at this domain
+    recipient_domain = models.CharField(max_length=255, blank=True, null=True)
+    from_address = models.CharField(max_length=255, blank=True, null=True)
+    template = models.ForeignKey(Template, on_delete=models.CASCADE, blank=True, null=True)
+    tag = models.CharField(max_length=100, blank=True, null=True)
+    category = models.CharField(max_length=100, blank=True, null=True)
+    api_key = models.ForeignKey(APIKey, on_delete=models.CASCADE, blank=True, null=True)
+    is_active = models.BooleanField(default=True, db_default=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+    updated_at = models.DateTimeField(auto_now=True)
+
+    def __str__(self):
+        return f"{self.name} -> {self.smtp_profile}"
+
+    class Meta:
+        db_table = "smtp_routing_rules"
+        verbose_name = "SMTP Routing Rule"
+        verbose_name_plural = "SMTP Routing Rules"
@@ -1,6 +1,6 @@
 from django.contrib import admin
 from django.contrib.auth.admin import UserAdmin as BaseUserAdmin
-from .models import User, Company, Industry, APIKey, TeamMember
+from .models import User, Company, Industry, APIKey, TeamMember, CreditLedgerEntry, Payment, UsageDaily, UsageSnapshot
 
 
 @admin.register(User)
@@ -54,3 +54,38 @@ class TeamMemberAdmin(admin.ModelAdmin):
     list_filter = ('role', 'created_at')
     search_fields = ('user__email', 'company__company_name')
     raw_id_fields = ('user', 'company')
+
+
+@admin.register(CreditLedgerEntry)
+class CreditLedgerEntryAdmin(admin.ModelAdmin):
+    list_display = ('company', 'entry_type', 'amount', 'message_id', 'created_at')
+    list_filter = ('entry_type', 'created_at')
+    search_fields = ('company__company_name', 'message_id')
+    raw_id_fields = ('company',)
+    readonly_fields = ('created_at',)
+
+
+@admin.register(Payment)
+class PaymentAdmin(admin.ModelAdmin):
+    list_display = ('company', 'kind', 'amount_cents', 'currency', 'credits', 'status', 'provider', 'created_at')
+    list_filter = ('kind', 'status', 'provider', 'created_at')
+    search_fields = ('company__company_name', 'provider_reference')
+    raw_id_fields = ('company',)
+    readonly_fields = ('created_at', 'updated_at')
+
+
+@admin.register(UsageSnapshot)
+class UsageSnapshotAdmin(admin.ModelAdmin):
+    list_display = ('company', 'period_start', 'period_end', 'pricing_tier', 'credits_used', 'emails_sent', 'emails_failed')
+    list_filter = ('pricing_tier', 'period_end')
+    search_fields = ('company__company_name',)
+    raw_id_fields = ('company',)
+    readonly_fields = ('created_at',)
+
+
+@admin.register(UsageDaily)
+class UsageDailyAdmin(admin.ModelAdmin):
+    list_display = ('company', 'day', 'api_key', 'smtp_profile', 'emails_sent', 'recipients', 'attachment_bytes')
+    list_filter = ('day',)
+    search_fields = ('company__company_name',)
+    raw_id_fields = ('company', 'api_key', 'smtp_profile')
@@ -45,3 +45,85 @@ class EmailStatus(EnumBase):
     SUCCESS = "Success"
     PENDING = "Pending"
     QUEUED = "Queued"
+    DEFERRED = "Deferred"
+    SCHEDULED = "Scheduled"
+    CANCELLED = "Cancelled"
+
+
+class QueueStatus(EnumBase):
+    """Status of an outbound email job"""
+
+    PENDING = "Pending"
+    PROCESSING = "Processing"
+    COMPLETED = "Completed"
+    FAILED = "Failed"
+    SCHEDULED = "Scheduled"
+    CANCELLED = "Cancelled"
+
+
+class CreditEntryType(EnumBase):
+    DEBIT = "Debit"
+    REFUND = "Refund"
+    TOPUP = "TopUp"
+    PRORATION = "Proration"
+
+
+class TlsMode(EnumBase):
+    """How an SMTP profile secures its connection"""
+
+    STARTTLS = "starttls"
+    IMPLICIT_TLS = "implicit_tls"
+    OPPORTUNISTIC = "opportunistic"
+    NONE = "none"
+
+
+class PaymentKind(EnumBase):
+    PLAN_CHANGE = "PlanChange"
+    TOPUP = "TopUp"
+
+
+class PaymentStatus(EnumBase):
+    PENDING = "Pending"
+    SUCCEEDED = "Succeeded"
+    FAILED = "Failed"
+    REFUNDED = "Refunded"
+
+
+class RecipientType(EnumBase):
+    TO = "to"
+    CC = "cc"
+    BCC = "bcc"
+
+
+class EmailEvent(EnumBase):
+    """Entries on a message's delivery timeline"""
+
+    QUEUED = "queued"
+    DEFERRED = "deferred"
+    SENT = "sent"
+    FAILED = "failed"
+    BOUNCED = "bounced"
+    SCHEDULED = "scheduled"
+    CANCELLED = "cancelled"
+
+
+class WebhookEvent(EnumBase):
+    QUEUED = "queued"
+    SENT = "sent"
+    FAILED = "failed"
+    BOUNCED = "bounced"
+
+
+class WebhookStatus(EnumBase):
+    """Delivery health of a webhook endpoint"""
+
+    ACTIVE = "Active"
+    FAILING = "Failing"
+    DISABLED = "Disabled"
+
+
+class WebhookDeliveryStatus(EnumBase):
+    PENDING = "Pending"
+    PROCESSING = "Processing"
+    SUCCEEDED = "Succeeded"
+    FAILED = "Failed"
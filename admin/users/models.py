@@ -11,7 +11,7 @@ from django.db import transaction
 from django.utils.translation import gettext_lazy as _
 from django.utils import timezone
 from sparky_utils.decorators import str_meta
-from .constants import Roles, APIKeyPermission
+from .constants import Roles, APIKeyPermission, CreditEntryType, PaymentKind, PaymentStatus
 
 # Create your models here.
 
@@ -106,6 +106,8 @@ class Company(models.Model):
     pricing_tier = models.CharField(max_length=50, choices=PRICING_TIERS, default='free')
     api_credits = models.BigIntegerField(default=20000)
     credits_reset_date = models.DateTimeField(default=timezone.now)
+    max_message_bytes = models.BigIntegerField(blank=True, null=True)
+    topup_credits = models.BigIntegerField(default=0, db_default=0)
 
     class Meta:
         db_table = "companies"
@@ -154,3 +156,100 @@ class TeamMember(models.Model):
         db_table = "team_members"
         verbose_name = "Team Member"
         verbose_name_plural = "Team Members"
+
+
+class CreditLedgerEntry(models.Model):
+    """A change to a company's API credits; debits are negative, refunds positive"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    message_id = models.CharField(max_length=255, blank=True, null=True, db_index=True)
+    amount = models.BigIntegerField()
+    entry_type = models.CharField(max_length=50, choices=CreditEntryType.choices())
+    reason = models.TextField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+    # Part of `amount` taken from or returned to top-up credits, so refunds go back where they came from
+    topup_amount = models.BigIntegerField(default=0, db_default=0)
+
+    def __str__(self):
+        return f"{self.company} {self.entry_type} {self.amount}"
+
+    class Meta:
+        db_table = "credit_ledger"
+        verbose_name = "Credit Ledger Entry"
+        verbose_name_plural = "Credit Ledger"
+
+
+class Payment(models.Model):
+    """A charge taken through the payment provider for a plan change or a credit top-up"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    kind = models.CharField(max_length=50, choices=PaymentKind.choices())
+    description = models.CharField(max_length=255)
+    amount_cents = models.BigIntegerField()
+    currency = models.CharField(max_length=3, default="usd")
+    credits = models.BigIntegerField(default=0)
+    provider = models.CharField(max_length=50)
+    provider_reference = models.CharField(max_length=255, blank=True, null=True)
+    status = models.CharField(max_length=50, choices=PaymentStatus.choices(), default=PaymentStatus.PENDING.value)
+    error = models.TextField(blank=True, null=True)
+    created_at = models.DateTimeField(auto_now_add=True)
+    updated_at = models.DateTimeField(auto_now=True)
+
+    def __str__(self):
+        return f"{self.company} {self.kind} {self.amount_cents} {self.status}"
+
+    class Meta:
+        db_table = "payments"
+        verbose_name = "Payment"
+        verbose_name_plural = "Payments"
+
+
+class UsageSnapshot(models.Model):
+    """Consumption for one closed credit period, written when the period's credits are reset"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    period_start = models.DateTimeField()
+    period_end = models.DateTimeField(db_index=True)
+    pricing_tier = models.CharField(max_length=50, choices=Company.PRICING_TIERS)
+    credits_allocated = models.BigIntegerField()
+    credits_used = models.BigIntegerField()
+    credits_remaining = models.BigIntegerField()
+    emails_sent = models.BigIntegerField(default=0)
+    emails_failed = models.BigIntegerField(default=0)
+    created_at = models.DateTimeField(auto_now_add=True)
+
+    def __str__(self):
+        return f"{self.company} {self.period_start:%Y-%m-%d} - {self.period_end:%Y-%m-%d}"
+
+    class Meta:
+        db_table = "usage_snapshots"
+        verbose_name = "Usage Snapshot"
+        verbose_name_plural = "Usage Snapshots"
+
+
+class UsageDaily(models.Model):
+    """Emails sent per company and day, split by API key and SMTP profile"""
+
+    company = models.ForeignKey(Company, on_delete=models.CASCADE)
+    day = models.DateField()
+    api_key = models.ForeignKey(APIKey, on_delete=models.SET_NULL, blank=True, null=True)
+    smtp_profile = models.ForeignKey("core.SMTPProfile", on_delete=models.SET_NULL, blank=True, null=True)
+    emails_sent = models.BigIntegerField(default=0)
+    recipients = models.BigIntegerField(default=0)
+    attachment_bytes = models.BigIntegerField(default=0)
+    updated_at = models.DateTimeField(auto_now=True)
+
+    def __str__(self):
+        return f"{self.company} {self.day}"
+
+    class Meta:
+        db_table = "usage_daily"
+        verbose_name = "Daily Usage"
+        verbose_name_plural = "Daily Usage"
+        constraints = [
+            models.UniqueConstraint(
+                fields=["company", "day", "api_key", "smtp_profile"],
+                name="usage_daily_bucket_uniq",
+                nulls_distinct=False,
+            ),
+        ]
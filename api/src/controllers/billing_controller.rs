@@ -0,0 +1,475 @@
+use crate::auth::jwt::Claims;
+use crate::controllers::company_id_for_user;
+use crate::errors::AppError;
+use crate::middleware::credits::check_and_reset_credits;
+use crate::models::users::{Company, UsageDaily};
+use crate::repositories::users::{UserRepository, UserRepositoryImpl};
+use crate::repositories::RepositoryFactory;
+use crate::services::billing::{self, CURRENCY};
+use crate::services::payments::PaymentProvider;
+use crate::utils::pricing::{PricingTier, TopUpPack};
+use crate::utils::utils::service_response;
+use actix_web::{web, HttpResponse};
+use chrono::{Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const MAX_REPORT_DAYS: i64 = 366;
+const REPORT_PERIODS: i64 = 12;
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct UsageTotals {
+    pub emails_sent: i64,
+    pub recipients: i64,
+    pub attachment_bytes: i64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, row: &UsageDaily) {
+        self.emails_sent += row.emails_sent;
+        self.recipients += row.recipients;
+        self.attachment_bytes += row.attachment_bytes;
+    }
+}
+
+#[derive(Serialize)]
+pub struct DailyUsage {
+    pub day: String,
+    #[serde(flatten)]
+    pub usage: UsageTotals,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyUsage {
+    pub api_key_id: Option<i64>,
+    pub api_key_name: Option<String>,
+    #[serde(flatten)]
+    pub usage: UsageTotals,
+}
+
+#[derive(Serialize)]
+pub struct SmtpProfileUsage {
+    pub smtp_profile_id: Option<i64>,
+    pub smtp_server: Option<String>,
+    #[serde(flatten)]
+    pub usage: UsageTotals,
+}
+
+#[derive(Serialize)]
+pub struct BillingPeriod {
+    pub period_start: String,
+    pub period_end: String,
+    pub pricing_tier: String,
+    pub credits_allocated: i64,
+    pub credits_used: i64,
+    pub credits_remaining: i64,
+    pub emails_sent: i64,
+    pub emails_failed: i64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    pub start: String,
+    pub end: String,
+    pub totals: UsageTotals,
+    pub by_day: Vec<DailyUsage>,
+    pub by_api_key: Vec<ApiKeyUsage>,
+    pub by_smtp_profile: Vec<SmtpProfileUsage>,
+    pub periods: Vec<BillingPeriod>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePlanRequest {
+    pub pricing_tier: String,
+}
+
+#[derive(Deserialize)]
+pub struct TopUpRequest {
+    pub pack: String,
+}
+
+#[derive(Serialize)]
+pub struct TierOption {
+    pub pricing_tier: String,
+    // None for Enterprise, which is unlimited and arranged with sales
+    pub monthly_credits: Option<i64>,
+    pub monthly_price_cents: Option<i64>,
+}
+
+impl From<&PricingTier> for TierOption {
+    fn from(tier: &PricingTier) -> Self {
+        Self {
+            pricing_tier: tier.to_string(),
+            monthly_credits: Some(tier.monthly_credits()).filter(|_| !tier.is_unlimited()),
+            monthly_price_cents: tier.monthly_price_cents(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TopUpPackOption {
+    pub pack: String,
+    pub credits: i64,
+    pub price_cents: i64,
+}
+
+#[derive(Serialize)]
+pub struct PlanResponse {
+    #[serde(flatten)]
+    pub plan: TierOption,
+    pub api_credits: i64,
+    pub topup_credits: i64,
+    pub credits_reset_date: String,
+    pub currency: String,
+    pub available_tiers: Vec<TierOption>,
+    pub top_up_packs: Vec<TopUpPackOption>,
+}
+
+#[derive(Serialize)]
+pub struct PlanChangeResponse {
+    pub previous_tier: String,
+    pub pricing_tier: String,
+    // Positive for an upgrade's prorated grant, negative when a downgrade caps the balance
+    pub credits_adjusted: i64,
+    pub amount_charged_cents: i64,
+    pub payment_id: Option<i64>,
+    pub api_credits: i64,
+    pub topup_credits: i64,
+    pub credits_reset_date: String,
+}
+
+#[derive(Serialize)]
+pub struct TopUpResponse {
+    pub pack: String,
+    pub credits_added: i64,
+    pub amount_charged_cents: i64,
+    pub payment_id: i64,
+    pub api_credits: i64,
+    pub topup_credits: i64,
+}
+
+pub struct BillingController;
+
+impl BillingController {
+    pub async fn get_usage(
+        claims: web::ReqData<Claims>,
+        query: web::Query<UsageQuery>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.user_id)?;
+
+        // Defaults to the calendar month so far
+        let today = Utc::now().date_naive();
+        let end = query.end.unwrap_or(today);
+        let start = query
+            .start
+            .unwrap_or_else(|| end.with_day(1).unwrap_or(end));
+        if start > end {
+            return Err(AppError::Validation("start must not be after end".to_string()));
+        }
+        if (end - start).num_days() >= MAX_REPORT_DAYS {
+            return Err(AppError::Validation(format!(
+                "Usage reports cover at most {} days",
+                MAX_REPORT_DAYS
+            )));
+        }
+
+        let rows = user_repo.get_usage_daily(company_id, start, end)?;
+        let api_key_names: HashMap<i64, String> = user_repo
+            .get_api_keys_by_company(company_id)?
+            .into_iter()
+            .map(|api_key| (api_key.id, api_key.name))
+            .collect();
+        let smtp_servers: HashMap<i64, String> = user_repo
+            .get_smtp_profiles_by_company(company_id)?
+            .into_iter()
+            .map(|profile| (profile.id, profile.smtp_server))
+            .collect();
+
+        match query.format.as_deref() {
+            None | Some("json") => {}
+            Some("csv") => {
+                let csv = Self::usage_csv(&rows, &api_key_names, &smtp_servers);
+                return Ok(HttpResponse::Ok()
+                    .content_type("text/csv; charset=utf-8")
+                    .insert_header((
+                        "Content-Disposition",
+                        format!("attachment; filename=\"usage-{}-{}.csv\"", start, end),
+                    ))
+                    .body(csv));
+            }
+            Some(format) => {
+                return Err(AppError::Validation(format!(
+                    "Unsupported format '{}'",
+                    format
+                )))
+            }
+        }
+
+        let mut totals = UsageTotals::default();
+        let mut by_day: BTreeMap<NaiveDate, UsageTotals> = BTreeMap::new();
+        let mut by_api_key: BTreeMap<Option<i64>, UsageTotals> = BTreeMap::new();
+        let mut by_smtp_profile: BTreeMap<Option<i64>, UsageTotals> = BTreeMap::new();
+        for row in &rows {
+            totals.add(row);
+            by_day.entry(row.day).or_default().add(row);
+            by_api_key.entry(row.api_key_id).or_default().add(row);
+            by_smtp_profile.entry(row.smtp_profile_id).or_default().add(row);
+        }
+
+        let periods = user_repo
+            .get_usage_snapshots(company_id, REPORT_PERIODS)?
+            .into_iter()
+            .map(|snapshot| BillingPeriod {
+                period_start: snapshot.period_start.to_rfc3339(),
+                period_end: snapshot.period_end.to_rfc3339(),
+                pricing_tier: snapshot.pricing_tier,
+                credits_allocated: snapshot.credits_allocated,
+                credits_used: snapshot.credits_used,
+                credits_remaining: snapshot.credits_remaining,
+                emails_sent: snapshot.emails_sent,
+                emails_failed: snapshot.emails_failed,
+            })
+            .collect();
+
+        let report = UsageReport {
+            start: start.to_string(),
+            end: end.to_string(),
+            totals,
+            by_day: by_day
+                .into_iter()
+                .map(|(day, usage)| DailyUsage {
+                    day: day.to_string(),
+                    usage,
+                })
+                .collect(),
+            by_api_key: by_api_key
+                .into_iter()
+                .map(|(api_key_id, usage)| ApiKeyUsage {
+                    api_key_id,
+                    api_key_name: api_key_id.and_then(|id| api_key_names.get(&id).cloned()),
+                    usage,
+                })
+                .collect(),
+            by_smtp_profile: by_smtp_profile
+                .into_iter()
+                .map(|(smtp_profile_id, usage)| SmtpProfileUsage {
+                    smtp_profile_id,
+                    smtp_server: smtp_profile_id.and_then(|id| smtp_servers.get(&id).cloned()),
+                    usage,
+                })
+                .collect(),
+            periods,
+        };
+
+        Ok(service_response(
+            200,
+            "Usage retrieved successfully",
+            true,
+            Some(serde_json::to_value(report).unwrap()),
+        ))
+    }
+
+    pub async fn get_plan(
+        claims: web::ReqData<Claims>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let company = check_and_reset_credits(company_id, &repo_factory).await?;
+
+        let plan = PlanResponse {
+            plan: TierOption::from(&PricingTier::from_str(&company.pricing_tier)),
+            api_credits: company.api_credits,
+            topup_credits: company.topup_credits,
+            credits_reset_date: company.credits_reset_date.to_rfc3339(),
+            currency: CURRENCY.to_string(),
+            available_tiers: [PricingTier::Free, PricingTier::Developer, PricingTier::Enterprise]
+                .iter()
+                .map(TierOption::from)
+                .collect(),
+            top_up_packs: TopUpPack::ALL
+                .iter()
+                .map(|pack| TopUpPackOption {
+                    pack: pack.name().to_string(),
+                    credits: pack.credits(),
+                    price_cents: pack.price_cents(),
+                })
+                .collect(),
+        };
+
+        Ok(service_response(
+            200,
+            "Plan retrieved successfully",
+            true,
+            Some(serde_json::to_value(plan).unwrap()),
+        ))
+    }
+
+    // Plan changes take effect immediately; see `billing::change_plan` for how they are priced
+    pub async fn change_plan(
+        claims: web::ReqData<Claims>,
+        req: web::Json<ChangePlanRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+        payment_provider: web::Data<dyn PaymentProvider>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.user_id)?;
+        Self::require_billing_admin(&user_repo, claims.user_id, company_id, "change the plan")?;
+
+        let target = PricingTier::parse(&req.pricing_tier).ok_or_else(|| {
+            AppError::Validation(format!("Unknown pricing tier '{}'", req.pricing_tier))
+        })?;
+        // Price the change against the current cycle, so an overdue reset happens first
+        let company = check_and_reset_credits(company_id, &repo_factory).await?;
+        let change = billing::change_plan(&user_repo, payment_provider.as_ref(), &company, target).await?;
+
+        let response = PlanChangeResponse {
+            previous_tier: change.previous_tier,
+            pricing_tier: change.company.pricing_tier.clone(),
+            credits_adjusted: change.company.api_credits - company.api_credits,
+            amount_charged_cents: change.amount_cents,
+            payment_id: change.payment.map(|payment| payment.id),
+            api_credits: change.company.api_credits,
+            topup_credits: change.company.topup_credits,
+            credits_reset_date: change.company.credits_reset_date.to_rfc3339(),
+        };
+
+        Ok(service_response(
+            200,
+            "Plan changed successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    pub async fn purchase_top_up(
+        claims: web::ReqData<Claims>,
+        req: web::Json<TopUpRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+        payment_provider: web::Data<dyn PaymentProvider>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.user_id)?;
+        Self::require_billing_admin(&user_repo, claims.user_id, company_id, "buy credits")?;
+
+        let pack = TopUpPack::parse(&req.pack)
+            .ok_or_else(|| AppError::Validation(format!("Unknown top-up pack '{}'", req.pack)))?;
+        let company: Company = user_repo.get_company_by_id(company_id)?;
+        let (payment, updated) =
+            billing::purchase_top_up(&user_repo, payment_provider.as_ref(), &company, pack).await?;
+
+        let response = TopUpResponse {
+            pack: pack.name().to_string(),
+            credits_added: pack.credits(),
+            amount_charged_cents: payment.amount_cents,
+            payment_id: payment.id,
+            api_credits: updated.api_credits,
+            topup_credits: updated.topup_credits,
+        };
+
+        Ok(service_response(
+            201,
+            "Credits purchased successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    fn require_billing_admin(
+        user_repo: &UserRepositoryImpl,
+        user_id: i64,
+        company_id: i64,
+        action: &str,
+    ) -> Result<(), AppError> {
+        let user_role = user_repo
+            .get_user_role_in_company(user_id, company_id)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => {
+                    AppError::Validation("User not found in company".to_string())
+                }
+                _ => AppError::Database(e),
+            })?;
+
+        if user_role != "Owner" && user_role != "Admin" {
+            return Err(AppError::Forbidden(format!(
+                "Only owners and admins can {}",
+                action
+            )));
+        }
+        Ok(())
+    }
+
+    // One line per day, API key and SMTP profile, as stored
+    fn usage_csv(
+        rows: &[UsageDaily],
+        api_key_names: &HashMap<i64, String>,
+        smtp_servers: &HashMap<i64, String>,
+    ) -> String {
+        let mut csv = String::from(
+            "day,api_key_id,api_key_name,smtp_profile_id,smtp_server,emails_sent,recipients,attachment_bytes\n",
+        );
+        let id = |id: Option<i64>| id.map(|id| id.to_string()).unwrap_or_default();
+
+        for row in rows {
+            let api_key_name = row.api_key_id.and_then(|id| api_key_names.get(&id));
+            let smtp_server = row.smtp_profile_id.and_then(|id| smtp_servers.get(&id));
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                row.day,
+                id(row.api_key_id),
+                Self::csv_field(api_key_name.map_or("", String::as_str)),
+                id(row.smtp_profile_id),
+                Self::csv_field(smtp_server.map_or("", String::as_str)),
+                row.emails_sent,
+                row.recipients,
+                row.attachment_bytes
+            ));
+        }
+
+        csv
+    }
+
+    // Spreadsheets run a cell that starts with one of these as a formula, so such values (API key
+    // names are user-chosen) get a leading ' to keep them as text
+    fn csv_field(value: &str) -> String {
+        let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+            format!("'{}", value)
+        } else {
+            value.to_string()
+        };
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(BillingController::csv_field("plain"), "plain");
+        assert_eq!(BillingController::csv_field("a,b"), "\"a,b\"");
+        assert_eq!(BillingController::csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_fields_cannot_start_a_formula() {
+        assert_eq!(BillingController::csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(BillingController::csv_field("+1"), "'+1");
+        assert_eq!(BillingController::csv_field("-1"), "'-1");
+        assert_eq!(BillingController::csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(BillingController::csv_field("a=b"), "a=b");
+    }
+}
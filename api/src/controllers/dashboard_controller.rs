@@ -12,6 +12,7 @@ pub struct DashboardStats {
     pub api_calls: i64,
     pub active_users: i64,
     pub api_credits_remaining: i64,
+    pub topup_credits: i64,
     pub pricing_tier: String,
     pub credits_reset_date: String,
 }
@@ -62,7 +63,8 @@ impl DashboardController {
             delivery_rate,
             api_calls: total_emails, // Total API calls = total emails attempted
             active_users: 1, // Current user count (could be enhanced to count team members)
-            api_credits_remaining: company.api_credits,
+            api_credits_remaining: company.api_credits + company.topup_credits,
+            topup_credits: company.topup_credits,
             pricing_tier: company.pricing_tier,
             credits_reset_date: company.credits_reset_date.format("%Y-%m-%d").to_string(),
         };
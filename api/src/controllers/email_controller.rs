@@ -3,6 +3,11 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::utils::utils::service_response;
 use crate::errors::AppError;
+use crate::auth::jwt::Claims;
+use crate::controllers::company_id_for_user;
+use crate::controllers::public_email_controller::PublicEmailController;
+use crate::repositories::{users::UserRepository, RepositoryFactory};
+use crate::services::email_queue::public_status;
 
 #[derive(Deserialize)]
 pub struct SendEmailRequest {
@@ -62,30 +67,32 @@ impl EmailController {
         ))
     }
 
-    pub async fn get_recent_emails() -> Result<HttpResponse, AppError> {
-        let recent_emails = vec![
-            RecentEmail {
-                to: "user@example.com".to_string(),
-                subject: "Welcome to our platform".to_string(),
-                status: "delivered".to_string(),
-                time: "2 min ago".to_string(),
-                message_id: "msg_abc123".to_string(),
-            },
-            RecentEmail {
-                to: "customer@company.com".to_string(),
-                subject: "Password reset request".to_string(),
-                status: "opened".to_string(),
-                time: "5 min ago".to_string(),
-                message_id: "msg_def456".to_string(),
-            },
+    pub async fn get_recent_emails(
+        claims: web::ReqData<Claims>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.user_id)?;
+
+        let email_logs = user_repo.get_email_logs_by_company(company_id)?;
+        let recent_emails: Vec<RecentEmail> = email_logs.into_iter().take(10).map(|log| {
+            let time_diff = chrono::Utc::now().signed_duration_since(log.created_at);
+            let time_str = if time_diff.num_minutes() < 60 {
+                format!("{} min ago", time_diff.num_minutes().max(1))
+            } else if time_diff.num_hours() < 24 {
+                format!("{} hours ago", time_diff.num_hours())
+            } else {
+                format!("{} days ago", time_diff.num_days())
+            };
+
             RecentEmail {
-                to: "admin@startup.io".to_string(),
-                subject: "Monthly newsletter".to_string(),
-                status: "clicked".to_string(),
-                time: "1 hour ago".to_string(),
-                message_id: "msg_ghi789".to_string(),
-            },
-        ];
+                to: log.to_email,
+                subject: log.subject,
+                status: public_status(log.status.as_deref()),
+                time: time_str,
+                message_id: log.message_id.unwrap_or_default(),
+            }
+        }).collect();
 
         Ok(service_response(
             200,
@@ -95,22 +102,21 @@ impl EmailController {
         ))
     }
 
-    pub async fn get_email_status(path: web::Path<String>) -> Result<HttpResponse, AppError> {
-        let message_id = path.into_inner();
-        
-        let status = serde_json::json!({
-            "message_id": message_id,
-            "status": "delivered",
-            "delivered_at": "2024-01-20T14:32:15Z",
-            "opened_at": "2024-01-20T14:35:22Z",
-            "clicked_at": null
-        });
+    pub async fn get_email_status(
+        claims: web::ReqData<Claims>,
+        path: web::Path<String>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.user_id)?;
+
+        let status = PublicEmailController::message_status(&user_repo, &path.into_inner(), company_id)?;
 
         Ok(service_response(
             200,
             "Email status retrieved successfully",
             true,
-            Some(status),
+            Some(serde_json::to_value(status).unwrap()),
         ))
     }
 }
\ No newline at end of file
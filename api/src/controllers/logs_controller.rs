@@ -1,10 +1,17 @@
 use crate::auth::jwt::Claims;
 use crate::errors::AppError;
-use crate::repositories::{users::UserRepository, RepositoryFactory};
+use crate::repositories::users::{EmailLogFilter, UserRepository};
+use crate::repositories::RepositoryFactory;
 use crate::utils::utils::service_response;
 use actix_web::{web, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
 #[derive(Serialize)]
 pub struct EmailLogResponse {
     pub id: i64,
@@ -14,6 +21,13 @@ pub struct EmailLogResponse {
     pub subject: String,
     pub status: String,
     pub from_email: String,
+    pub message_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EmailLogPage {
+    pub logs: Vec<EmailLogResponse>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -37,6 +51,8 @@ pub struct LogFilters {
     pub event_type: Option<String>,
     pub status: Option<String>,
     pub search: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
 }
 
 pub struct LogsController;
@@ -44,7 +60,7 @@ pub struct LogsController;
 impl LogsController {
     pub async fn get_logs(
         claims: web::ReqData<Claims>,
-        _query: web::Query<LogFilters>,
+        query: web::Query<LogFilters>,
         repo_factory: web::Data<RepositoryFactory>,
     ) -> Result<HttpResponse, AppError> {
         let user_repo = repo_factory.create_user_repository();
@@ -59,17 +75,34 @@ impl LogsController {
             })?
             .company_id;
 
-        let email_logs = user_repo.get_email_logs_by_company(company_id)?;
+        let mut filter = Self::build_filter(&query)?;
+        let page_size = filter.limit;
+        // One extra row tells us whether another page follows
+        filter.limit = page_size + 1;
+
+        let mut email_logs = user_repo.search_email_logs(company_id, &filter)?;
+        let has_more = email_logs.len() as i64 > page_size;
+        email_logs.truncate(page_size as usize);
+
+        let next_cursor = match email_logs.last() {
+            Some(last) if has_more => Some(Self::encode_cursor(last.created_at, last.id)),
+            _ => None,
+        };
+
         let logs: Vec<EmailLogResponse> = email_logs
             .into_iter()
-            .map(|log| EmailLogResponse {
-                id: log.id,
-                timestamp: log.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-                event: "email.sent".to_string(),
-                recipient: log.to_email,
-                subject: log.subject,
-                status: log.status.unwrap_or("Unknown".to_string()).to_lowercase(),
-                from_email: log.from_email,
+            .map(|log| {
+                let status = log.status.unwrap_or("Unknown".to_string());
+                EmailLogResponse {
+                    id: log.id,
+                    timestamp: log.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    event: Self::event_for_status(&status).to_string(),
+                    recipient: log.to_email,
+                    subject: log.subject,
+                    status: status.to_lowercase(),
+                    from_email: log.from_email,
+                    message_id: log.message_id,
+                }
             })
             .collect();
 
@@ -77,10 +110,120 @@ impl LogsController {
             200,
             "Email logs retrieved successfully",
             true,
-            Some(serde_json::to_value(logs).unwrap()),
+            Some(serde_json::to_value(EmailLogPage { logs, next_cursor }).unwrap()),
         ))
     }
 
+    fn build_filter(query: &LogFilters) -> Result<EmailLogFilter, AppError> {
+        let since = match query.time_range.as_deref().map(str::trim) {
+            None | Some("") | Some("all") => None,
+            Some(range) => Some(
+                Utc::now()
+                    .checked_sub_signed(Self::parse_time_range(range)?)
+                    .ok_or_else(|| AppError::Validation(format!("Time range '{}' is too long", range)))?,
+            ),
+        };
+
+        // event_type and status both narrow by delivery state; when both are given they must agree
+        let event_status = Self::non_empty(&query.event_type)
+            .map(|event| {
+                Self::status_for_event(event)
+                    .ok_or_else(|| AppError::Validation(format!("Unknown event type '{}'", event)))
+            })
+            .transpose()?;
+        let status = Self::non_empty(&query.status)
+            .map(|status| {
+                Self::canonical_status(status)
+                    .ok_or_else(|| AppError::Validation(format!("Unknown status '{}'", status)))
+            })
+            .transpose()?;
+        let status = match (event_status, status) {
+            (Some(event_status), Some(status)) if event_status != status => {
+                return Err(AppError::Validation(
+                    "event_type and status filters conflict".to_string(),
+                ))
+            }
+            (event_status, status) => status.or(event_status),
+        };
+
+        let before = Self::non_empty(&query.cursor)
+            .map(|cursor| {
+                Self::decode_cursor(cursor)
+                    .ok_or_else(|| AppError::Validation("Invalid cursor".to_string()))
+            })
+            .transpose()?;
+
+        Ok(EmailLogFilter {
+            since,
+            status: status.map(str::to_string),
+            search: Self::non_empty(&query.search).map(str::to_string),
+            before,
+            limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+        })
+    }
+
+    fn non_empty(value: &Option<String>) -> Option<&str> {
+        value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+    }
+
+    // "15m", "24h", "7d", ...
+    fn parse_time_range(range: &str) -> Result<TimeDelta, AppError> {
+        let invalid = || AppError::Validation(format!("Invalid time range '{}'", range));
+        let split = range.len() - range.chars().last().map_or(0, char::len_utf8);
+        let amount: i64 = range[..split].parse().map_err(|_| invalid())?;
+        if amount <= 0 {
+            return Err(invalid());
+        }
+
+        let delta = match &range[split..] {
+            "m" => TimeDelta::try_minutes(amount),
+            "h" => TimeDelta::try_hours(amount),
+            "d" => TimeDelta::try_days(amount),
+            _ => return Err(invalid()),
+        };
+        delta.ok_or_else(|| AppError::Validation(format!("Time range '{}' is too long", range)))
+    }
+
+    fn canonical_status(status: &str) -> Option<&'static str> {
+        match status.to_lowercase().as_str() {
+            "success" | "sent" => Some("Success"),
+            "failed" => Some("Failed"),
+            "queued" => Some("Queued"),
+            "deferred" => Some("Deferred"),
+            "pending" => Some("Pending"),
+            "scheduled" => Some("Scheduled"),
+            "cancelled" => Some("Cancelled"),
+            _ => None,
+        }
+    }
+
+    fn status_for_event(event: &str) -> Option<&'static str> {
+        Self::canonical_status(event.strip_prefix("email.").unwrap_or(event))
+    }
+
+    fn event_for_status(status: &str) -> &'static str {
+        match status {
+            "Success" => "email.sent",
+            "Failed" => "email.failed",
+            "Deferred" => "email.deferred",
+            "Pending" => "email.pending",
+            "Scheduled" => "email.scheduled",
+            "Cancelled" => "email.cancelled",
+            _ => "email.queued",
+        }
+    }
+
+    fn encode_cursor(created_at: DateTime<Utc>, id: i64) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", created_at.timestamp_micros(), id))
+    }
+
+    fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, i64)> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (micros, id) = decoded.split_once(':')?;
+        let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+        Some((created_at, id.parse().ok()?))
+    }
+
     pub async fn get_log_stats(
         claims: web::ReqData<Claims>,
         repo_factory: web::Data<RepositoryFactory>,
@@ -170,3 +313,41 @@ impl LogsController {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(time_range: &str) -> LogFilters {
+        LogFilters {
+            time_range: Some(time_range.to_string()),
+            event_type: None,
+            status: None,
+            search: None,
+            cursor: None,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn parses_time_ranges() {
+        assert_eq!(LogsController::parse_time_range("15m").unwrap(), TimeDelta::minutes(15));
+        assert_eq!(LogsController::parse_time_range("24h").unwrap(), TimeDelta::hours(24));
+        assert_eq!(LogsController::parse_time_range("7d").unwrap(), TimeDelta::days(7));
+        assert!(LogsController::parse_time_range("0d").is_err());
+        assert!(LogsController::parse_time_range("7w").is_err());
+    }
+
+    #[test]
+    fn rejects_time_ranges_that_overflow() {
+        assert!(LogsController::parse_time_range("100000000000000d").is_err());
+        assert!(LogsController::build_filter(&filters("100000000d")).is_err());
+        assert!(LogsController::build_filter(&filters("9223372036854775807m")).is_err());
+    }
+
+    #[test]
+    fn all_time_has_no_lower_bound() {
+        assert!(LogsController::build_filter(&filters("all")).unwrap().since.is_none());
+        assert!(LogsController::build_filter(&filters("7d")).unwrap().since.is_some());
+    }
+}
@@ -9,4 +9,19 @@ pub mod team_controller;
 pub mod user_controller;
 pub mod smtp_controller;
 pub mod public_email_controller;
-pub mod settings_controller;
\ No newline at end of file
+pub mod settings_controller;
+pub mod webhooks_controller;
+pub mod billing_controller;
+pub mod routing_rules_controller;
+
+use crate::errors::AppError;
+use crate::repositories::users::UserRepository;
+
+// Dashboard users act for the company they are a team member of
+pub fn company_id_for_user(user_repo: &impl UserRepository, user_id: i64) -> Result<i64, AppError> {
+    let team_members = user_repo.get_team_members_by_user(user_id)?;
+    Ok(team_members
+        .first()
+        .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
+        .company_id)
+}
\ No newline at end of file
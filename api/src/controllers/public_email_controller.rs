@@ -1,26 +1,151 @@
 use crate::errors::AppError;
-use crate::models::users::NewEmailLog;
+use crate::middleware::credits::CreditAllowance;
+use crate::models::users::{ApiKey, Company, NewEmailJob, NewEmailLog, NewIdempotencyKey};
 use crate::repositories::{users::UserRepository, RepositoryFactory};
-use crate::services::email_service::EmailService;
-use crate::utils::utils::service_response;
-use actix_web::{web, HttpRequest, HttpResponse};
+use crate::services::email_queue::{
+    idempotency_key_lease, idempotency_key_ttl, new_email_job, public_status, record_email_event,
+    EVENT_CANCELLED, EVENT_SCHEDULED,
+};
+use crate::services::email_service::{EmailAttachment, OutboundEmail};
+use crate::services::smtp_routing::{RoutedMessage, SmtpRouter};
+use crate::services::webhooks::{self, email_event_data};
+use crate::utils::template::render_template;
+use crate::utils::utils::{get_env, one_or_many, service_response};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use lettre::message::Mailbox;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct SendEmailRequest {
-    pub from: String,
-    pub to: String,
+    pub from: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub reply_to: Option<String>,
     pub subject: String,
     pub html: Option<String>,
     pub text: Option<String>,
     pub template_id: Option<i64>,
+    // Values bound into the template's subject and body
+    #[serde(default)]
+    pub variables: Option<serde_json::Value>,
+    #[serde(default)]
+    pub attachments: Vec<EmailAttachment>,
+    // Held as "Scheduled" until this time instead of going out immediately
+    #[serde(default)]
+    pub send_at: Option<DateTime<Utc>>,
+    // Free-form labels that SMTP routing rules can match on, e.g. "transactional" or "bulk"
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RescheduleEmailRequest {
+    pub send_at: DateTime<Utc>,
 }
 
 #[derive(Serialize)]
 pub struct SendEmailResponse {
     pub message_id: String,
     pub status: String,
+    pub recipients: usize,
+    pub scheduled_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CancelEmailResponse {
+    pub message_id: String,
+    pub status: String,
+    pub credits_refunded: i64,
+}
+
+#[derive(Serialize)]
+pub struct RescheduleEmailResponse {
+    pub message_id: String,
+    pub status: String,
+    pub scheduled_at: String,
+}
+
+#[derive(Serialize)]
+pub struct EmailStatusResponse {
+    pub message_id: String,
+    pub status: String,
+    pub from: String,
+    pub subject: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub scheduled_at: Option<String>,
+    pub recipients: Vec<RecipientStatus>,
+    pub events: Vec<EmailEventResponse>,
+}
+
+#[derive(Serialize)]
+pub struct RecipientStatus {
+    pub email: String,
+    pub recipient_type: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_smtp_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_retry_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EmailEventResponse {
+    pub event: String,
+    pub smtp_code: Option<i32>,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+// Up to MAX_BATCH_SIZE messages; each is parsed on its own so one malformed item doesn't sink the rest
+#[derive(Deserialize)]
+pub struct SendBatchRequest {
+    pub messages: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct SendBatchResponse {
+    pub queued: usize,
+    pub rejected: usize,
+    pub credits_charged: i64,
+    pub results: Vec<BatchItemResult>,
+}
+
+#[derive(Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub message_id: Option<String>,
+    pub status: String,
+    pub recipients: usize,
+    pub error: Option<String>,
+}
+
+// A validated recipient: the address as given for the message, the bare email for the log
+struct Recipient {
+    address: String,
+    email: String,
+    recipient_type: &'static str,
+}
+
+// A send request that passed validation, ready to be charged for and queued
+struct PreparedEmail {
+    message_id: String,
+    logs: Vec<NewEmailLog>,
+    job: NewEmailJob,
+    outbound: OutboundEmail,
+    recipients: usize,
+    send_at: Option<DateTime<Utc>>,
 }
 
 pub struct PublicEmailController;
@@ -28,19 +153,415 @@ pub struct PublicEmailController;
 impl PublicEmailController {
     pub async fn send_email(
         req: HttpRequest,
-        email_req: web::Json<SendEmailRequest>,
+        body: web::Json<serde_json::Value>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let api_key_data = Self::authenticate(&req, &user_repo)?;
+        let body = body.into_inner();
+
+        let Some(key) = Self::idempotency_key(&req)? else {
+            let allowance = Self::credit_allowance(&req, api_key_data.company_id);
+            let response =
+                Self::queue_email(&repo_factory, &user_repo, &allowance, api_key_data.id, body)?;
+            return Ok(Self::send_response(serde_json::to_value(response).unwrap()));
+        };
+
+        // serde_json keeps object keys sorted, so the same body with its fields reordered hashes the same
+        let request_hash = hex::encode(Sha256::digest(body.to_string().as_bytes()));
+        let new_key = NewIdempotencyKey {
+            company_id: api_key_data.company_id,
+            key,
+            request_hash: request_hash.clone(),
+            created_at: Utc::now(),
+        };
+        let (idempotency_key, reserved) = user_repo.reserve_idempotency_key(
+            new_key,
+            Utc::now() - idempotency_key_ttl(),
+            Utc::now() - idempotency_key_lease(),
+        )?;
+
+        if !reserved {
+            if idempotency_key.request_hash != request_hash {
+                return Err(AppError::Conflict(
+                    "Idempotency-Key was already used with a different request body".to_string(),
+                ));
+            }
+            let Some(response) = idempotency_key.response else {
+                return Err(AppError::Conflict(
+                    "A request with this Idempotency-Key is still being processed".to_string(),
+                ));
+            };
+
+            log::info!(
+                "Replaying response for idempotency key {} (message {:?})",
+                idempotency_key.key,
+                idempotency_key.message_id
+            );
+            let mut replay = Self::send_response(response);
+            replay.headers_mut().insert(
+                HeaderName::from_static("idempotent-replayed"),
+                HeaderValue::from_static("true"),
+            );
+            return Ok(replay);
+        }
+
+        let allowance = Self::credit_allowance(&req, api_key_data.company_id);
+        match Self::queue_email(&repo_factory, &user_repo, &allowance, api_key_data.id, body) {
+            Ok(response) => {
+                let response = serde_json::to_value(response).unwrap();
+                let message_id = response["message_id"].as_str().unwrap_or_default();
+                if let Err(e) =
+                    user_repo.complete_idempotency_key(idempotency_key.id, message_id, response.clone())
+                {
+                    log::error!(
+                        "Failed to store response for idempotency key {}: {:?}",
+                        idempotency_key.key,
+                        e
+                    );
+                }
+                Ok(Self::send_response(response))
+            }
+            Err(e) => {
+                // Nothing went out, so the client may retry with the same key
+                if let Err(release_error) = user_repo.release_idempotency_key(idempotency_key.id) {
+                    log::error!(
+                        "Failed to release idempotency key {}: {:?}",
+                        idempotency_key.key,
+                        release_error
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn queue_email(
+        repo_factory: &RepositoryFactory,
+        user_repo: &impl UserRepository,
+        allowance: &CreditAllowance,
+        api_key_id: i64,
+        body: serde_json::Value,
+    ) -> Result<SendEmailResponse, AppError> {
+        let email_req = serde_json::from_value::<SendEmailRequest>(body)
+            .map_err(|e| AppError::Validation(format!("Invalid request body: {}", e)))?;
+
+        let company = user_repo.get_company_by_id(allowance.company_id)?;
+
+        // Routing rules pick the profile, falling back to the first healthy one in failover order;
+        // the worker moves on to the next profile if it fails
+        let router = SmtpRouter::for_company(user_repo, company.id)?
+            .ok_or_else(|| AppError::Validation("No SMTP profile configured".to_string()))?;
+
+        let prepared = Self::prepare_email(user_repo, &company, &router, api_key_id, &email_req)?;
+
+        // Every recipient costs one credit (nothing on unlimited tiers), taken in the same
+        // transaction that queues the message. Workers pick the job up from the database
+        let credits_required = allowance.charge(prepared.recipients as i64);
+        user_repo
+            .enqueue_email(prepared.logs, prepared.job, credits_required)
+            .map_err(|e| Self::enqueue_error(e, credits_required))?;
+        Self::record_queued(
+            repo_factory,
+            user_repo,
+            company.id,
+            &prepared.message_id,
+            &prepared.outbound,
+            prepared.send_at.is_some(),
+        );
+        log::info!(
+            "Email {} {} for {} recipients",
+            prepared.message_id,
+            Self::queued_status(prepared.send_at),
+            prepared.recipients
+        );
+
+        Ok(SendEmailResponse {
+            message_id: prepared.message_id,
+            status: Self::queued_status(prepared.send_at).to_string(),
+            recipients: prepared.recipients,
+            scheduled_at: prepared.send_at.map(|send_at| send_at.to_rfc3339()),
+        })
+    }
+
+    fn send_response(response: serde_json::Value) -> HttpResponse {
+        let message = if response["status"] == "scheduled" {
+            "Email scheduled successfully"
+        } else {
+            "Email queued successfully"
+        };
+
+        service_response(200, message, true, Some(response))
+    }
+
+    pub async fn send_batch(
+        req: HttpRequest,
+        batch_req: web::Json<SendBatchRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let api_key_data = Self::authenticate(&req, &user_repo)?;
+
+        let batch_req = batch_req.into_inner();
+        if batch_req.messages.is_empty() {
+            return Err(AppError::Validation(
+                "At least one message is required".to_string(),
+            ));
+        }
+        let max_batch_size = Self::max_batch_size();
+        if batch_req.messages.len() > max_batch_size {
+            return Err(AppError::Validation(format!(
+                "Too many messages: {} (maximum {})",
+                batch_req.messages.len(),
+                max_batch_size
+            )));
+        }
+
+        let company = user_repo.get_company_by_id(api_key_data.company_id)?;
+        let router = SmtpRouter::for_company(&user_repo, company.id)?
+            .ok_or_else(|| AppError::Validation("No SMTP profile configured".to_string()))?;
+
+        // Every item is validated before anything is charged or queued; invalid items are
+        // reported back and the rest of the batch still goes out
+        let mut results = Vec::with_capacity(batch_req.messages.len());
+        let mut prepared = Vec::new();
+        for (index, message) in batch_req.messages.into_iter().enumerate() {
+            let outcome = serde_json::from_value::<SendEmailRequest>(message)
+                .map_err(|e| AppError::Validation(format!("Invalid message: {}", e)))
+                .and_then(|email_req| {
+                    Self::prepare_email(
+                        &user_repo,
+                        &company,
+                        &router,
+                        api_key_data.id,
+                        &email_req,
+                    )
+                });
+
+            match outcome {
+                Ok(email) => {
+                    results.push(BatchItemResult {
+                        index,
+                        message_id: Some(email.message_id.clone()),
+                        status: Self::queued_status(email.send_at).to_string(),
+                        recipients: email.recipients,
+                        error: None,
+                    });
+                    prepared.push(email);
+                }
+                Err(AppError::Validation(error)) => results.push(BatchItemResult {
+                    index,
+                    message_id: None,
+                    status: "rejected".to_string(),
+                    recipients: 0,
+                    error: Some(error),
+                }),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let queued = prepared.len();
+        let rejected = results.len() - queued;
+        let allowance = Self::credit_allowance(&req, company.id);
+        let credits_required: i64 = prepared
+            .iter()
+            .map(|email| allowance.charge(email.recipients as i64))
+            .sum();
+
+        if prepared.is_empty() {
+            let response = SendBatchResponse {
+                queued,
+                rejected,
+                credits_charged: 0,
+                results,
+            };
+            return Ok(service_response(
+                400,
+                "No valid messages in batch",
+                false,
+                Some(serde_json::to_value(response).unwrap()),
+            ));
+        }
+
+        // Credits for the whole batch are taken in the same transaction that queues it
+        let mut jobs = Vec::with_capacity(queued);
+        let mut queued_emails = Vec::with_capacity(queued);
+        for email in prepared {
+            let credits = allowance.charge(email.recipients as i64);
+            jobs.push((email.logs, email.job, credits));
+            queued_emails.push((email.message_id, email.outbound, email.send_at.is_some()));
+        }
+        user_repo
+            .enqueue_email_batch(company.id, jobs)
+            .map_err(|e| Self::enqueue_error(e, credits_required))?;
+
+        for (message_id, outbound, scheduled) in &queued_emails {
+            Self::record_queued(
+                &repo_factory,
+                &user_repo,
+                company.id,
+                message_id,
+                outbound,
+                *scheduled,
+            );
+        }
+        log::info!(
+            "Batch of {} emails queued for company {} ({} rejected)",
+            queued,
+            company.id,
+            rejected
+        );
+
+        let response = SendBatchResponse {
+            queued,
+            rejected,
+            credits_charged: credits_required,
+            results,
+        };
+
+        Ok(service_response(
+            200,
+            "Batch queued successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    pub async fn get_email_status(
+        req: HttpRequest,
+        path: web::Path<String>,
         repo_factory: web::Data<RepositoryFactory>,
     ) -> Result<HttpResponse, AppError> {
-        // Extract API key from header
+        let user_repo = repo_factory.create_user_repository();
+        let api_key_data = Self::authenticate(&req, &user_repo)?;
+
+        let status = Self::message_status(&user_repo, &path.into_inner(), api_key_data.company_id)?;
+
+        Ok(service_response(
+            200,
+            "Email status retrieved successfully",
+            true,
+            Some(serde_json::to_value(status).unwrap()),
+        ))
+    }
+
+    pub async fn cancel_email(
+        req: HttpRequest,
+        path: web::Path<String>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let api_key_data = Self::authenticate(&req, &user_repo)?;
+        let message_id = path.into_inner();
+
+        let company_id = api_key_data.company_id;
+        let credits_refunded = match user_repo.cancel_scheduled_email(&message_id, company_id) {
+            Ok(credits) => credits,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Self::not_scheduled(&user_repo, &message_id, company_id, "cancelled"))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        record_email_event(&user_repo, company_id, &message_id, EVENT_CANCELLED, None);
+        log::info!(
+            "Scheduled email {} cancelled, {} credits refunded",
+            message_id,
+            credits_refunded
+        );
+
+        let response = CancelEmailResponse {
+            message_id,
+            status: "cancelled".to_string(),
+            credits_refunded,
+        };
+
+        Ok(service_response(
+            200,
+            "Email cancelled successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    pub async fn reschedule_email(
+        req: HttpRequest,
+        path: web::Path<String>,
+        reschedule_req: web::Json<RescheduleEmailRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let api_key_data = Self::authenticate(&req, &user_repo)?;
+        let message_id = path.into_inner();
+
+        Self::validate_send_at(reschedule_req.send_at)?;
+
+        let company_id = api_key_data.company_id;
+        let job = match user_repo.reschedule_email(&message_id, company_id, reschedule_req.send_at) {
+            Ok(job) => job,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Self::not_scheduled(&user_repo, &message_id, company_id, "rescheduled"))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        record_email_event(&user_repo, company_id, &message_id, EVENT_SCHEDULED, None);
+
+        let response = RescheduleEmailResponse {
+            message_id,
+            status: "scheduled".to_string(),
+            scheduled_at: job.available_at.to_rfc3339(),
+        };
+
+        Ok(service_response(
+            200,
+            "Email rescheduled successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    // Tells a missing message apart from one that has already been released or cancelled
+    fn not_scheduled(
+        user_repo: &impl UserRepository,
+        message_id: &str,
+        company_id: i64,
+        action: &str,
+    ) -> AppError {
+        match user_repo.get_email_logs_by_message_id(message_id, company_id) {
+            Ok(logs) if logs.is_empty() => AppError::Validation("Message not found".to_string()),
+            Ok(_) => AppError::Validation(format!(
+                "Only scheduled messages can be {}",
+                action
+            )),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, AppError> {
+        let Some(value) = req.headers().get("Idempotency-Key") else {
+            return Ok(None);
+        };
+
+        let key = value
+            .to_str()
+            .map(str::trim)
+            .map_err(|_| AppError::Validation("Invalid Idempotency-Key header".to_string()))?;
+        if key.is_empty() || key.len() > 255 {
+            return Err(AppError::Validation(
+                "Idempotency-Key must be between 1 and 255 characters".to_string(),
+            ));
+        }
+
+        Ok(Some(key.to_string()))
+    }
+
+    // Resolves the X-API-Key header to an active key
+    fn authenticate(req: &HttpRequest, user_repo: &impl UserRepository) -> Result<ApiKey, AppError> {
         let api_key = req
             .headers()
             .get("X-API-Key")
             .and_then(|h| h.to_str().ok())
             .ok_or_else(|| AppError::Forbidden("Missing X-API-Key header".to_string()))?;
 
-        let user_repo = repo_factory.create_user_repository();
-
-        // Validate API key and get company info
         let api_key_data = user_repo
             .get_api_key_by_key(api_key)
             .map_err(|_| AppError::Validation("Invalid API key".to_string()))?;
@@ -49,119 +570,467 @@ impl PublicEmailController {
             return Err(AppError::Validation("API key is inactive".to_string()));
         }
 
-        // Get company and check credits
-        let company = user_repo.get_company_by_id(api_key_data.company_id)?;
-        if company.api_credits <= 0 {
-            return Err(AppError::Validation("Insufficient API credits".to_string()));
-        }
+        Ok(api_key_data)
+    }
 
-        // Get default SMTP profile for the company
-        let smtp_profile = user_repo
-            .get_default_smtp_profile(company.id)
-            .map_err(|_| AppError::Validation("No default SMTP profile configured".to_string()))?;
+    pub fn message_status(
+        user_repo: &impl UserRepository,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<EmailStatusResponse, AppError> {
+        let logs = user_repo.get_email_logs_by_message_id(message_id, company_id)?;
+        let first = logs
+            .first()
+            .ok_or_else(|| AppError::Validation("Message not found".to_string()))?;
+        let events = user_repo.get_email_events_by_message_id(message_id, company_id)?;
 
-        // Deduct API credit
-        user_repo.deduct_api_credit(company.id)?;
+        let updated_at = events
+            .last()
+            .map(|event| event.created_at)
+            .unwrap_or(first.created_at);
 
-        // Prepare email content (check for template first)
-        let (content, subject, is_html) = if let Some(template_id) = email_req.template_id {
+        Ok(EmailStatusResponse {
+            message_id: message_id.to_string(),
+            // Every recipient of a message shares one queue job, so they move together
+            status: public_status(first.status.as_deref()),
+            from: first.from_email.clone(),
+            subject: first.subject.clone(),
+            created_at: first.created_at.to_rfc3339(),
+            updated_at: updated_at.to_rfc3339(),
+            scheduled_at: first.scheduled_at.map(|scheduled_at| scheduled_at.to_rfc3339()),
+            recipients: logs
+                .iter()
+                .map(|log| RecipientStatus {
+                    email: log.to_email.clone(),
+                    recipient_type: log.recipient_type.clone(),
+                    status: public_status(log.status.as_deref()),
+                    attempts: log.attempts,
+                    last_smtp_code: log.last_smtp_code,
+                    last_error: log.last_error.clone(),
+                    next_retry_at: log.next_retry_at.map(|retry_at| retry_at.to_rfc3339()),
+                })
+                .collect(),
+            events: events
+                .into_iter()
+                .map(|event| EmailEventResponse {
+                    event: event.event,
+                    smtp_code: event.smtp_code,
+                    detail: event.detail,
+                    created_at: event.created_at.to_rfc3339(),
+                })
+                .collect(),
+        })
+    }
+
+    // Validates a send request and builds its log rows and job without charging or queueing anything
+    fn prepare_email(
+        user_repo: &impl UserRepository,
+        company: &Company,
+        router: &SmtpRouter,
+        api_key_id: i64,
+        email_req: &SendEmailRequest,
+    ) -> Result<PreparedEmail, AppError> {
+        let recipients = Self::validate_recipients(email_req)?;
+
+        let from = Self::resolve_from(company, email_req.from.as_deref())?;
+
+        if let Some(send_at) = email_req.send_at {
+            Self::validate_send_at(send_at)?;
+        }
+        let status = if email_req.send_at.is_some() { "Scheduled" } else { "Queued" };
+
+        // Prepare email content (check for template first). HTML is the primary body when given;
+        // the text part becomes its alternative, or is derived from the HTML at send time
+        let (content, text, subject, is_html) = if let Some(template_id) = email_req.template_id {
             let template = user_repo
                 .get_template_by_id(template_id, company.id)
                 .map_err(|_| AppError::Validation("Template not found".to_string()))?;
 
-            (template.content, template.subject, true)
+            let variables = match email_req.variables.clone() {
+                Some(variables @ serde_json::Value::Object(_)) => variables,
+                None | Some(serde_json::Value::Null) => serde_json::json!({}),
+                Some(_) => {
+                    return Err(AppError::Validation(
+                        "variables must be a JSON object".to_string(),
+                    ))
+                }
+            };
+            let subject = render_template(&template.subject, &variables, false)
+                .map_err(|e| AppError::Validation(format!("Template subject: {}", e)))?;
+            let content = render_template(&template.content, &variables, true)
+                .map_err(|e| AppError::Validation(format!("Template body: {}", e)))?;
+
+            (content, None, subject, true)
         } else {
-            let empty_string = String::new();
-            let content = email_req
-                .html
-                .as_ref()
-                .unwrap_or(email_req.text.as_ref().unwrap_or(&empty_string));
-            (
-                content.clone(),
-                email_req.subject.clone(),
-                email_req.html.is_some(),
-            )
+            match (&email_req.html, &email_req.text) {
+                (Some(html), text) => (html.clone(), text.clone(), email_req.subject.clone(), true),
+                (None, Some(text)) => (text.clone(), None, email_req.subject.clone(), false),
+                (None, None) => (String::new(), None, email_req.subject.clone(), false),
+            }
         };
 
+        // Per-company limit overrides the platform default, up to the ceiling the body limit allows
+        let max_message_bytes = company
+            .max_message_bytes
+            .unwrap_or_else(Self::default_max_message_bytes)
+            .min(Self::max_message_bytes_ceiling());
+        Self::validate_attachments(&email_req.attachments, content.len(), max_message_bytes)?;
+
         // Generate message ID
         let message_id = format!("msg_{}", Uuid::new_v4().simple());
 
-        // Create email log entry
-        let new_log = NewEmailLog {
-            from_email: email_req.from.clone(),
-            to_email: email_req.to.clone(),
-            subject: subject.clone(),
-            body: content.clone(),
-            status: Some("Queued".to_string()),
-            created_at: chrono::Utc::now(),
-            company_id: company.id,
+        // One log row per recipient, all sharing the message ID
+        let logs: Vec<NewEmailLog> = recipients
+            .iter()
+            .map(|recipient| NewEmailLog {
+                from_email: from.email.to_string(),
+                to_email: recipient.email.clone(),
+                subject: subject.clone(),
+                body: content.clone(),
+                status: Some(status.to_string()),
+                created_at: chrono::Utc::now(),
+                company_id: company.id,
+                message_id: Some(message_id.clone()),
+                recipient_type: recipient.recipient_type.to_string(),
+                scheduled_at: email_req.send_at,
+            })
+            .collect();
+
+        let addresses_of = |recipient_type: &str| -> Vec<String> {
+            recipients
+                .iter()
+                .filter(|r| r.recipient_type == recipient_type)
+                .map(|r| r.address.clone())
+                .collect()
+        };
+
+        let outbound = OutboundEmail {
+            from: from.to_string(),
+            to: addresses_of("to"),
+            cc: addresses_of("cc"),
+            bcc: addresses_of("bcc"),
+            reply_to: email_req.reply_to.clone(),
+            subject,
+            content,
+            is_html,
+            text,
+            attachments: email_req.attachments.clone(),
         };
+        let from_address = from.email.to_string();
+        let smtp_profile_id = router.route(&RoutedMessage {
+            recipients: recipients.iter().map(|r| r.email.as_str()).collect(),
+            from: &from_address,
+            template_id: email_req.template_id,
+            tag: email_req.tag.as_deref(),
+            category: email_req.category.as_deref(),
+            api_key_id,
+        });
+        let job = new_email_job(
+            &message_id,
+            company.id,
+            smtp_profile_id,
+            &outbound,
+            email_req.send_at,
+            Some(api_key_id),
+        );
+
+        Ok(PreparedEmail {
+            message_id,
+            logs,
+            job,
+            outbound,
+            recipients: recipients.len(),
+            send_at: email_req.send_at,
+        })
+    }
+
+    fn record_queued(
+        repo_factory: &RepositoryFactory,
+        user_repo: &impl UserRepository,
+        company_id: i64,
+        message_id: &str,
+        outbound: &OutboundEmail,
+        scheduled: bool,
+    ) {
+        // Scheduled messages report "queued" when the scheduler releases them
+        if scheduled {
+            record_email_event(user_repo, company_id, message_id, EVENT_SCHEDULED, None);
+            return;
+        }
+
+        record_email_event(user_repo, company_id, message_id, webhooks::EVENT_QUEUED, None);
+        webhooks::dispatch_event(
+            repo_factory,
+            company_id,
+            webhooks::EVENT_QUEUED,
+            email_event_data(message_id, outbound),
+        );
+    }
+
+    fn validate_recipients(email_req: &SendEmailRequest) -> Result<Vec<Recipient>, AppError> {
+        if email_req.to.is_empty() {
+            return Err(AppError::Validation(
+                "At least one recipient is required".to_string(),
+            ));
+        }
+
+        let mut recipients: Vec<Recipient> = Vec::new();
+        let mut invalid = Vec::new();
+        let fields = [
+            ("to", &email_req.to),
+            ("cc", &email_req.cc),
+            ("bcc", &email_req.bcc),
+        ];
 
-        let email_log = user_repo.create_email_log(new_log)?;
-
-        // Clone data for background task
-        let smtp_server = smtp_profile.smtp_server.clone();
-        let smtp_username = smtp_profile.smtp_username.clone();
-        let smtp_password = smtp_profile.smtp_password.clone();
-        let smtp_port = smtp_profile.smtp_port;
-        let from_email = email_req.from.clone();
-        let to_email = email_req.to.clone();
-        let email_subject = subject.clone();
-        let email_content = content.clone();
-        let log_id = email_log.id;
-        let repo_factory_clone = repo_factory.clone();
-
-        // Send email in background
-        tokio::spawn(async move {
-            let email_service = EmailService::new();
-
-            let result = email_service
-                .send_email(
-                    &smtp_server,
-                    &smtp_username,
-                    &smtp_password,
-                    &from_email,
-                    Some(smtp_port as u16),
-                    &to_email,
-                    &email_subject,
-                    &email_content,
-                    is_html,
-                )
-                .await;
-
-            // Update email log status
-            let user_repo = repo_factory_clone.create_user_repository();
-            let status = if result.is_ok() { "Success" } else { "Failed" };
-
-            if let Err(e) = user_repo.update_email_log_status(log_id, status) {
-                log::error!(
-                    "Failed to update email log status for ID {}: {:?}",
-                    log_id,
-                    e
-                );
+        // An address listed more than once, in any case or field, is sent and charged once; the first
+        // field it appears in wins
+        let mut seen = HashSet::new();
+        for (recipient_type, addresses) in fields {
+            for (index, address) in addresses.iter().enumerate() {
+                match address.parse::<Mailbox>() {
+                    Ok(mailbox) => {
+                        let email = mailbox.email.to_string();
+                        if seen.insert(email.to_lowercase()) {
+                            recipients.push(Recipient {
+                                address: address.clone(),
+                                email,
+                                recipient_type,
+                            });
+                        }
+                    }
+                    Err(_) => invalid.push(format!("{}[{}] '{}'", recipient_type, index, address)),
+                }
             }
+        }
 
-            if result.is_ok() {
-                log::info!("Email sent successfully for log ID: {}", log_id);
-            } else {
-                log::error!(
-                    "Failed to send email for log ID: {}: {:?}",
-                    log_id,
-                    result.err()
-                );
+        if let Some(reply_to) = &email_req.reply_to {
+            if reply_to.parse::<Mailbox>().is_err() {
+                invalid.push(format!("reply_to '{}'", reply_to));
             }
-        });
+        }
 
-        let response = SendEmailResponse {
-            message_id,
-            status: "queued".to_string(),
+        if !invalid.is_empty() {
+            return Err(AppError::Validation(format!(
+                "Invalid email addresses: {}",
+                invalid.join(", ")
+            )));
+        }
+
+        let max_recipients: usize = get_env("MAX_RECIPIENTS_PER_MESSAGE", "50")
+            .parse()
+            .unwrap_or(50);
+        if recipients.len() > max_recipients {
+            return Err(AppError::Validation(format!(
+                "Too many recipients: {} (maximum {})",
+                recipients.len(),
+                max_recipients
+            )));
+        }
+
+        Ok(recipients)
+    }
+
+    // The requested From must be on the company's sending domain; without one, the company defaults apply
+    fn resolve_from(company: &Company, requested: Option<&str>) -> Result<Mailbox, AppError> {
+        let default_name = company
+            .default_from_name
+            .clone()
+            .filter(|name| !name.trim().is_empty());
+
+        let Some(requested) = requested.filter(|from| !from.trim().is_empty()) else {
+            let default_email = company
+                .default_from_email
+                .as_deref()
+                .filter(|email| !email.trim().is_empty())
+                .ok_or_else(|| {
+                    AppError::Validation(
+                        "No from address given and no default from email configured".to_string(),
+                    )
+                })?;
+            let email = default_email.parse().map_err(|_| {
+                AppError::Validation(format!(
+                    "Default from email '{}' is not a valid address",
+                    default_email
+                ))
+            })?;
+            return Ok(Mailbox::new(default_name, email));
         };
 
-        Ok(service_response(
-            200,
-            "Email queued successfully",
-            true,
-            Some(serde_json::to_value(response).unwrap()),
-        ))
+        let mailbox: Mailbox = requested
+            .parse()
+            .map_err(|_| AppError::Validation(format!("Invalid from address '{}'", requested)))?;
+
+        let sending_domain = company
+            .sending_domain
+            .as_deref()
+            .map(|domain| domain.trim())
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| {
+                AppError::Validation("No sending domain configured for this company".to_string())
+            })?;
+        if !mailbox.email.domain().eq_ignore_ascii_case(sending_domain) {
+            return Err(AppError::Validation(format!(
+                "From address '{}' is not on the sending domain '{}'",
+                mailbox.email, sending_domain
+            )));
+        }
+
+        Ok(Mailbox::new(mailbox.name.or(default_name), mailbox.email))
+    }
+
+    pub fn default_max_message_bytes() -> i64 {
+        get_env("MAX_MESSAGE_SIZE_BYTES", "10485760")
+            .parse()
+            .unwrap_or(10_485_760)
+    }
+
+    // The largest limit a company can be given. Request bodies are accepted up to this size so
+    // companies above the default aren't cut off before their own limit can be checked
+    pub fn max_message_bytes_ceiling() -> i64 {
+        get_env("MAX_MESSAGE_SIZE_CEILING_BYTES", "52428800")
+            .parse::<i64>()
+            .unwrap_or(52_428_800)
+            .max(Self::default_max_message_bytes())
+    }
+
+    // Set by the CreditEnforcement middleware; without it every call is charged
+    fn credit_allowance(req: &HttpRequest, company_id: i64) -> CreditAllowance {
+        req.extensions()
+            .get::<CreditAllowance>()
+            .copied()
+            .filter(|allowance| allowance.company_id == company_id)
+            .unwrap_or(CreditAllowance {
+                company_id,
+                unlimited: false,
+                remaining: 0,
+            })
+    }
+
+    // The credit debit is a conditional update that matches no row when the balance falls short
+    fn enqueue_error(error: diesel::result::Error, credits_required: i64) -> AppError {
+        match error {
+            diesel::result::Error::NotFound => AppError::Validation(format!(
+                "Insufficient API credits: {} required",
+                credits_required
+            )),
+            e => e.into(),
+        }
+    }
+
+    fn queued_status(send_at: Option<DateTime<Utc>>) -> &'static str {
+        if send_at.is_some() {
+            "scheduled"
+        } else {
+            "queued"
+        }
+    }
+
+    fn validate_send_at(send_at: DateTime<Utc>) -> Result<(), AppError> {
+        if send_at <= Utc::now() {
+            return Err(AppError::Validation(
+                "send_at must be in the future".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn max_batch_size() -> usize {
+        get_env("MAX_BATCH_SIZE", "500").parse().unwrap_or(500)
+    }
+
+    fn validate_attachments(
+        attachments: &[EmailAttachment],
+        content_bytes: usize,
+        max_message_bytes: i64,
+    ) -> Result<(), AppError> {
+        let mut total_bytes = content_bytes as i64;
+
+        for (index, attachment) in attachments.iter().enumerate() {
+            if attachment.filename.trim().is_empty() {
+                return Err(AppError::Validation(format!(
+                    "attachments[{}]: filename is required",
+                    index
+                )));
+            }
+
+            let bytes = attachment.decode().map_err(|_| {
+                AppError::Validation(format!(
+                    "attachments[{}]: content must be valid base64",
+                    index
+                ))
+            })?;
+
+            if attachment.mime_type().is_err() {
+                return Err(AppError::Validation(format!(
+                    "attachments[{}]: invalid content_type",
+                    index
+                )));
+            }
+
+            if let Some(content_id) = &attachment.content_id {
+                let invalid = content_id.is_empty()
+                    || content_id
+                        .chars()
+                        .any(|c| c.is_whitespace() || c == '<' || c == '>');
+                if invalid {
+                    return Err(AppError::Validation(format!(
+                        "attachments[{}]: invalid content_id",
+                        index
+                    )));
+                }
+            }
+
+            total_bytes += bytes.len() as i64;
+        }
+
+        if total_bytes > max_message_bytes {
+            return Err(AppError::Validation(format!(
+                "Message size {} bytes exceeds the limit of {} bytes",
+                total_bytes, max_message_bytes
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> SendEmailRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn duplicate_recipients_are_counted_once() {
+        let email_req = request(serde_json::json!({
+            "to": ["Ann <ann@example.com>", "ANN@example.com"],
+            "cc": ["ann@EXAMPLE.com", "bob@example.com"],
+            "bcc": ["Bob@example.com", "carol@example.com"],
+            "subject": "Hi",
+        }));
+
+        let recipients = PublicEmailController::validate_recipients(&email_req).unwrap();
+        let kept: Vec<(&str, &str)> = recipients
+            .iter()
+            .map(|r| (r.recipient_type, r.address.as_str()))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                ("to", "Ann <ann@example.com>"),
+                ("cc", "bob@example.com"),
+                ("bcc", "carol@example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_recipient_limit_applies_after_deduplication() {
+        let to: Vec<String> = (0..200).map(|i| format!("Same{}@Example.com", i % 2)).collect();
+        let email_req = request(serde_json::json!({ "to": to, "subject": "Hi" }));
+        assert_eq!(PublicEmailController::validate_recipients(&email_req).unwrap().len(), 2);
     }
 }
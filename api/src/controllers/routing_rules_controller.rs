@@ -0,0 +1,346 @@
+use crate::auth::jwt::Claims;
+use crate::controllers::company_id_for_user;
+use crate::errors::AppError;
+use crate::models::users::{NewSmtpRoutingRule, SmtpRoutingRule};
+use crate::repositories::{users::UserRepository, RepositoryFactory};
+use crate::utils::utils::service_response;
+use actix_web::{web, HttpResponse};
+use lettre::Address;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct RoutingRuleResponse {
+    pub id: i64,
+    pub name: String,
+    pub smtp_profile_id: i64,
+    pub priority: i32,
+    pub recipient_domain: Option<String>,
+    pub from_address: Option<String>,
+    pub template_id: Option<i64>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub is_active: bool,
+    pub created_at: String,
+}
+
+impl From<SmtpRoutingRule> for RoutingRuleResponse {
+    fn from(rule: SmtpRoutingRule) -> Self {
+        Self {
+            id: rule.id,
+            name: rule.name,
+            smtp_profile_id: rule.smtp_profile_id,
+            priority: rule.priority,
+            recipient_domain: rule.recipient_domain,
+            from_address: rule.from_address,
+            template_id: rule.template_id,
+            tag: rule.tag,
+            category: rule.category,
+            api_key_id: rule.api_key_id,
+            is_active: rule.is_active,
+            created_at: rule.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        }
+    }
+}
+
+// A rule sends matching messages through `smtp_profile_id`. Every criterion given must match and
+// at least one is required; rules are checked by ascending priority
+#[derive(Deserialize)]
+pub struct CreateRoutingRuleRequest {
+    pub name: String,
+    pub smtp_profile_id: i64,
+    pub priority: Option<i32>,
+    pub recipient_domain: Option<String>,
+    pub from_address: Option<String>,
+    pub template_id: Option<i64>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub is_active: Option<bool>,
+}
+
+// Replaces the rule; criteria left out are cleared
+#[derive(Deserialize)]
+pub struct UpdateRoutingRuleRequest {
+    pub name: String,
+    pub smtp_profile_id: i64,
+    pub priority: Option<i32>,
+    pub recipient_domain: Option<String>,
+    pub from_address: Option<String>,
+    pub template_id: Option<i64>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub is_active: Option<bool>,
+}
+
+// Rule fields after validation against the company's profiles, templates and API keys
+struct RuleFields {
+    name: String,
+    smtp_profile_id: i64,
+    priority: i32,
+    recipient_domain: Option<String>,
+    from_address: Option<String>,
+    template_id: Option<i64>,
+    tag: Option<String>,
+    category: Option<String>,
+    api_key_id: Option<i64>,
+}
+
+pub struct RoutingRulesController;
+
+impl RoutingRulesController {
+    pub async fn get_routing_rules(
+        claims: web::ReqData<Claims>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.into_inner().user_id)?;
+
+        let rules = user_repo.get_routing_rules_by_company(company_id)?;
+        let response: Vec<RoutingRuleResponse> =
+            rules.into_iter().map(RoutingRuleResponse::from).collect();
+
+        Ok(service_response(
+            200,
+            "Routing rules retrieved successfully",
+            true,
+            Some(serde_json::to_value(response).unwrap()),
+        ))
+    }
+
+    pub async fn get_routing_rule(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.into_inner().user_id)?;
+
+        let rule = Self::find_rule(&user_repo, path.into_inner(), company_id)?;
+
+        Ok(service_response(
+            200,
+            "Routing rule retrieved successfully",
+            true,
+            Some(serde_json::to_value(RoutingRuleResponse::from(rule)).unwrap()),
+        ))
+    }
+
+    pub async fn create_routing_rule(
+        claims: web::ReqData<Claims>,
+        req: web::Json<CreateRoutingRuleRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.into_inner().user_id)?;
+        let req = req.into_inner();
+
+        let fields = Self::rule_fields(
+            &user_repo,
+            company_id,
+            RuleFields {
+                name: req.name,
+                smtp_profile_id: req.smtp_profile_id,
+                priority: req.priority.unwrap_or(0),
+                recipient_domain: req.recipient_domain,
+                from_address: req.from_address,
+                template_id: req.template_id,
+                tag: req.tag,
+                category: req.category,
+                api_key_id: req.api_key_id,
+            },
+        )?;
+
+        let now = chrono::Utc::now();
+        let new_rule = NewSmtpRoutingRule {
+            company_id,
+            smtp_profile_id: fields.smtp_profile_id,
+            name: fields.name,
+            priority: fields.priority,
+            recipient_domain: fields.recipient_domain,
+            from_address: fields.from_address,
+            template_id: fields.template_id,
+            tag: fields.tag,
+            category: fields.category,
+            api_key_id: fields.api_key_id,
+            is_active: req.is_active.unwrap_or(true),
+            created_at: now,
+            updated_at: now,
+        };
+
+        let rule = user_repo.create_routing_rule(new_rule)?;
+
+        Ok(service_response(
+            201,
+            "Routing rule created successfully",
+            true,
+            Some(serde_json::to_value(RoutingRuleResponse::from(rule)).unwrap()),
+        ))
+    }
+
+    pub async fn update_routing_rule(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        req: web::Json<UpdateRoutingRuleRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.into_inner().user_id)?;
+        let rule_id = path.into_inner();
+        let req = req.into_inner();
+
+        let mut rule = Self::find_rule(&user_repo, rule_id, company_id)?;
+
+        let fields = Self::rule_fields(
+            &user_repo,
+            company_id,
+            RuleFields {
+                name: req.name,
+                smtp_profile_id: req.smtp_profile_id,
+                priority: req.priority.unwrap_or(rule.priority),
+                recipient_domain: req.recipient_domain,
+                from_address: req.from_address,
+                template_id: req.template_id,
+                tag: req.tag,
+                category: req.category,
+                api_key_id: req.api_key_id,
+            },
+        )?;
+
+        rule.name = fields.name;
+        rule.smtp_profile_id = fields.smtp_profile_id;
+        rule.priority = fields.priority;
+        rule.recipient_domain = fields.recipient_domain;
+        rule.from_address = fields.from_address;
+        rule.template_id = fields.template_id;
+        rule.tag = fields.tag;
+        rule.category = fields.category;
+        rule.api_key_id = fields.api_key_id;
+        rule.is_active = req.is_active.unwrap_or(rule.is_active);
+
+        let updated_rule = user_repo.update_routing_rule(rule_id, &rule)?;
+
+        Ok(service_response(
+            200,
+            "Routing rule updated successfully",
+            true,
+            Some(serde_json::to_value(RoutingRuleResponse::from(updated_rule)).unwrap()),
+        ))
+    }
+
+    pub async fn delete_routing_rule(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let company_id = company_id_for_user(&user_repo, claims.into_inner().user_id)?;
+
+        let deleted_count = user_repo.delete_routing_rule(path.into_inner(), company_id)?;
+        if deleted_count == 0 {
+            return Err(AppError::Validation("Routing rule not found".to_string()));
+        }
+
+        Ok(service_response(
+            200,
+            "Routing rule deleted successfully",
+            true,
+            None,
+        ))
+    }
+
+    fn find_rule(
+        user_repo: &impl UserRepository,
+        rule_id: i64,
+        company_id: i64,
+    ) -> Result<SmtpRoutingRule, AppError> {
+        user_repo
+            .get_routing_rule_by_id(rule_id, company_id)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => {
+                    AppError::Validation("Routing rule not found".to_string())
+                }
+                e => AppError::Database(e),
+            })
+    }
+
+    // Normalises the criteria and checks that everything the rule points at belongs to the company
+    fn rule_fields(
+        user_repo: &impl UserRepository,
+        company_id: i64,
+        fields: RuleFields,
+    ) -> Result<RuleFields, AppError> {
+        let non_empty = |value: Option<String>| {
+            value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let name = fields.name.trim().to_string();
+        if name.is_empty() {
+            return Err(AppError::Validation("name is required".to_string()));
+        }
+        if fields.priority < 0 {
+            return Err(AppError::Validation("priority must be zero or greater".to_string()));
+        }
+
+        let recipient_domain = non_empty(fields.recipient_domain)
+            .map(|domain| domain.trim_start_matches('@').to_lowercase());
+        if let Some(domain) = &recipient_domain {
+            if domain.is_empty() || domain.contains('@') {
+                return Err(AppError::Validation(
+                    "recipient_domain must be a domain such as example.com".to_string(),
+                ));
+            }
+        }
+        let from_address = non_empty(fields.from_address);
+        if let Some(from_address) = &from_address {
+            from_address.parse::<Address>().map_err(|_| {
+                AppError::Validation("from_address must be an email address".to_string())
+            })?;
+        }
+        let tag = non_empty(fields.tag);
+        let category = non_empty(fields.category);
+
+        if recipient_domain.is_none()
+            && from_address.is_none()
+            && fields.template_id.is_none()
+            && tag.is_none()
+            && category.is_none()
+            && fields.api_key_id.is_none()
+        {
+            return Err(AppError::Validation(
+                "A routing rule needs at least one of recipient_domain, from_address, template_id, tag, category or api_key_id".to_string(),
+            ));
+        }
+
+        let smtp_profile = user_repo
+            .get_smtp_profile_by_id(fields.smtp_profile_id)
+            .ok()
+            .filter(|profile| profile.company_id == company_id);
+        if smtp_profile.is_none() {
+            return Err(AppError::Validation("SMTP profile not found".to_string()));
+        }
+        if let Some(template_id) = fields.template_id {
+            user_repo
+                .get_template_by_id(template_id, company_id)
+                .map_err(|_| AppError::Validation("Template not found".to_string()))?;
+        }
+        if let Some(api_key_id) = fields.api_key_id {
+            let api_keys = user_repo.get_api_keys_by_company(company_id)?;
+            if !api_keys.iter().any(|api_key| api_key.id == api_key_id) {
+                return Err(AppError::Validation("API key not found".to_string()));
+            }
+        }
+
+        Ok(RuleFields {
+            name,
+            recipient_domain,
+            from_address,
+            tag,
+            category,
+            ..fields
+        })
+    }
+}
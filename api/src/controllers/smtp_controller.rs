@@ -1,7 +1,12 @@
 use crate::auth::jwt::Claims;
+use crate::controllers::company_id_for_user;
 use crate::errors::AppError;
-use crate::models::users::NewSmtpProfile;
+use crate::models::users::{NewSmtpProfile, SmtpProfile};
 use crate::repositories::{users::UserRepository, RepositoryFactory};
+use crate::services::email_service::{TlsMode, TlsSettings};
+use crate::services::smtp_diagnostics::{self, TestMessage};
+use crate::services::smtp_failover::DEFAULT_WEIGHT;
+use crate::services::smtp_pool;
 use crate::utils::utils::service_response;
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
@@ -13,9 +18,32 @@ pub struct SmtpProfileResponse {
     pub smtp_server: String,
     pub smtp_port: i32,
     pub is_default: bool,
+    pub tls_mode: String,
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    pub priority: i32,
+    pub weight: i32,
     pub created_at: String,
 }
 
+impl From<SmtpProfile> for SmtpProfileResponse {
+    fn from(profile: SmtpProfile) -> Self {
+        Self {
+            id: profile.id,
+            smtp_username: profile.smtp_username,
+            smtp_server: profile.smtp_server,
+            smtp_port: profile.smtp_port,
+            is_default: profile.is_default,
+            tls_mode: profile.tls_mode,
+            ca_certificate: profile.ca_certificate,
+            tls_hostname: profile.tls_hostname,
+            priority: profile.priority,
+            weight: profile.weight,
+            created_at: profile.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct CreateSmtpProfileRequest {
     pub smtp_username: String,
@@ -23,6 +51,15 @@ pub struct CreateSmtpProfileRequest {
     pub smtp_server: String,
     pub smtp_port: i32,
     pub is_default: Option<bool>,
+    // One of starttls (default), implicit_tls, opportunistic or none
+    pub tls_mode: Option<String>,
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    // Failover order: lower priorities are tried first (default 0)
+    pub priority: Option<i32>,
+    // Share of traffic among profiles with the same priority (default 1, 0 only as a last resort).
+    // While every profile keeps the default, the default profile takes all the traffic
+    pub weight: Option<i32>,
 }
 
 #[derive(Deserialize)]
@@ -32,6 +69,24 @@ pub struct UpdateSmtpProfileRequest {
     pub smtp_server: String,
     pub smtp_port: i32,
     pub is_default: Option<bool>,
+    // One of starttls (default), implicit_tls, opportunistic or none
+    pub tls_mode: Option<String>,
+    // Left out to keep the stored value; an empty string clears it
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    // Failover order: lower priorities are tried first (default 0)
+    pub priority: Option<i32>,
+    // Share of traffic among profiles with the same priority (default 1, 0 only as a last resort).
+    // While every profile keeps the default, the default profile takes all the traffic
+    pub weight: Option<i32>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct TestSmtpProfileRequest {
+    // Sends a real test message here once the connection checks pass
+    pub to: Option<String>,
+    // Defaults to the company's default from address
+    pub from: Option<String>,
 }
 
 pub struct SmtpController;
@@ -44,23 +99,11 @@ impl SmtpController {
         let user_repo = repo_factory.create_user_repository();
         let user_id = claims.into_inner().user_id;
 
-        // Get user's company through team membership
-        let team_members = user_repo.get_team_members_by_user(user_id)?;
-        let company_id = team_members.first()
-            .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
-            .company_id;
+        let company_id = company_id_for_user(&user_repo, user_id)?;
 
         let profiles = user_repo.get_smtp_profiles_by_company(company_id)?;
-        let response_profiles: Vec<SmtpProfileResponse> = profiles.into_iter().map(|profile| {
-            SmtpProfileResponse {
-                id: profile.id,
-                smtp_username: profile.smtp_username,
-                smtp_server: profile.smtp_server,
-                smtp_port: profile.smtp_port,
-                is_default: profile.is_default,
-                created_at: profile.created_at.format("%Y-%m-%d %H:%M").to_string(),
-            }
-        }).collect();
+        let response_profiles: Vec<SmtpProfileResponse> =
+            profiles.into_iter().map(SmtpProfileResponse::from).collect();
 
         Ok(service_response(
             200,
@@ -78,11 +121,16 @@ impl SmtpController {
         let user_repo = repo_factory.create_user_repository();
         let user_id = claims.into_inner().user_id;
 
-        // Get user's company through team membership
-        let team_members = user_repo.get_team_members_by_user(user_id)?;
-        let company_id = team_members.first()
-            .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
-            .company_id;
+        let company_id = company_id_for_user(&user_repo, user_id)?;
+
+        let tls = Self::tls_settings(
+            &req.smtp_server,
+            req.tls_mode.as_deref(),
+            &req.ca_certificate,
+            &req.tls_hostname,
+        )?;
+        let (priority, weight) =
+            Self::failover_settings(req.priority.unwrap_or(0), req.weight.unwrap_or(DEFAULT_WEIGHT))?;
 
         let new_profile = NewSmtpProfile {
             company_id,
@@ -93,18 +141,16 @@ impl SmtpController {
             is_default: req.is_default.unwrap_or(false),
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
+            tls_mode: tls.mode.as_str().to_string(),
+            ca_certificate: tls.ca_certificate,
+            tls_hostname: tls.hostname,
+            priority,
+            weight,
         };
 
         let created_profile = user_repo.create_smtp_profile(new_profile)?;
 
-        let response = SmtpProfileResponse {
-            id: created_profile.id,
-            smtp_username: created_profile.smtp_username,
-            smtp_server: created_profile.smtp_server,
-            smtp_port: created_profile.smtp_port,
-            is_default: created_profile.is_default,
-            created_at: created_profile.created_at.format("%Y-%m-%d %H:%M").to_string(),
-        };
+        let response = SmtpProfileResponse::from(created_profile);
 
         Ok(service_response(
             201,
@@ -124,11 +170,7 @@ impl SmtpController {
         let user_id = claims.into_inner().user_id;
         let profile_id = path.into_inner();
 
-        // Get user's company through team membership
-        let team_members = user_repo.get_team_members_by_user(user_id)?;
-        let company_id = team_members.first()
-            .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
-            .company_id;
+        let company_id = company_id_for_user(&user_repo, user_id)?;
 
         // Get existing profile to ensure it belongs to user's company
         let mut existing_profile = user_repo.get_smtp_profile_by_id(profile_id)?;
@@ -136,23 +178,33 @@ impl SmtpController {
             return Err(AppError::Validation("SMTP profile not found".to_string()));
         }
 
+        let tls = Self::tls_settings(
+            &req.smtp_server,
+            Some(req.tls_mode.as_deref().unwrap_or(&existing_profile.tls_mode)),
+            &req.ca_certificate.clone().or(existing_profile.ca_certificate.clone()),
+            &req.tls_hostname.clone().or(existing_profile.tls_hostname.clone()),
+        )?;
+        let (priority, weight) = Self::failover_settings(
+            req.priority.unwrap_or(existing_profile.priority),
+            req.weight.unwrap_or(existing_profile.weight),
+        )?;
+
         // Update profile fields
         existing_profile.smtp_username = req.smtp_username.clone();
         existing_profile.smtp_password = req.smtp_password.clone();
         existing_profile.smtp_server = req.smtp_server.clone();
         existing_profile.smtp_port = req.smtp_port;
         existing_profile.is_default = req.is_default.unwrap_or(existing_profile.is_default);
+        existing_profile.tls_mode = tls.mode.as_str().to_string();
+        existing_profile.ca_certificate = tls.ca_certificate;
+        existing_profile.tls_hostname = tls.hostname;
+        existing_profile.priority = priority;
+        existing_profile.weight = weight;
 
         let updated_profile = user_repo.update_smtp_profile(profile_id, &existing_profile)?;
+        smtp_pool::invalidate(profile_id);
 
-        let response = SmtpProfileResponse {
-            id: updated_profile.id,
-            smtp_username: updated_profile.smtp_username,
-            smtp_server: updated_profile.smtp_server,
-            smtp_port: updated_profile.smtp_port,
-            is_default: updated_profile.is_default,
-            created_at: updated_profile.created_at.format("%Y-%m-%d %H:%M").to_string(),
-        };
+        let response = SmtpProfileResponse::from(updated_profile);
 
         Ok(service_response(
             200,
@@ -171,16 +223,13 @@ impl SmtpController {
         let user_id = claims.into_inner().user_id;
         let profile_id = path.into_inner();
 
-        // Get user's company through team membership
-        let team_members = user_repo.get_team_members_by_user(user_id)?;
-        let company_id = team_members.first()
-            .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
-            .company_id;
+        let company_id = company_id_for_user(&user_repo, user_id)?;
 
         let deleted_count = user_repo.delete_smtp_profile(profile_id, company_id)?;
         if deleted_count == 0 {
             return Err(AppError::Validation("SMTP profile not found".to_string()));
         }
+        smtp_pool::invalidate(profile_id);
 
         Ok(service_response(
             200,
@@ -199,22 +248,11 @@ impl SmtpController {
         let user_id = claims.into_inner().user_id;
         let profile_id = path.into_inner();
 
-        // Get user's company through team membership
-        let team_members = user_repo.get_team_members_by_user(user_id)?;
-        let company_id = team_members.first()
-            .ok_or_else(|| AppError::Validation("User not associated with any company".to_string()))?
-            .company_id;
+        let company_id = company_id_for_user(&user_repo, user_id)?;
 
         let updated_profile = user_repo.set_default_smtp_profile(profile_id, company_id)?;
 
-        let response = SmtpProfileResponse {
-            id: updated_profile.id,
-            smtp_username: updated_profile.smtp_username,
-            smtp_server: updated_profile.smtp_server,
-            smtp_port: updated_profile.smtp_port,
-            is_default: updated_profile.is_default,
-            created_at: updated_profile.created_at.format("%Y-%m-%d %H:%M").to_string(),
-        };
+        let response = SmtpProfileResponse::from(updated_profile);
 
         Ok(service_response(
             200,
@@ -223,4 +261,115 @@ impl SmtpController {
             Some(serde_json::to_value(response).unwrap()),
         ))
     }
+
+    pub async fn test_smtp_profile(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        req: Option<web::Json<TestSmtpProfileRequest>>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let user_repo = repo_factory.create_user_repository();
+        let user_id = claims.into_inner().user_id;
+        let profile_id = path.into_inner();
+        let req = req.map(web::Json::into_inner).unwrap_or_default();
+
+        let company_id = company_id_for_user(&user_repo, user_id)?;
+
+        let profile = user_repo.get_smtp_profile_by_id(profile_id)?;
+        if profile.company_id != company_id {
+            return Err(AppError::Validation("SMTP profile not found".to_string()));
+        }
+
+        let test_message = match req.to {
+            Some(to) => {
+                let from = match req.from {
+                    Some(from) => from,
+                    None => user_repo
+                        .get_company_by_id(company_id)?
+                        .default_from_email
+                        .ok_or_else(|| {
+                            AppError::Validation(
+                                "from is required when the company has no default from address"
+                                    .to_string(),
+                            )
+                        })?,
+                };
+                Some(TestMessage { from, to })
+            }
+            None => None,
+        };
+
+        let report = smtp_diagnostics::test_smtp_profile(&profile, test_message).await;
+        let message = if report.success {
+            "SMTP profile test passed"
+        } else {
+            "SMTP profile test failed"
+        };
+
+        Ok(service_response(
+            200,
+            message,
+            true,
+            Some(serde_json::to_value(report).unwrap()),
+        ))
+    }
+
+    // Checks the TLS fields the way the transport will use them, so a bad certificate is caught on
+    // save rather than on the first send
+    fn tls_settings(
+        smtp_server: &str,
+        tls_mode: Option<&str>,
+        ca_certificate: &Option<String>,
+        tls_hostname: &Option<String>,
+    ) -> Result<TlsSettings, AppError> {
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+
+        let mode = match tls_mode {
+            Some(mode) => TlsMode::parse(mode).ok_or_else(|| {
+                AppError::Validation(format!(
+                    "tls_mode must be one of: {}",
+                    TlsMode::ALL.map(|m| m.as_str()).join(", ")
+                ))
+            })?,
+            None => TlsMode::default(),
+        };
+        let settings = TlsSettings {
+            mode,
+            ca_certificate: non_empty(ca_certificate),
+            hostname: non_empty(tls_hostname),
+        };
+        if mode == TlsMode::None && (settings.ca_certificate.is_some() || settings.hostname.is_some()) {
+            return Err(AppError::Validation(
+                "ca_certificate and tls_hostname need a tls_mode other than none".to_string(),
+            ));
+        }
+        if let Some(pem) = &settings.ca_certificate {
+            if !pem.contains("-----BEGIN CERTIFICATE-----") {
+                return Err(AppError::Validation(
+                    "ca_certificate must be one or more PEM certificates".to_string(),
+                ));
+            }
+        }
+        settings
+            .tls(smtp_server)
+            .map_err(|e| AppError::Validation(format!("Invalid TLS settings: {}", e)))?;
+
+        Ok(settings)
+    }
+
+    fn failover_settings(priority: i32, weight: i32) -> Result<(i32, i32), AppError> {
+        if priority < 0 {
+            return Err(AppError::Validation("priority must be zero or greater".to_string()));
+        }
+        if weight < 0 {
+            return Err(AppError::Validation("weight must be zero or greater".to_string()));
+        }
+        Ok((priority, weight))
+    }
 }
\ No newline at end of file
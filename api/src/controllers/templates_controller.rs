@@ -1,6 +1,7 @@
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use crate::utils::utils::service_response;
+use crate::utils::template::validate_template;
 use crate::errors::AppError;
 use crate::auth::jwt::Claims;
 use crate::repositories::{users::UserRepository, RepositoryFactory};
@@ -102,6 +103,9 @@ impl TemplatesController {
         if req.name.is_empty() || req.subject.is_empty() {
             return Err(AppError::Validation("Template name and subject are required".to_string()));
         }
+        validate_template(&req.subject)
+            .and_then(|_| validate_template(&req.content))
+            .map_err(|e| AppError::Validation(e.to_string()))?;
 
         let user_repo = repo_factory.create_user_repository();
         let user_id = claims.into_inner().user_id;
@@ -27,6 +27,7 @@ pub struct CompanyInfo {
     pub website: Option<String>,
     pub pricing_tier: String,
     pub api_credits: i64,
+    pub topup_credits: i64,
     pub sending_domain: Option<String>,
     pub member_role: Option<String>,
 }
@@ -53,6 +54,7 @@ impl UserController {
                         website: company.website,
                         pricing_tier: company.pricing_tier,
                         api_credits: company.api_credits,
+                        topup_credits: company.topup_credits,
                         sending_domain: company.sending_domain,
                         member_role: Some(team_member.role.clone()),
                     })
@@ -0,0 +1,381 @@
+use crate::auth::jwt::Claims;
+use crate::controllers::company_id_for_user;
+use crate::errors::AppError;
+use crate::models::core::{NewWebhook, Webhook, WebhookDelivery};
+use crate::repositories::{CoreRepository, RepositoryFactory};
+use crate::services::webhooks::{self, STATUS_ACTIVE, WEBHOOK_EVENTS};
+use crate::utils::utils::service_response;
+use api::webhook_signature::generate_secret;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct WebhookResponse {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub is_active: bool,
+    pub status: Option<String>,
+    pub success_rate: Option<f64>,
+    pub last_delivered: Option<String>,
+    pub created_at: String,
+    // Only returned when the secret is created or rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(webhook: Webhook) -> Self {
+        WebhookResponse {
+            id: webhook.id,
+            name: webhook.name,
+            url: webhook.url,
+            events: webhook.events.unwrap_or_default().into_iter().flatten().collect(),
+            is_active: webhook.is_active,
+            status: webhook.status,
+            success_rate: webhook.success_rate,
+            last_delivered: webhook
+                .last_delivered
+                .map(|delivered| delivered.format("%Y-%m-%d %H:%M").to_string()),
+            created_at: webhook.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            secret: None,
+        }
+    }
+}
+
+impl WebhookResponse {
+    fn with_secret(webhook: Webhook) -> Self {
+        let secret = webhook.secret.clone();
+        WebhookResponse {
+            secret,
+            ..WebhookResponse::from(webhook)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhookRequest {
+    pub name: String,
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateWebhookRequest {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub is_active: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct WebhookDeliveryResponse {
+    pub id: i64,
+    pub event_id: String,
+    pub event: String,
+    pub attempt: i32,
+    pub status: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: Option<i32>,
+    pub payload: serde_json::Value,
+    pub next_attempt_at: String,
+    pub completed_at: Option<String>,
+    pub created_at: String,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryResponse {
+    fn from(delivery: WebhookDelivery) -> Self {
+        WebhookDeliveryResponse {
+            id: delivery.id,
+            event_id: delivery.event_id,
+            event: delivery.event,
+            attempt: delivery.attempt,
+            status: delivery.status,
+            response_status: delivery.response_status,
+            response_body: delivery.response_body,
+            error: delivery.error,
+            latency_ms: delivery.latency_ms,
+            payload: delivery.payload,
+            next_attempt_at: delivery.next_attempt_at.to_rfc3339(),
+            completed_at: delivery.completed_at.map(|completed| completed.to_rfc3339()),
+            created_at: delivery.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryFilters {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+pub struct WebhooksController;
+
+impl WebhooksController {
+    pub async fn get_webhooks(
+        claims: web::ReqData<Claims>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+
+        let webhooks: Vec<WebhookResponse> = core_repo
+            .get_webhooks_by_company(company_id)?
+            .into_iter()
+            .map(WebhookResponse::from)
+            .collect();
+
+        Ok(service_response(
+            200,
+            "Webhooks retrieved successfully",
+            true,
+            Some(serde_json::to_value(webhooks).unwrap()),
+        ))
+    }
+
+    pub async fn create_webhook(
+        claims: web::ReqData<Claims>,
+        req: web::Json<CreateWebhookRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+
+        if req.name.trim().is_empty() {
+            return Err(AppError::Validation("Webhook name is required".to_string()));
+        }
+        Self::validate_url(&req.url).await?;
+        let events = Self::validate_events(&req.events)?;
+
+        let now = chrono::Utc::now();
+        let new_webhook = NewWebhook {
+            name: req.name.trim().to_string(),
+            url: req.url.trim().to_string(),
+            company_id,
+            events: Some(events),
+            status: Some(STATUS_ACTIVE.to_string()),
+            is_active: true,
+            created_at: now,
+            updated_at: now,
+            secret: Some(generate_secret()),
+        };
+
+        let core_repo = repo_factory.create_core_repository();
+        let webhook = core_repo.create_webhook(new_webhook)?;
+        log::info!("Created webhook {} for company {}", webhook.id, company_id);
+
+        Ok(service_response(
+            201,
+            "Webhook created successfully",
+            true,
+            Some(serde_json::to_value(WebhookResponse::with_secret(webhook)).unwrap()),
+        ))
+    }
+
+    pub async fn get_webhook(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+
+        let webhook = core_repo
+            .get_webhook_by_id(path.into_inner(), company_id)
+            .map_err(|_| AppError::Validation("Webhook not found".to_string()))?;
+
+        Ok(service_response(
+            200,
+            "Webhook retrieved successfully",
+            true,
+            Some(serde_json::to_value(WebhookResponse::from(webhook)).unwrap()),
+        ))
+    }
+
+    pub async fn update_webhook(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        req: web::Json<UpdateWebhookRequest>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+        let webhook_id = path.into_inner();
+
+        let mut webhook = core_repo
+            .get_webhook_by_id(webhook_id, company_id)
+            .map_err(|_| AppError::Validation("Webhook not found".to_string()))?;
+
+        if let Some(name) = &req.name {
+            if name.trim().is_empty() {
+                return Err(AppError::Validation("Webhook name is required".to_string()));
+            }
+            webhook.name = name.trim().to_string();
+        }
+        if let Some(url) = &req.url {
+            Self::validate_url(url).await?;
+            webhook.url = url.trim().to_string();
+        }
+        if let Some(events) = &req.events {
+            webhook.events = Some(Self::validate_events(events)?);
+        }
+        if let Some(is_active) = req.is_active {
+            // Re-enabling starts the webhook over with a clean status
+            if is_active && !webhook.is_active {
+                webhook.status = Some(STATUS_ACTIVE.to_string());
+                webhook.consecutive_failures = 0;
+            }
+            webhook.is_active = is_active;
+        }
+
+        let updated = core_repo.update_webhook(webhook_id, &webhook)?;
+
+        Ok(service_response(
+            200,
+            "Webhook updated successfully",
+            true,
+            Some(serde_json::to_value(WebhookResponse::from(updated)).unwrap()),
+        ))
+    }
+
+    pub async fn delete_webhook(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+
+        let deleted_count = core_repo.delete_webhook(path.into_inner(), company_id)?;
+        if deleted_count == 0 {
+            return Err(AppError::Validation("Webhook not found".to_string()));
+        }
+
+        Ok(service_response(
+            200,
+            "Webhook deleted successfully",
+            true,
+            None,
+        ))
+    }
+
+    pub async fn rotate_webhook_secret(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+
+        let webhook = core_repo
+            .get_webhook_by_id(path.into_inner(), company_id)
+            .map_err(|_| AppError::Validation("Webhook not found".to_string()))?;
+
+        // The old secret stops working immediately
+        let rotated = core_repo.update_webhook_secret(webhook.id, &generate_secret())?;
+        log::info!("Rotated signing secret for webhook {}", rotated.id);
+
+        Ok(service_response(
+            200,
+            "Webhook secret rotated successfully",
+            true,
+            Some(serde_json::to_value(WebhookResponse::with_secret(rotated)).unwrap()),
+        ))
+    }
+
+    pub async fn get_webhook_deliveries(
+        claims: web::ReqData<Claims>,
+        path: web::Path<i64>,
+        query: web::Query<DeliveryFilters>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+
+        let webhook = core_repo
+            .get_webhook_by_id(path.into_inner(), company_id)
+            .map_err(|_| AppError::Validation("Webhook not found".to_string()))?;
+
+        let limit = query.limit.unwrap_or(50).clamp(1, 200);
+        let deliveries: Vec<WebhookDeliveryResponse> = core_repo
+            .get_webhook_deliveries(webhook.id, query.status.as_deref(), limit)?
+            .into_iter()
+            .map(WebhookDeliveryResponse::from)
+            .collect();
+
+        Ok(service_response(
+            200,
+            "Webhook deliveries retrieved successfully",
+            true,
+            Some(serde_json::to_value(deliveries).unwrap()),
+        ))
+    }
+
+    pub async fn replay_webhook_delivery(
+        claims: web::ReqData<Claims>,
+        path: web::Path<(i64, i64)>,
+        repo_factory: web::Data<RepositoryFactory>,
+    ) -> Result<HttpResponse, AppError> {
+        let company_id = company_id_for_user(&repo_factory.create_user_repository(), claims.user_id)?;
+        let core_repo = repo_factory.create_core_repository();
+        let (webhook_id, delivery_id) = path.into_inner();
+
+        let webhook = core_repo
+            .get_webhook_by_id(webhook_id, company_id)
+            .map_err(|_| AppError::Validation("Webhook not found".to_string()))?;
+        let delivery = core_repo
+            .get_webhook_delivery_by_id(delivery_id, webhook.id)
+            .map_err(|_| AppError::Validation("Webhook delivery not found".to_string()))?;
+
+        let replay = webhooks::replay_delivery(&repo_factory, &delivery)?;
+        log::info!(
+            "Replaying event {} to webhook {} as delivery {}",
+            delivery.event_id,
+            webhook.id,
+            replay.id
+        );
+
+        Ok(service_response(
+            202,
+            "Webhook delivery queued for replay",
+            true,
+            Some(serde_json::to_value(WebhookDeliveryResponse::from(replay)).unwrap()),
+        ))
+    }
+
+    async fn validate_url(url: &str) -> Result<(), AppError> {
+        webhooks::check_destination(url).await.map_err(AppError::Validation)
+    }
+
+    fn validate_events(events: &[String]) -> Result<Vec<Option<String>>, AppError> {
+        if events.is_empty() {
+            return Err(AppError::Validation(format!(
+                "At least one event is required: {}",
+                WEBHOOK_EVENTS.join(", ")
+            )));
+        }
+
+        let unknown: Vec<&str> = events
+            .iter()
+            .map(String::as_str)
+            .filter(|event| !WEBHOOK_EVENTS.contains(event))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(AppError::Validation(format!(
+                "Unknown webhook events: {} (expected {})",
+                unknown.join(", "),
+                WEBHOOK_EVENTS.join(", ")
+            )));
+        }
+
+        let mut subscribed: Vec<Option<String>> = Vec::new();
+        for event in events {
+            if !subscribed.iter().flatten().any(|e| e == event) {
+                subscribed.push(Some(event.clone()));
+            }
+        }
+        Ok(subscribed)
+    }
+}
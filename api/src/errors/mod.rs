@@ -1,3 +1,4 @@
+use crate::services::payments::PaymentError;
 use crate::utils::utils::service_response;
 use actix_web::{HttpResponse, ResponseError};
 use redis::Msg;
@@ -29,6 +30,12 @@ pub enum AppError {
     #[error("User already exists")]
     UserExists,
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Payment error: {0}")]
+    Payment(#[from] PaymentError),
+
     #[error("Internal server error")]
     Internal,
 }
@@ -70,6 +77,17 @@ impl ResponseError for AppError {
                 log::warn!("User already exists");
                 service_response(409, "User already exists", false, None)
             }
+            AppError::Conflict(msg) => {
+                log::warn!("Conflict: {}", msg);
+                service_response(409, msg, false, None)
+            }
+            AppError::Payment(e) => {
+                log::warn!("Payment error: {}", e);
+                match e {
+                    PaymentError::Declined(_) => service_response(402, &e.to_string(), false, None),
+                    PaymentError::Unavailable(_) => service_response(503, &e.to_string(), false, None),
+                }
+            }
             AppError::Internal => {
                 log::error!("Internal server error");
                 service_response(500, "Internal server error", false, None)
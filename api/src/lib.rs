@@ -0,0 +1,2 @@
+// Library surface for services that receive MailNow webhooks
+pub mod webhook_signature;
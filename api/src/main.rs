@@ -91,11 +91,18 @@ async fn main() -> std::io::Result<()> {
     // Create repository factory
     let repo_factory = RepositoryFactory::new(db_pool.clone());
 
+    // Start the outbound email queue workers
+    services::background::spawn_background_jobs(repo_factory.clone());
+    log::info!("Background jobs started");
+
     // Create JWT service
     let jwt_secret = get_env("JWT_SECRET", "your-secret-key");
     let jwt_service = JwtService::new(jwt_secret);
     log::info!("JWT service initialized");
 
+    let payment_provider = services::payments::payment_provider_from_env();
+    log::info!("Payment provider initialized ({})", payment_provider.name());
+
     log::info!("🚀 Server starting on port {} 🔥", port);
 
     HttpServer::new(move || {
@@ -111,6 +118,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(repo_factory.clone()))
             .app_data(web::Data::new(jwt_service.clone()))
+            .app_data(web::Data::from(payment_provider.clone()))
             .wrap(cors)
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
@@ -127,6 +135,9 @@ async fn main() -> std::io::Result<()> {
             .configure(routes::smtp_routes::register_smtp_routes)
             .configure(routes::public_email_routes::register_public_email_routes)
             .configure(routes::settings_routes::register_settings_routes)
+            .configure(routes::webhooks_routes::register_webhooks_routes)
+            .configure(routes::billing_routes::register_billing_routes)
+            .configure(routes::routing_rules_routes::register_routing_rules_routes)
     })
     .bind(("0.0.0.0", port))?
     .run()
@@ -1,65 +1,179 @@
 use crate::repositories::{users::UserRepository, RepositoryFactory};
 use crate::utils::pricing::{PricingTier, should_reset_credits};
 use crate::errors::AppError;
+use crate::models::users::Company;
+use crate::services::email_queue::idempotency_key_ttl;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage,
+};
+use chrono::Utc;
+use futures_util::future::LocalBoxFuture;
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+// Calls that consume credits; everything else under /v1 only gets the reset and tier lookup
+const METERED_PATHS: [&str; 2] = ["/v1/email/send", "/v1/email/batch"];
+
+// What the credit layer decided for this request's company, for handlers to charge against
+#[derive(Debug, Clone, Copy)]
+pub struct CreditAllowance {
+    pub company_id: i64,
+    pub unlimited: bool,
+    pub remaining: i64,
+}
+
+impl CreditAllowance {
+    // Credits a metered call should debit for the given number of units
+    pub fn charge(&self, units: i64) -> i64 {
+        if self.unlimited {
+            0
+        } else {
+            units
+        }
+    }
+}
 
 pub async fn check_and_reset_credits(
     company_id: i64,
     repo_factory: &RepositoryFactory,
-) -> Result<(), AppError> {
+) -> Result<Company, AppError> {
     let user_repo = repo_factory.create_user_repository();
     let company = user_repo.get_company_by_id(company_id)
         .map_err(|e| AppError::Database(e))?;
-    
+
     // Check if credits need to be reset
     if should_reset_credits(company.credits_reset_date) {
         let pricing_tier = PricingTier::from_str(&company.pricing_tier);
-        
+
         // Only reset for non-enterprise tiers
-        if !matches!(pricing_tier, PricingTier::Enterprise) {
-            user_repo.reset_company_credits(company_id, &company.pricing_tier)
-                .map_err(|e| AppError::Database(e))?;
-            log::info!("Reset API credits for company {} ({})", company_id, company.pricing_tier);
+        if !pricing_tier.is_unlimited() {
+            match user_repo.reset_company_credits(company_id, &company.pricing_tier) {
+                Ok(company) => {
+                    log::info!("Reset API credits for company {} ({})", company_id, company.pricing_tier);
+                    return Ok(company);
+                }
+                // Another request got to the reset first
+                Err(diesel::result::Error::NotFound) => {
+                    return user_repo.get_company_by_id(company_id).map_err(AppError::Database);
+                }
+                Err(e) => return Err(AppError::Database(e)),
+            }
         }
     }
-    
-    Ok(())
+
+    Ok(company)
 }
 
 pub async fn check_api_credits(
     company_id: i64,
     repo_factory: &RepositoryFactory,
-) -> Result<bool, AppError> {
+) -> Result<CreditAllowance, AppError> {
     // First check if credits need reset
-    check_and_reset_credits(company_id, repo_factory).await?;
-    
-    let user_repo = repo_factory.create_user_repository();
-    let company = user_repo.get_company_by_id(company_id)
-        .map_err(|e| AppError::Database(e))?;
-    
+    let company = check_and_reset_credits(company_id, repo_factory).await?;
+
     // Enterprise has unlimited credits
-    if company.pricing_tier == "enterprise" {
-        return Ok(true);
+    Ok(CreditAllowance {
+        company_id,
+        unlimited: PricingTier::from_str(&company.pricing_tier).is_unlimited(),
+        remaining: company.api_credits + company.topup_credits,
+    })
+}
+
+// A retry of a send that already went through is answered from the stored response and charges
+// nothing, so it must not be turned away just because the balance has since run out
+fn is_idempotent_replay(req: &ServiceRequest, company_id: i64, repo_factory: &RepositoryFactory) -> bool {
+    let Some(key) = req.headers().get("Idempotency-Key").and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    repo_factory
+        .create_user_repository()
+        .get_completed_idempotency_key(company_id, key.trim(), Utc::now() - idempotency_key_ttl())
+        .is_ok_and(|stored| stored.is_some())
+}
+
+// Resolves the calling API key's company on every /v1 request, resets its credits when due and
+// applies its pricing tier. Metered calls are turned away up front once a limited balance is spent;
+// the handlers' atomic debit remains the final check
+pub struct CreditEnforcement;
+
+impl<S, B> Transform<S, ServiceRequest> for CreditEnforcement
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CreditEnforcementMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CreditEnforcementMiddleware {
+            service: Rc::new(service),
+        }))
     }
-    
-    // Check if company has credits remaining
-    Ok(company.api_credits > 0)
 }
 
-pub async fn deduct_api_credit(
-    company_id: i64,
-    repo_factory: &RepositoryFactory,
-) -> Result<(), AppError> {
-    let user_repo = repo_factory.create_user_repository();
-    let company = user_repo.get_company_by_id(company_id)
-        .map_err(|e| AppError::Database(e))?;
-    
-    // Don't deduct for enterprise
-    if company.pricing_tier == "enterprise" {
-        return Ok(());
+pub struct CreditEnforcementMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CreditEnforcementMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let repo_factory = req.app_data::<web::Data<RepositoryFactory>>().cloned();
+            let api_key = req
+                .headers()
+                .get("X-API-Key")
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string);
+
+            // Requests without a usable key are left to the handler to reject
+            let company_id = match (&repo_factory, api_key) {
+                (Some(repo_factory), Some(api_key)) => repo_factory
+                    .create_user_repository()
+                    .get_api_key_by_key(&api_key)
+                    .ok()
+                    .filter(|api_key| api_key.is_active)
+                    .map(|api_key| api_key.company_id),
+                _ => None,
+            };
+
+            if let (Some(repo_factory), Some(company_id)) = (&repo_factory, company_id) {
+                let allowance = check_api_credits(company_id, repo_factory).await?;
+
+                let metered = req.method() == actix_web::http::Method::POST
+                    && METERED_PATHS.contains(&req.path().trim_end_matches('/'));
+                if metered
+                    && !allowance.unlimited
+                    && allowance.remaining <= 0
+                    && !is_idempotent_replay(&req, company_id, repo_factory)
+                {
+                    log::warn!("Company {} is out of API credits", company_id);
+                    return Err(AppError::Validation("Insufficient API credits".to_string()).into());
+                }
+
+                req.extensions_mut().insert(allowance);
+            }
+
+            service.call(req).await
+        })
     }
-    
-    user_repo.deduct_api_credit(company_id)
-        .map_err(|e| AppError::Database(e))?;
-    
-    Ok(())
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
 use crate::models::users::Company;
-use crate::schema::{emaillog, smtpprofiles, templates, webhooks};
+use crate::schema::{emaillog, smtpprofiles, templates, webhook_deliveries, webhooks};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -17,6 +17,11 @@ pub struct SMTPProfile {
     pub is_default: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub tls_mode: String,
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    pub priority: i32,
+    pub weight: i32,
 }
 
 #[derive(Debug, Insertable)]
@@ -69,6 +74,8 @@ pub struct Webhook {
     pub events: Option<Vec<Option<String>>>,
     pub status: Option<String>,
     pub company_id: i64,
+    pub secret: Option<String>,
+    pub consecutive_failures: i32,
 }
 
 #[derive(Debug, Insertable)]
@@ -79,6 +86,58 @@ pub struct NewWebhook {
     pub company_id: i64,
     pub events: Option<Vec<Option<String>>>,
     pub status: Option<String>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(belongs_to(Webhook))]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub company_id: i64,
+    pub event_id: String,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempt: i32,
+    pub status: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: Option<i32>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub locked_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct NewWebhookDelivery {
+    pub webhook_id: i64,
+    pub company_id: i64,
+    pub event_id: String,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempt: i32,
+    pub status: String,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Outcome of one delivery attempt
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDeliveryResult {
+    pub status: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: Option<i32>,
+    pub completed_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
@@ -93,6 +152,14 @@ pub struct EmailLog {
     pub status: Option<String>,
     pub created_at: DateTime<Utc>,
     pub company_id: i64,
+    pub attempts: i32,
+    pub last_smtp_code: Option<i32>,
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub message_id: Option<String>,
+    pub recipient_type: String,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub smtp_profile_id: Option<i64>,
 }
 
 #[derive(Debug, Insertable)]
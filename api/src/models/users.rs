@@ -1,5 +1,5 @@
-use crate::schema::{api_keys, companies, industries, team_members, users, smtpprofiles, emaillog, templates};
-use chrono::{DateTime, Utc};
+use crate::schema::{api_keys, companies, credit_ledger, industries, team_members, users, smtpprofiles, emaillog, email_events, email_queue, idempotency_keys, payments, smtp_routing_rules, templates, usage_daily, usage_snapshots};
+use chrono::{DateTime, NaiveDate, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -52,7 +52,7 @@ pub struct NewIndustry {
     pub description: Option<String>,
 }
 
-#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[derive(Debug, Clone, Queryable, Identifiable, Associations, Serialize, Deserialize)]
 #[diesel(table_name = companies)]
 #[diesel(belongs_to(User, foreign_key = owner_id))]
 #[diesel(belongs_to(Industry, foreign_key = industry_id))]
@@ -69,6 +69,9 @@ pub struct Company {
     pub pricing_tier: String,
     pub api_credits: i64,
     pub credits_reset_date: DateTime<Utc>,
+    pub max_message_bytes: Option<i64>,
+    // Purchased credits; spent after `api_credits` and never cleared by a reset
+    pub topup_credits: i64,
 }
 
 #[derive(Debug, Insertable)]
@@ -149,6 +152,11 @@ pub struct SmtpProfile {
     pub is_default: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub tls_mode: String,
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    pub priority: i32,
+    pub weight: i32,
 }
 
 #[derive(Debug, Insertable)]
@@ -162,6 +170,49 @@ pub struct NewSmtpProfile {
     pub is_default: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub tls_mode: String,
+    pub ca_certificate: Option<String>,
+    pub tls_hostname: Option<String>,
+    pub priority: i32,
+    pub weight: i32,
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = smtp_routing_rules)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct SmtpRoutingRule {
+    pub id: i64,
+    pub company_id: i64,
+    pub smtp_profile_id: i64,
+    pub name: String,
+    pub priority: i32,
+    pub recipient_domain: Option<String>,
+    pub from_address: Option<String>,
+    pub template_id: Option<i64>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = smtp_routing_rules)]
+pub struct NewSmtpRoutingRule {
+    pub company_id: i64,
+    pub smtp_profile_id: i64,
+    pub name: String,
+    pub priority: i32,
+    pub recipient_domain: Option<String>,
+    pub from_address: Option<String>,
+    pub template_id: Option<i64>,
+    pub tag: Option<String>,
+    pub category: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
@@ -176,6 +227,14 @@ pub struct EmailLog {
     pub status: Option<String>,
     pub created_at: DateTime<Utc>,
     pub company_id: i64,
+    pub attempts: i32,
+    pub last_smtp_code: Option<i32>,
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub message_id: Option<String>,
+    pub recipient_type: String,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub smtp_profile_id: Option<i64>,
 }
 
 #[derive(Debug, Insertable)]
@@ -188,6 +247,21 @@ pub struct NewEmailLog {
     pub status: Option<String>,
     pub created_at: DateTime<Utc>,
     pub company_id: i64,
+    pub message_id: Option<String>,
+    pub recipient_type: String,
+    pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = emaillog)]
+#[diesel(treat_none_as_null = true)]
+pub struct EmailLogAttempt {
+    pub status: Option<String>,
+    pub attempts: i32,
+    pub last_smtp_code: Option<i32>,
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub smtp_profile_id: Option<i64>,
 }
 
 #[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
@@ -215,3 +289,211 @@ pub struct NewTemplate {
     pub date_created: DateTime<Utc>,
     pub date_updated: DateTime<Utc>,
 }
+
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = email_queue)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct EmailJob {
+    pub id: i64,
+    pub message_id: String,
+    pub company_id: i64,
+    pub smtp_profile_id: Option<i64>,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub available_at: DateTime<Utc>,
+    pub locked_at: Option<DateTime<Utc>>,
+    pub locked_by: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub api_key_id: Option<i64>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = email_queue)]
+pub struct NewEmailJob {
+    pub message_id: String,
+    pub company_id: i64,
+    pub smtp_profile_id: Option<i64>,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub available_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub api_key_id: Option<i64>,
+}
+
+// Timeline entry for a message: queued, deferred, sent, failed, bounced
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = email_events)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct EmailEvent {
+    pub id: i64,
+    pub message_id: String,
+    pub company_id: i64,
+    pub event: String,
+    pub smtp_code: Option<i32>,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = email_events)]
+pub struct NewEmailEvent {
+    pub message_id: String,
+    pub company_id: i64,
+    pub event: String,
+    pub smtp_code: Option<i32>,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = credit_ledger)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct CreditLedgerEntry {
+    pub id: i64,
+    pub company_id: i64,
+    pub message_id: Option<String>,
+    pub amount: i64,
+    pub entry_type: String,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub topup_amount: i64,
+}
+
+// Debits carry a negative amount, refunds a positive one
+#[derive(Debug, Insertable)]
+#[diesel(table_name = credit_ledger)]
+pub struct NewCreditLedgerEntry {
+    pub company_id: i64,
+    pub message_id: Option<String>,
+    pub amount: i64,
+    // Part of `amount` taken from or returned to top-up credits rather than the monthly allowance
+    pub topup_amount: i64,
+    pub entry_type: String,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// One row per company, day, API key and SMTP profile; sends add to it
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = usage_daily)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct UsageDaily {
+    pub id: i64,
+    pub company_id: i64,
+    pub day: NaiveDate,
+    pub api_key_id: Option<i64>,
+    pub smtp_profile_id: Option<i64>,
+    pub emails_sent: i64,
+    pub recipients: i64,
+    pub attachment_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = usage_daily)]
+pub struct NewUsageDaily {
+    pub company_id: i64,
+    pub day: NaiveDate,
+    pub api_key_id: Option<i64>,
+    pub smtp_profile_id: Option<i64>,
+    pub emails_sent: i64,
+    pub recipients: i64,
+    pub attachment_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+// A charge taken through the payment provider, recorded as Pending before the provider is called
+#[derive(Debug, Clone, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = payments)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct Payment {
+    pub id: i64,
+    pub company_id: i64,
+    pub kind: String,
+    pub description: String,
+    pub amount_cents: i64,
+    pub currency: String,
+    pub credits: i64,
+    pub provider: String,
+    pub provider_reference: Option<String>,
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = payments)]
+pub struct NewPayment {
+    pub company_id: i64,
+    pub kind: String,
+    pub description: String,
+    pub amount_cents: i64,
+    pub currency: String,
+    pub credits: i64,
+    pub provider: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Consumption for one closed credit period, written when the period's credits are reset
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = usage_snapshots)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct UsageSnapshot {
+    pub id: i64,
+    pub company_id: i64,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub pricing_tier: String,
+    pub credits_allocated: i64,
+    pub credits_used: i64,
+    pub credits_remaining: i64,
+    pub emails_sent: i64,
+    pub emails_failed: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = usage_snapshots)]
+pub struct NewUsageSnapshot {
+    pub company_id: i64,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub pricing_tier: String,
+    pub credits_allocated: i64,
+    pub credits_used: i64,
+    pub credits_remaining: i64,
+    pub emails_sent: i64,
+    pub emails_failed: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+// A response is only stored once the request has completed; until then the key is held in progress
+#[derive(Debug, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[diesel(table_name = idempotency_keys)]
+#[diesel(belongs_to(Company, foreign_key = company_id))]
+pub struct IdempotencyKey {
+    pub id: i64,
+    pub company_id: i64,
+    pub key: String,
+    pub request_hash: String,
+    pub message_id: Option<String>,
+    pub response: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = idempotency_keys)]
+pub struct NewIdempotencyKey {
+    pub company_id: i64,
+    pub key: String,
+    pub request_hash: String,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,6 +1,8 @@
-use crate::models::core::{SMTPProfile, NewSMTPProfile, Template, NewTemplate, Webhook, NewWebhook, EmailLog, NewEmailLog};
-use crate::schema::{smtpprofiles, templates, webhooks, emaillog};
+use crate::models::core::{SMTPProfile, NewSMTPProfile, Template, NewTemplate, Webhook, NewWebhook, WebhookDelivery, NewWebhookDelivery, WebhookDeliveryResult, EmailLog, NewEmailLog};
+use crate::schema::{smtpprofiles, templates, webhooks, webhook_deliveries, emaillog};
+use diesel::dsl::sql;
 use diesel::prelude::*;
+use diesel::sql_types::{Float8, Nullable};
 use super::DbPool;
 
 pub trait CoreRepository {
@@ -10,6 +12,20 @@ pub trait CoreRepository {
     fn get_templates_by_company(&self, company_id: i64) -> Result<Vec<Template>, diesel::result::Error>;
     fn create_webhook(&self, new_webhook: NewWebhook) -> Result<Webhook, diesel::result::Error>;
     fn get_webhooks_by_company(&self, company_id: i64) -> Result<Vec<Webhook>, diesel::result::Error>;
+    fn get_webhook_by_id(&self, webhook_id: i64, company_id: i64) -> Result<Webhook, diesel::result::Error>;
+    fn get_active_webhooks_by_company(&self, company_id: i64) -> Result<Vec<Webhook>, diesel::result::Error>;
+    fn update_webhook(&self, webhook_id: i64, webhook: &Webhook) -> Result<Webhook, diesel::result::Error>;
+    fn delete_webhook(&self, webhook_id: i64, company_id: i64) -> Result<usize, diesel::result::Error>;
+    fn update_webhook_secret(&self, webhook_id: i64, secret: &str) -> Result<Webhook, diesel::result::Error>;
+    fn record_webhook_success(&self, webhook_id: i64) -> Result<usize, diesel::result::Error>;
+    fn record_webhook_failure(&self, webhook_id: i64) -> Result<Webhook, diesel::result::Error>;
+    fn disable_webhook(&self, webhook_id: i64) -> Result<usize, diesel::result::Error>;
+    fn create_webhook_deliveries(&self, new_deliveries: Vec<NewWebhookDelivery>) -> Result<Vec<WebhookDelivery>, diesel::result::Error>;
+    fn claim_webhook_deliveries(&self, limit: i64) -> Result<Vec<WebhookDelivery>, diesel::result::Error>;
+    fn complete_webhook_delivery(&self, delivery_id: i64, result: &WebhookDeliveryResult) -> Result<WebhookDelivery, diesel::result::Error>;
+    fn release_stale_webhook_deliveries(&self, locked_before: chrono::DateTime<chrono::Utc>) -> Result<usize, diesel::result::Error>;
+    fn get_webhook_deliveries(&self, webhook_id: i64, status: Option<&str>, limit: i64) -> Result<Vec<WebhookDelivery>, diesel::result::Error>;
+    fn get_webhook_delivery_by_id(&self, delivery_id: i64, webhook_id: i64) -> Result<WebhookDelivery, diesel::result::Error>;
     fn create_email_log(&self, new_log: NewEmailLog) -> Result<EmailLog, diesel::result::Error>;
     fn get_email_logs_by_company(&self, company_id: i64) -> Result<Vec<EmailLog>, diesel::result::Error>;
 }
@@ -68,6 +84,175 @@ impl CoreRepository for CoreRepositoryImpl {
             .load(&mut conn)
     }
 
+    fn get_webhook_by_id(&self, webhook_id: i64, company_id: i64) -> Result<Webhook, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        webhooks::table
+            .filter(webhooks::id.eq(webhook_id))
+            .filter(webhooks::company_id.eq(company_id))
+            .first(&mut conn)
+    }
+
+    fn get_active_webhooks_by_company(&self, company_id: i64) -> Result<Vec<Webhook>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        webhooks::table
+            .filter(webhooks::company_id.eq(company_id))
+            .filter(webhooks::is_active.eq(true))
+            .load(&mut conn)
+    }
+
+    fn update_webhook(&self, webhook_id: i64, webhook: &Webhook) -> Result<Webhook, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(webhooks::table.find(webhook_id))
+            .set((
+                webhooks::name.eq(&webhook.name),
+                webhooks::url.eq(&webhook.url),
+                webhooks::events.eq(&webhook.events),
+                webhooks::is_active.eq(webhook.is_active),
+                webhooks::status.eq(&webhook.status),
+                webhooks::consecutive_failures.eq(webhook.consecutive_failures),
+                webhooks::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result(&mut conn)
+    }
+
+    fn delete_webhook(&self, webhook_id: i64, company_id: i64) -> Result<usize, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::delete(
+            webhooks::table
+                .filter(webhooks::id.eq(webhook_id))
+                .filter(webhooks::company_id.eq(company_id)),
+        )
+        .execute(&mut conn)
+    }
+
+    fn update_webhook_secret(&self, webhook_id: i64, secret: &str) -> Result<Webhook, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(webhooks::table.find(webhook_id))
+            .set((
+                webhooks::secret.eq(secret),
+                webhooks::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result(&mut conn)
+    }
+
+    fn record_webhook_success(&self, webhook_id: i64) -> Result<usize, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        // success_rate is a moving average (percent) weighting the latest attempt at 10%
+        diesel::update(webhooks::table.find(webhook_id))
+            .set((
+                webhooks::last_delivered.eq(chrono::Utc::now()),
+                webhooks::success_rate.eq(sql::<Nullable<Float8>>("COALESCE(success_rate, 100) * 0.9 + 10")),
+                webhooks::status.eq("Active"),
+                webhooks::consecutive_failures.eq(0),
+            ))
+            .execute(&mut conn)
+    }
+
+    fn record_webhook_failure(&self, webhook_id: i64) -> Result<Webhook, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(webhooks::table.find(webhook_id))
+            .set((
+                webhooks::success_rate.eq(sql::<Nullable<Float8>>("COALESCE(success_rate, 0) * 0.9")),
+                webhooks::status.eq("Failing"),
+                webhooks::consecutive_failures.eq(webhooks::consecutive_failures + 1),
+            ))
+            .get_result(&mut conn)
+    }
+
+    fn disable_webhook(&self, webhook_id: i64) -> Result<usize, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(webhooks::table.find(webhook_id))
+            .set((
+                webhooks::is_active.eq(false),
+                webhooks::status.eq("Disabled"),
+                webhooks::updated_at.eq(chrono::Utc::now()),
+            ))
+            .execute(&mut conn)
+    }
+
+    fn create_webhook_deliveries(&self, new_deliveries: Vec<NewWebhookDelivery>) -> Result<Vec<WebhookDelivery>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::insert_into(webhook_deliveries::table)
+            .values(&new_deliveries)
+            .get_results(&mut conn)
+    }
+
+    fn claim_webhook_deliveries(&self, limit: i64) -> Result<Vec<WebhookDelivery>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let now = chrono::Utc::now();
+
+        // Deliveries for disabled webhooks wait until the webhook is switched back on
+        conn.transaction(|conn| {
+            let active_webhooks = webhooks::table
+                .filter(webhooks::is_active.eq(true))
+                .select(webhooks::id);
+            let delivery_ids: Vec<i64> = webhook_deliveries::table
+                .filter(webhook_deliveries::status.eq("Pending"))
+                .filter(webhook_deliveries::next_attempt_at.le(now))
+                .filter(webhook_deliveries::webhook_id.eq_any(active_webhooks))
+                .order(webhook_deliveries::next_attempt_at.asc())
+                .limit(limit)
+                .select(webhook_deliveries::id)
+                .for_update()
+                .skip_locked()
+                .load(conn)?;
+
+            if delivery_ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&delivery_ids)))
+                .set((
+                    webhook_deliveries::status.eq("Processing"),
+                    webhook_deliveries::locked_at.eq(Some(now)),
+                ))
+                .get_results(conn)
+        })
+    }
+
+    fn complete_webhook_delivery(&self, delivery_id: i64, result: &WebhookDeliveryResult) -> Result<WebhookDelivery, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(webhook_deliveries::table.find(delivery_id))
+            .set((result, webhook_deliveries::locked_at.eq(None::<chrono::DateTime<chrono::Utc>>)))
+            .get_result(&mut conn)
+    }
+
+    fn release_stale_webhook_deliveries(&self, locked_before: chrono::DateTime<chrono::Utc>) -> Result<usize, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(
+            webhook_deliveries::table
+                .filter(webhook_deliveries::status.eq("Processing"))
+                .filter(webhook_deliveries::locked_at.lt(locked_before)),
+        )
+        .set((
+            webhook_deliveries::status.eq("Pending"),
+            webhook_deliveries::locked_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+        ))
+        .execute(&mut conn)
+    }
+
+    fn get_webhook_deliveries(&self, webhook_id: i64, status: Option<&str>, limit: i64) -> Result<Vec<WebhookDelivery>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let mut query = webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .into_boxed();
+        if let Some(status) = status {
+            query = query.filter(webhook_deliveries::status.eq(status));
+        }
+        query
+            .order((webhook_deliveries::created_at.desc(), webhook_deliveries::id.desc()))
+            .limit(limit)
+            .load(&mut conn)
+    }
+
+    fn get_webhook_delivery_by_id(&self, delivery_id: i64, webhook_id: i64) -> Result<WebhookDelivery, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        webhook_deliveries::table
+            .filter(webhook_deliveries::id.eq(delivery_id))
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .first(&mut conn)
+    }
+
     fn create_email_log(&self, new_log: NewEmailLog) -> Result<EmailLog, diesel::result::Error> {
         let mut conn = self.pool.get().expect("Failed to get DB connection");
         diesel::insert_into(emaillog::table)
@@ -1,10 +1,32 @@
 use super::DbPool;
 use crate::models::users::{
-    ApiKey, Company, EmailLog, Industry, NewApiKey, NewCompany, NewEmailLog, NewIndustry,
-    NewSmtpProfile, NewTeamMember, NewUser, SmtpProfile, TeamMember, User, Template, NewTemplate,
+    ApiKey, Company, EmailEvent, EmailJob, EmailLog, EmailLogAttempt, IdempotencyKey, Industry, NewApiKey, NewCompany, NewCreditLedgerEntry, NewEmailEvent, NewEmailJob, NewEmailLog,
+    NewIdempotencyKey, NewIndustry, NewPayment, NewSmtpProfile, NewSmtpRoutingRule, Payment, NewTeamMember, NewUser, SmtpProfile, SmtpRoutingRule, TeamMember, User, Template,
+    NewTemplate, NewUsageDaily, NewUsageSnapshot, UsageDaily, UsageSnapshot,
 };
-use crate::schema::{api_keys, companies, emaillog, industries, smtpprofiles, team_members, users, templates};
+use crate::schema::{api_keys, companies, credit_ledger, email_events, email_queue, emaillog, idempotency_keys, industries, payments, smtp_routing_rules, smtpprofiles, team_members, users, templates, usage_daily, usage_snapshots};
+use chrono::{DateTime, NaiveDate, Utc};
 use diesel::prelude::*;
+use diesel::upsert::excluded;
+
+// Filters for paging through a company's email logs, newest first
+#[derive(Debug, Default)]
+pub struct EmailLogFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub status: Option<String>,
+    pub search: Option<String>,
+    // Position of the last row of the previous page
+    pub before: Option<(DateTime<Utc>, i64)>,
+    pub limit: i64,
+}
+
+// How a plan change moves the monthly balance: upgrades grant prorated credits, downgrades cap the
+// balance at the new tier's allowance
+#[derive(Debug, Clone, Copy)]
+pub enum PlanCreditChange {
+    Grant(i64),
+    Cap(i64),
+}
 
 pub trait UserRepository {
     fn create_user(&self, new_user: NewUser) -> Result<User, diesel::result::Error>;
@@ -62,7 +84,41 @@ pub trait UserRepository {
         company_id: i64,
         tier: &str,
     ) -> Result<Company, diesel::result::Error>;
-    fn deduct_api_credit(&self, company_id: i64) -> Result<Company, diesel::result::Error>;
+    fn get_companies_due_for_reset(&self, limit: i64) -> Result<Vec<Company>, diesel::result::Error>;
+    fn record_usage(&self, usage: NewUsageDaily) -> Result<UsageDaily, diesel::result::Error>;
+    fn get_usage_daily(
+        &self,
+        company_id: i64,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<UsageDaily>, diesel::result::Error>;
+    fn get_usage_snapshots(
+        &self,
+        company_id: i64,
+        limit: i64,
+    ) -> Result<Vec<UsageSnapshot>, diesel::result::Error>;
+    fn change_pricing_tier(
+        &self,
+        company_id: i64,
+        from_tier: &str,
+        to_tier: &str,
+        credit_change: PlanCreditChange,
+        payment: Option<(i64, &str)>,
+    ) -> Result<Company, diesel::result::Error>;
+    fn add_topup_credits(
+        &self,
+        company_id: i64,
+        payment_id: i64,
+        provider_reference: &str,
+    ) -> Result<Company, diesel::result::Error>;
+    fn create_payment(&self, new_payment: NewPayment) -> Result<Payment, diesel::result::Error>;
+    fn update_payment_status(
+        &self,
+        payment_id: i64,
+        status: &str,
+        provider_reference: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<Payment, diesel::result::Error>;
 
     fn create_smtp_profile(
         &self,
@@ -90,6 +146,30 @@ pub trait UserRepository {
         company_id: i64,
     ) -> Result<SmtpProfile, diesel::result::Error>;
 
+    fn get_routing_rules_by_company(
+        &self,
+        company_id: i64,
+    ) -> Result<Vec<SmtpRoutingRule>, diesel::result::Error>;
+    fn get_routing_rule_by_id(
+        &self,
+        rule_id: i64,
+        company_id: i64,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error>;
+    fn create_routing_rule(
+        &self,
+        new_rule: NewSmtpRoutingRule,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error>;
+    fn update_routing_rule(
+        &self,
+        rule_id: i64,
+        rule: &SmtpRoutingRule,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error>;
+    fn delete_routing_rule(
+        &self,
+        rule_id: i64,
+        company_id: i64,
+    ) -> Result<usize, diesel::result::Error>;
+
     fn create_email_log(&self, new_log: NewEmailLog) -> Result<EmailLog, diesel::result::Error>;
     fn get_default_smtp_profile(
         &self,
@@ -108,6 +188,11 @@ pub trait UserRepository {
         &self,
         company_id: i64,
     ) -> Result<(i64, i64, i64, i64), diesel::result::Error>;
+    fn search_email_logs(
+        &self,
+        company_id: i64,
+        filter: &EmailLogFilter,
+    ) -> Result<Vec<EmailLog>, diesel::result::Error>;
     
     fn create_template(&self, new_template: NewTemplate) -> Result<Template, diesel::result::Error>;
     fn get_templates_by_company(&self, company_id: i64) -> Result<Vec<Template>, diesel::result::Error>;
@@ -115,6 +200,104 @@ pub trait UserRepository {
     fn update_template(&self, template_id: i64, template: &Template) -> Result<Template, diesel::result::Error>;
     fn delete_template(&self, template_id: i64, company_id: i64) -> Result<usize, diesel::result::Error>;
     fn update_company(&self, company_id: i64, company: &Company) -> Result<Company, diesel::result::Error>;
+
+    fn enqueue_email(
+        &self,
+        new_logs: Vec<NewEmailLog>,
+        new_job: NewEmailJob,
+        credits: i64,
+    ) -> Result<(Vec<EmailLog>, EmailJob), diesel::result::Error>;
+    fn enqueue_email_batch(
+        &self,
+        company_id: i64,
+        emails: Vec<(Vec<NewEmailLog>, NewEmailJob, i64)>,
+    ) -> Result<Vec<EmailJob>, diesel::result::Error>;
+    fn refund_email_credits(
+        &self,
+        company_id: i64,
+        message_id: &str,
+        reason: &str,
+    ) -> Result<i64, diesel::result::Error>;
+    fn requeue_email_log(
+        &self,
+        log_id: i64,
+        new_job: NewEmailJob,
+    ) -> Result<EmailJob, diesel::result::Error>;
+    fn claim_email_job(&self, worker_id: &str) -> Result<Option<EmailJob>, diesel::result::Error>;
+    fn reserve_idempotency_key(
+        &self,
+        new_key: NewIdempotencyKey,
+        expired_before: DateTime<Utc>,
+        lease_expired_before: DateTime<Utc>,
+    ) -> Result<(IdempotencyKey, bool), diesel::result::Error>;
+    fn get_completed_idempotency_key(
+        &self,
+        company_id: i64,
+        key: &str,
+        expired_before: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyKey>, diesel::result::Error>;
+    fn complete_idempotency_key(
+        &self,
+        key_id: i64,
+        message_id: &str,
+        response: serde_json::Value,
+    ) -> Result<IdempotencyKey, diesel::result::Error>;
+    fn release_idempotency_key(&self, key_id: i64) -> Result<usize, diesel::result::Error>;
+    fn purge_expired_idempotency_keys(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<usize, diesel::result::Error>;
+    fn release_scheduled_email_jobs(&self, limit: i64) -> Result<Vec<EmailJob>, diesel::result::Error>;
+    fn cancel_scheduled_email(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<i64, diesel::result::Error>;
+    fn reschedule_email(
+        &self,
+        message_id: &str,
+        company_id: i64,
+        send_at: DateTime<Utc>,
+    ) -> Result<EmailJob, diesel::result::Error>;
+    fn complete_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+    ) -> Result<EmailJob, diesel::result::Error>;
+    fn fail_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+        error: &str,
+    ) -> Result<EmailJob, diesel::result::Error>;
+    fn retry_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+        available_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<EmailJob, diesel::result::Error>;
+    fn record_email_log_attempt(
+        &self,
+        message_id: &str,
+        attempt: &EmailLogAttempt,
+    ) -> Result<usize, diesel::result::Error>;
+    fn release_stale_email_jobs(
+        &self,
+        locked_before: DateTime<Utc>,
+    ) -> Result<usize, diesel::result::Error>;
+    fn get_orphaned_queued_email_logs(&self) -> Result<Vec<EmailLog>, diesel::result::Error>;
+    fn get_email_logs_by_message_id(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<Vec<EmailLog>, diesel::result::Error>;
+    fn create_email_event(&self, new_event: NewEmailEvent) -> Result<EmailEvent, diesel::result::Error>;
+    fn get_email_events_by_message_id(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<Vec<EmailEvent>, diesel::result::Error>;
 }
 
 #[derive(Clone)]
@@ -126,6 +309,160 @@ impl UserRepositoryImpl {
     pub fn new(pool: DbPool) -> Self {
         Self { pool }
     }
+
+    fn debit_entry(company_id: i64, message_id: &str, credits: i64) -> NewCreditLedgerEntry {
+        NewCreditLedgerEntry {
+            company_id,
+            message_id: Some(message_id.to_string()),
+            amount: -credits,
+            // Set by `debit_credits` once the split between the balances is known
+            topup_amount: 0,
+            entry_type: "Debit".to_string(),
+            reason: Some("Email send".to_string()),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    // The company row is locked while the debits are split between the balances, so concurrent
+    // sends can't overdraw; when the balance doesn't cover the debits it returns NotFound. The
+    // monthly allowance is spent before top-up credits, which outlive the reset, and each entry
+    // records how much of it came out of top-ups so a refund can put it back in the same place
+    fn debit_credits(
+        conn: &mut PgConnection,
+        company_id: i64,
+        mut entries: Vec<NewCreditLedgerEntry>,
+    ) -> Result<Company, diesel::result::Error> {
+        let company = companies::table
+            .find(company_id)
+            .for_update()
+            .first::<Company>(conn)?;
+
+        let credits: i64 = entries.iter().map(|entry| -entry.amount).sum();
+        if company.api_credits + company.topup_credits < credits {
+            return Err(diesel::result::Error::NotFound);
+        }
+
+        let mut api_available = company.api_credits.max(0);
+        let mut from_topup = 0;
+        for entry in &mut entries {
+            let charge = -entry.amount;
+            let from_api = charge.min(api_available);
+            api_available -= from_api;
+            from_topup += charge - from_api;
+            entry.topup_amount = -(charge - from_api);
+        }
+
+        let company = diesel::update(companies::table.find(company_id))
+            .set((
+                companies::api_credits.eq(companies::api_credits - (credits - from_topup)),
+                companies::topup_credits.eq(companies::topup_credits - from_topup),
+            ))
+            .get_result::<Company>(conn)?;
+
+        diesel::insert_into(credit_ledger::table)
+            .values(&entries)
+            .execute(conn)?;
+
+        Ok(company)
+    }
+
+    // Gives back whatever the message was charged and has not already had refunded, to the
+    // balance it was taken from
+    fn refund_message_credits(
+        conn: &mut PgConnection,
+        company_id: i64,
+        message_id: &str,
+        reason: &str,
+    ) -> Result<i64, diesel::result::Error> {
+        // Locking the company row serialises refunds, so a message is never refunded twice
+        companies::table
+            .find(company_id)
+            .select(companies::id)
+            .for_update()
+            .first::<i64>(conn)?;
+
+        let amounts: Vec<(i64, i64)> = credit_ledger::table
+            .filter(credit_ledger::company_id.eq(company_id))
+            .filter(credit_ledger::message_id.eq(message_id))
+            .select((credit_ledger::amount, credit_ledger::topup_amount))
+            .load(conn)?;
+        let outstanding = -amounts.iter().map(|(amount, _)| amount).sum::<i64>();
+        if outstanding <= 0 {
+            return Ok(0);
+        }
+        let outstanding_topup = (-amounts.iter().map(|(_, topup)| topup).sum::<i64>())
+            .clamp(0, outstanding);
+
+        diesel::insert_into(credit_ledger::table)
+            .values(&NewCreditLedgerEntry {
+                company_id,
+                message_id: Some(message_id.to_string()),
+                amount: outstanding,
+                topup_amount: outstanding_topup,
+                entry_type: "Refund".to_string(),
+                reason: Some(reason.to_string()),
+                created_at: chrono::Utc::now(),
+            })
+            .execute(conn)?;
+
+        diesel::update(companies::table.filter(companies::id.eq(company_id)))
+            .set((
+                companies::api_credits.eq(companies::api_credits + (outstanding - outstanding_topup)),
+                companies::topup_credits.eq(companies::topup_credits + outstanding_topup),
+            ))
+            .execute(conn)?;
+
+        Ok(outstanding)
+    }
+
+    // One row per (company, day, key, profile) bucket; later sends add to it
+    fn add_usage(
+        conn: &mut PgConnection,
+        usage: &NewUsageDaily,
+    ) -> Result<UsageDaily, diesel::result::Error> {
+        diesel::insert_into(usage_daily::table)
+            .values(usage)
+            .on_conflict((
+                usage_daily::company_id,
+                usage_daily::day,
+                usage_daily::api_key_id,
+                usage_daily::smtp_profile_id,
+            ))
+            .do_update()
+            .set((
+                usage_daily::emails_sent.eq(usage_daily::emails_sent + excluded(usage_daily::emails_sent)),
+                usage_daily::recipients.eq(usage_daily::recipients + excluded(usage_daily::recipients)),
+                usage_daily::attachment_bytes
+                    .eq(usage_daily::attachment_bytes + excluded(usage_daily::attachment_bytes)),
+                usage_daily::updated_at.eq(excluded(usage_daily::updated_at)),
+            ))
+            .get_result::<UsageDaily>(conn)
+    }
+
+    // The bucket key treats NULLs as equal, so a row can't just have its key or profile cleared
+    // when a row without one already exists for that day; it is folded into that row instead
+    fn detach_usage(
+        conn: &mut PgConnection,
+        rows: Vec<UsageDaily>,
+        detach: fn(&mut NewUsageDaily),
+    ) -> Result<(), diesel::result::Error> {
+        for row in rows {
+            diesel::delete(usage_daily::table.find(row.id)).execute(conn)?;
+            let mut usage = NewUsageDaily {
+                company_id: row.company_id,
+                day: row.day,
+                api_key_id: row.api_key_id,
+                smtp_profile_id: row.smtp_profile_id,
+                emails_sent: row.emails_sent,
+                recipients: row.recipients,
+                attachment_bytes: row.attachment_bytes,
+                updated_at: chrono::Utc::now(),
+            };
+            detach(&mut usage);
+            Self::add_usage(conn, &usage)?;
+        }
+        Ok(())
+    }
 }
 
 impl UserRepository for UserRepositoryImpl {
@@ -400,6 +737,7 @@ impl UserRepository for UserRepositoryImpl {
         let pricing_tier = PricingTier::from_str(tier);
         let new_credits = pricing_tier.monthly_credits();
         let next_reset = get_next_reset_date();
+        let now = chrono::Utc::now();
 
         log::debug!(
             "Resetting credits for company ID: {} to {} ({})",
@@ -409,21 +747,270 @@ impl UserRepository for UserRepositoryImpl {
         );
         let mut conn = self.pool.get().expect("Failed to get DB connection");
 
-        diesel::update(companies::table.filter(companies::id.eq(company_id)))
+        // Only a reset that is still due goes through, so concurrent callers can't reset twice;
+        // the losers get NotFound
+        conn.transaction(|conn| {
+            let company = companies::table
+                .filter(companies::id.eq(company_id))
+                .filter(companies::credits_reset_date.le(now))
+                .for_update()
+                .first::<Company>(conn)?;
+
+            // The closing period runs from the previous reset (or a month before this one was due)
+            // up to now, so consecutive snapshots never overlap or leave a gap
+            let period_start = usage_snapshots::table
+                .filter(usage_snapshots::company_id.eq(company_id))
+                .select(diesel::dsl::max(usage_snapshots::period_end))
+                .first::<Option<DateTime<Utc>>>(conn)?
+                .unwrap_or_else(|| {
+                    company
+                        .credits_reset_date
+                        .checked_sub_months(chrono::Months::new(1))
+                        .unwrap_or(company.credits_reset_date)
+                });
+
+            let credits_used: i64 = credit_ledger::table
+                .filter(credit_ledger::company_id.eq(company_id))
+                .filter(credit_ledger::created_at.ge(period_start))
+                .filter(credit_ledger::created_at.lt(now))
+                .filter(credit_ledger::entry_type.eq_any(["Debit", "Refund"]))
+                .select(diesel::dsl::sql::<diesel::sql_types::BigInt>("COALESCE(-SUM(amount), 0)::bigint"))
+                .first(conn)?;
+            let count_emails = |conn: &mut PgConnection, status: &str| {
+                emaillog::table
+                    .filter(emaillog::company_id.eq(company_id))
+                    .filter(emaillog::status.eq(status))
+                    .filter(emaillog::created_at.ge(period_start))
+                    .filter(emaillog::created_at.lt(now))
+                    .count()
+                    .get_result::<i64>(conn)
+            };
+            let emails_sent = count_emails(conn, "Success")?;
+            let emails_failed = count_emails(conn, "Failed")?;
+
+            diesel::insert_into(usage_snapshots::table)
+                .values(&NewUsageSnapshot {
+                    company_id,
+                    period_start,
+                    period_end: now,
+                    pricing_tier: company.pricing_tier.clone(),
+                    credits_allocated: PricingTier::from_str(&company.pricing_tier).monthly_credits(),
+                    credits_used,
+                    credits_remaining: company.api_credits,
+                    emails_sent,
+                    emails_failed,
+                    created_at: now,
+                })
+                .execute(conn)?;
+
+            diesel::update(companies::table.find(company_id))
+                .set((
+                    companies::api_credits.eq(new_credits),
+                    companies::credits_reset_date.eq(next_reset),
+                ))
+                .get_result::<Company>(conn)
+        })
+    }
+
+    fn get_companies_due_for_reset(&self, limit: i64) -> Result<Vec<Company>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        companies::table
+            .filter(companies::credits_reset_date.le(chrono::Utc::now()))
+            .filter(companies::pricing_tier.ne("enterprise"))
+            .order(companies::credits_reset_date.asc())
+            .limit(limit)
+            .load::<Company>(&mut conn)
+    }
+
+    fn record_usage(&self, usage: NewUsageDaily) -> Result<UsageDaily, diesel::result::Error> {
+        log::debug!(
+            "Recording usage for company {} on {}: {} emails",
+            usage.company_id,
+            usage.day,
+            usage.emails_sent
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        Self::add_usage(&mut conn, &usage)
+    }
+
+    fn get_usage_daily(
+        &self,
+        company_id: i64,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<UsageDaily>, diesel::result::Error> {
+        log::debug!("Fetching usage for company {} from {} to {}", company_id, start, end);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        usage_daily::table
+            .filter(usage_daily::company_id.eq(company_id))
+            .filter(usage_daily::day.ge(start))
+            .filter(usage_daily::day.le(end))
+            .order((usage_daily::day.asc(), usage_daily::id.asc()))
+            .load::<UsageDaily>(&mut conn)
+    }
+
+    fn get_usage_snapshots(
+        &self,
+        company_id: i64,
+        limit: i64,
+    ) -> Result<Vec<UsageSnapshot>, diesel::result::Error> {
+        log::debug!("Fetching usage snapshots for company: {}", company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        usage_snapshots::table
+            .filter(usage_snapshots::company_id.eq(company_id))
+            .order(usage_snapshots::period_end.desc())
+            .limit(limit)
+            .load::<UsageSnapshot>(&mut conn)
+    }
+
+    fn change_pricing_tier(
+        &self,
+        company_id: i64,
+        from_tier: &str,
+        to_tier: &str,
+        credit_change: PlanCreditChange,
+        payment: Option<(i64, &str)>,
+    ) -> Result<Company, diesel::result::Error> {
+        log::debug!(
+            "Changing pricing tier for company ID: {} from {} to {}",
+            company_id,
+            from_tier,
+            to_tier
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let now = chrono::Utc::now();
+
+        // The change only applies to the tier it was priced against; if another change got in
+        // first this matches no row and returns NotFound
+        conn.transaction(|conn| {
+            let company = companies::table
+                .filter(companies::id.eq(company_id))
+                .filter(companies::pricing_tier.eq(from_tier))
+                .for_update()
+                .first::<Company>(conn)?;
+
+            let api_credits = match credit_change {
+                PlanCreditChange::Grant(credits) => company.api_credits + credits,
+                PlanCreditChange::Cap(credits) => company.api_credits.min(credits),
+            };
+            let amount = api_credits - company.api_credits;
+            if amount != 0 {
+                diesel::insert_into(credit_ledger::table)
+                    .values(&NewCreditLedgerEntry {
+                        company_id,
+                        message_id: None,
+                        amount,
+                        topup_amount: 0,
+                        entry_type: "Proration".to_string(),
+                        reason: Some(format!("Plan change from {} to {}", from_tier, to_tier)),
+                        created_at: now,
+                    })
+                    .execute(conn)?;
+            }
+
+            if let Some((payment_id, provider_reference)) = payment {
+                diesel::update(payments::table.find(payment_id))
+                    .set((
+                        payments::status.eq("Succeeded"),
+                        payments::provider_reference.eq(provider_reference),
+                        payments::credits.eq(amount.max(0)),
+                        payments::updated_at.eq(now),
+                    ))
+                    .execute(conn)?;
+            }
+
+            diesel::update(companies::table.find(company_id))
+                .set((
+                    companies::pricing_tier.eq(to_tier),
+                    companies::api_credits.eq(api_credits),
+                ))
+                .get_result::<Company>(conn)
+        })
+    }
+
+    fn add_topup_credits(
+        &self,
+        company_id: i64,
+        payment_id: i64,
+        provider_reference: &str,
+    ) -> Result<Company, diesel::result::Error> {
+        log::debug!(
+            "Adding top-up credits from payment ID: {} for company ID: {}",
+            payment_id,
+            company_id
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let now = chrono::Utc::now();
+
+        // Only a pending payment is credited, so the same payment can't top up twice
+        conn.transaction(|conn| {
+            let payment = diesel::update(
+                payments::table
+                    .filter(payments::id.eq(payment_id))
+                    .filter(payments::company_id.eq(company_id))
+                    .filter(payments::status.eq("Pending")),
+            )
             .set((
-                companies::api_credits.eq(new_credits),
-                companies::credits_reset_date.eq(next_reset),
+                payments::status.eq("Succeeded"),
+                payments::provider_reference.eq(provider_reference),
+                payments::updated_at.eq(now),
             ))
-            .get_result::<Company>(&mut conn)
+            .get_result::<Payment>(conn)?;
+
+            diesel::insert_into(credit_ledger::table)
+                .values(&NewCreditLedgerEntry {
+                    company_id,
+                    message_id: None,
+                    amount: payment.credits,
+                    topup_amount: payment.credits,
+                    entry_type: "TopUp".to_string(),
+                    reason: Some(payment.description.clone()),
+                    created_at: now,
+                })
+                .execute(conn)?;
+
+            diesel::update(companies::table.find(company_id))
+                .set(companies::topup_credits.eq(companies::topup_credits + payment.credits))
+                .get_result::<Company>(conn)
+        })
     }
 
-    fn deduct_api_credit(&self, company_id: i64) -> Result<Company, diesel::result::Error> {
-        log::debug!("Deducting 1 API credit for company ID: {}", company_id);
+    fn create_payment(&self, new_payment: NewPayment) -> Result<Payment, diesel::result::Error> {
+        log::debug!(
+            "Creating {} payment of {} {} for company ID: {}",
+            new_payment.kind,
+            new_payment.amount_cents,
+            new_payment.currency,
+            new_payment.company_id
+        );
         let mut conn = self.pool.get().expect("Failed to get DB connection");
 
-        diesel::update(companies::table.filter(companies::id.eq(company_id)))
-            .set(companies::api_credits.eq(companies::api_credits - 1))
-            .get_result::<Company>(&mut conn)
+        diesel::insert_into(payments::table)
+            .values(&new_payment)
+            .get_result::<Payment>(&mut conn)
+    }
+
+    fn update_payment_status(
+        &self,
+        payment_id: i64,
+        status: &str,
+        provider_reference: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<Payment, diesel::result::Error> {
+        log::debug!("Marking payment ID: {} as {}", payment_id, status);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        diesel::update(payments::table.find(payment_id))
+            .set((
+                payments::status.eq(status),
+                payments::provider_reference.eq(provider_reference),
+                payments::error.eq(error),
+                payments::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<Payment>(&mut conn)
     }
 
     fn delete_api_key(
@@ -438,12 +1025,32 @@ impl UserRepository for UserRepositoryImpl {
         );
         let mut conn = self.pool.get().expect("Failed to get DB connection");
 
-        diesel::delete(
-            api_keys::table
+        // Queued jobs keep their place in the queue, they just stop pointing at the key. Routing
+        // rules scoped to the key are removed with it
+        conn.transaction(|conn| {
+            let key_id: Option<i64> = api_keys::table
                 .filter(api_keys::id.eq(api_key_id))
-                .filter(api_keys::company_id.eq(company_id)),
-        )
-        .execute(&mut conn)
+                .filter(api_keys::company_id.eq(company_id))
+                .select(api_keys::id)
+                .for_update()
+                .first(conn)
+                .optional()?;
+            let Some(key_id) = key_id else {
+                return Ok(0);
+            };
+
+            diesel::update(email_queue::table.filter(email_queue::api_key_id.eq(key_id)))
+                .set(email_queue::api_key_id.eq(None::<i64>))
+                .execute(conn)?;
+            let usage = usage_daily::table
+                .filter(usage_daily::api_key_id.eq(key_id))
+                .load::<UsageDaily>(conn)?;
+            Self::detach_usage(conn, usage, |usage| usage.api_key_id = None)?;
+            diesel::delete(smtp_routing_rules::table.filter(smtp_routing_rules::api_key_id.eq(key_id)))
+                .execute(conn)?;
+
+            diesel::delete(api_keys::table.find(key_id)).execute(conn)
+        })
     }
 
     fn get_user_role_in_company(
@@ -525,6 +1132,11 @@ impl UserRepository for UserRepositoryImpl {
                 smtpprofiles::smtp_server.eq(&profile.smtp_server),
                 smtpprofiles::smtp_port.eq(profile.smtp_port),
                 smtpprofiles::is_default.eq(profile.is_default),
+                smtpprofiles::tls_mode.eq(&profile.tls_mode),
+                smtpprofiles::ca_certificate.eq(&profile.ca_certificate),
+                smtpprofiles::tls_hostname.eq(&profile.tls_hostname),
+                smtpprofiles::priority.eq(profile.priority),
+                smtpprofiles::weight.eq(profile.weight),
                 smtpprofiles::updated_at.eq(chrono::Utc::now()),
             ))
             .get_result::<SmtpProfile>(&mut conn)
@@ -541,12 +1153,38 @@ impl UserRepository for UserRepositoryImpl {
             company_id
         );
         let mut conn = self.pool.get().expect("Failed to get DB connection");
-        diesel::delete(
-            smtpprofiles::table
+
+        // Jobs still waiting on the profile fall back to the company's other profiles, and sent
+        // mail keeps its log entry without the link. Routing rules sending through it are removed
+        conn.transaction(|conn| {
+            let profile_id: Option<i64> = smtpprofiles::table
                 .filter(smtpprofiles::id.eq(profile_id))
-                .filter(smtpprofiles::company_id.eq(company_id)),
-        )
-        .execute(&mut conn)
+                .filter(smtpprofiles::company_id.eq(company_id))
+                .select(smtpprofiles::id)
+                .for_update()
+                .first(conn)
+                .optional()?;
+            let Some(profile_id) = profile_id else {
+                return Ok(0);
+            };
+
+            diesel::update(email_queue::table.filter(email_queue::smtp_profile_id.eq(profile_id)))
+                .set(email_queue::smtp_profile_id.eq(None::<i64>))
+                .execute(conn)?;
+            diesel::update(emaillog::table.filter(emaillog::smtp_profile_id.eq(profile_id)))
+                .set(emaillog::smtp_profile_id.eq(None::<i64>))
+                .execute(conn)?;
+            let usage = usage_daily::table
+                .filter(usage_daily::smtp_profile_id.eq(profile_id))
+                .load::<UsageDaily>(conn)?;
+            Self::detach_usage(conn, usage, |usage| usage.smtp_profile_id = None)?;
+            diesel::delete(
+                smtp_routing_rules::table.filter(smtp_routing_rules::smtp_profile_id.eq(profile_id)),
+            )
+            .execute(conn)?;
+
+            diesel::delete(smtpprofiles::table.find(profile_id)).execute(conn)
+        })
     }
 
     fn set_default_smtp_profile(
@@ -579,6 +1217,81 @@ impl UserRepository for UserRepositoryImpl {
         .get_result::<SmtpProfile>(&mut conn)
     }
 
+    fn get_routing_rules_by_company(
+        &self,
+        company_id: i64,
+    ) -> Result<Vec<SmtpRoutingRule>, diesel::result::Error> {
+        log::debug!("Fetching SMTP routing rules for company: {}", company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        smtp_routing_rules::table
+            .filter(smtp_routing_rules::company_id.eq(company_id))
+            .order((smtp_routing_rules::priority.asc(), smtp_routing_rules::id.asc()))
+            .load::<SmtpRoutingRule>(&mut conn)
+    }
+
+    fn get_routing_rule_by_id(
+        &self,
+        rule_id: i64,
+        company_id: i64,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error> {
+        log::debug!("Fetching SMTP routing rule: {} for company: {}", rule_id, company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        smtp_routing_rules::table
+            .filter(smtp_routing_rules::id.eq(rule_id))
+            .filter(smtp_routing_rules::company_id.eq(company_id))
+            .first::<SmtpRoutingRule>(&mut conn)
+    }
+
+    fn create_routing_rule(
+        &self,
+        new_rule: NewSmtpRoutingRule,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error> {
+        log::debug!("Creating SMTP routing rule for company: {}", new_rule.company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::insert_into(smtp_routing_rules::table)
+            .values(&new_rule)
+            .get_result::<SmtpRoutingRule>(&mut conn)
+    }
+
+    fn update_routing_rule(
+        &self,
+        rule_id: i64,
+        rule: &SmtpRoutingRule,
+    ) -> Result<SmtpRoutingRule, diesel::result::Error> {
+        log::debug!("Updating SMTP routing rule: {}", rule_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(smtp_routing_rules::table.find(rule_id))
+            .set((
+                smtp_routing_rules::smtp_profile_id.eq(rule.smtp_profile_id),
+                smtp_routing_rules::name.eq(&rule.name),
+                smtp_routing_rules::priority.eq(rule.priority),
+                smtp_routing_rules::recipient_domain.eq(&rule.recipient_domain),
+                smtp_routing_rules::from_address.eq(&rule.from_address),
+                smtp_routing_rules::template_id.eq(rule.template_id),
+                smtp_routing_rules::tag.eq(&rule.tag),
+                smtp_routing_rules::category.eq(&rule.category),
+                smtp_routing_rules::api_key_id.eq(rule.api_key_id),
+                smtp_routing_rules::is_active.eq(rule.is_active),
+                smtp_routing_rules::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<SmtpRoutingRule>(&mut conn)
+    }
+
+    fn delete_routing_rule(
+        &self,
+        rule_id: i64,
+        company_id: i64,
+    ) -> Result<usize, diesel::result::Error> {
+        log::debug!("Deleting SMTP routing rule: {} for company: {}", rule_id, company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::delete(
+            smtp_routing_rules::table
+                .filter(smtp_routing_rules::id.eq(rule_id))
+                .filter(smtp_routing_rules::company_id.eq(company_id)),
+        )
+        .execute(&mut conn)
+    }
+
     fn create_email_log(&self, new_log: NewEmailLog) -> Result<EmailLog, diesel::result::Error> {
         log::debug!("Creating email log for company: {}", new_log.company_id);
         let mut conn = self.pool.get().expect("Failed to get DB connection");
@@ -624,6 +1337,53 @@ impl UserRepository for UserRepositoryImpl {
             .load::<EmailLog>(&mut conn)
     }
 
+    fn search_email_logs(
+        &self,
+        company_id: i64,
+        filter: &EmailLogFilter,
+    ) -> Result<Vec<EmailLog>, diesel::result::Error> {
+        log::debug!("Searching email logs for company {}: {:?}", company_id, filter);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        let mut query = emaillog::table
+            .filter(emaillog::company_id.eq(company_id))
+            .into_boxed();
+
+        if let Some(since) = filter.since {
+            query = query.filter(emaillog::created_at.ge(since));
+        }
+        if let Some(status) = &filter.status {
+            query = query.filter(emaillog::status.eq(status));
+        }
+        if let Some(search) = &filter.search {
+            // Escape LIKE wildcards so the term matches literally
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            let pattern = format!("%{}%", escaped);
+            query = query.filter(
+                emaillog::to_email
+                    .ilike(pattern.clone())
+                    .or(emaillog::from_email.ilike(pattern.clone()))
+                    .or(emaillog::subject.ilike(pattern.clone()))
+                    .or(emaillog::message_id.ilike(pattern)),
+            );
+        }
+        if let Some((created_at, id)) = filter.before {
+            query = query.filter(
+                emaillog::created_at
+                    .lt(created_at)
+                    .or(emaillog::created_at.eq(created_at).and(emaillog::id.lt(id))),
+            );
+        }
+
+        query
+            .order((emaillog::created_at.desc(), emaillog::id.desc()))
+            .limit(filter.limit)
+            .load::<EmailLog>(&mut conn)
+    }
+
     fn get_email_log_stats(
         &self,
         company_id: i64,
@@ -700,11 +1460,25 @@ impl UserRepository for UserRepositoryImpl {
     fn delete_template(&self, template_id: i64, company_id: i64) -> Result<usize, diesel::result::Error> {
         log::debug!("Deleting template: {} for company: {}", template_id, company_id);
         let mut conn = self.pool.get().expect("Failed to get DB connection");
-        diesel::delete(
-            templates::table
+
+        // A rule matching on the template goes with it; clearing the criterion would widen the rule
+        conn.transaction(|conn| {
+            let template_id: Option<i64> = templates::table
                 .filter(templates::id.eq(template_id))
                 .filter(templates::company_id.eq(company_id))
-        ).execute(&mut conn)
+                .select(templates::id)
+                .for_update()
+                .first(conn)
+                .optional()?;
+            let Some(template_id) = template_id else {
+                return Ok(0);
+            };
+
+            diesel::delete(smtp_routing_rules::table.filter(smtp_routing_rules::template_id.eq(template_id)))
+                .execute(conn)?;
+
+            diesel::delete(templates::table.find(template_id)).execute(conn)
+        })
     }
 
     fn update_company(&self, company_id: i64, company: &Company) -> Result<Company, diesel::result::Error> {
@@ -720,4 +1494,515 @@ impl UserRepository for UserRepositoryImpl {
             ))
             .get_result::<Company>(&mut conn)
     }
+
+    fn enqueue_email(
+        &self,
+        new_logs: Vec<NewEmailLog>,
+        new_job: NewEmailJob,
+        credits: i64,
+    ) -> Result<(Vec<EmailLog>, EmailJob), diesel::result::Error> {
+        log::debug!(
+            "Enqueueing email {} to {} recipients for company: {}",
+            new_job.message_id,
+            new_logs.len(),
+            new_job.company_id
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        // The debit, log rows and job are written together so a crash can't leave one without the others
+        conn.transaction(|conn| {
+            if credits > 0 {
+                let debit = Self::debit_entry(new_job.company_id, &new_job.message_id, credits);
+                Self::debit_credits(conn, new_job.company_id, vec![debit])?;
+            }
+
+            let email_logs = diesel::insert_into(emaillog::table)
+                .values(&new_logs)
+                .get_results::<EmailLog>(conn)?;
+
+            let job = diesel::insert_into(email_queue::table)
+                .values(&new_job)
+                .get_result::<EmailJob>(conn)?;
+
+            Ok((email_logs, job))
+        })
+    }
+
+    fn enqueue_email_batch(
+        &self,
+        company_id: i64,
+        emails: Vec<(Vec<NewEmailLog>, NewEmailJob, i64)>,
+    ) -> Result<Vec<EmailJob>, diesel::result::Error> {
+        log::debug!(
+            "Enqueueing batch of {} emails for company: {}",
+            emails.len(),
+            company_id
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        // Credits are only taken when the balance covers the whole batch; otherwise nothing is queued
+        conn.transaction(|conn| {
+            let mut new_logs = Vec::new();
+            let mut new_jobs = Vec::with_capacity(emails.len());
+            let mut debits = Vec::with_capacity(emails.len());
+            for (logs, job, credits) in emails {
+                if credits > 0 {
+                    debits.push(Self::debit_entry(company_id, &job.message_id, credits));
+                }
+                new_logs.extend(logs);
+                new_jobs.push(job);
+            }
+
+            // Unlimited tiers aren't debited at all
+            if !debits.is_empty() {
+                Self::debit_credits(conn, company_id, debits)?;
+            }
+
+            diesel::insert_into(emaillog::table)
+                .values(&new_logs)
+                .execute(conn)?;
+
+            diesel::insert_into(email_queue::table)
+                .values(&new_jobs)
+                .get_results::<EmailJob>(conn)
+        })
+    }
+
+    fn refund_email_credits(
+        &self,
+        company_id: i64,
+        message_id: &str,
+        reason: &str,
+    ) -> Result<i64, diesel::result::Error> {
+        log::debug!("Refunding credits for message {} of company: {}", message_id, company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        conn.transaction(|conn| Self::refund_message_credits(conn, company_id, message_id, reason))
+    }
+
+    fn requeue_email_log(
+        &self,
+        log_id: i64,
+        new_job: NewEmailJob,
+    ) -> Result<EmailJob, diesel::result::Error> {
+        log::debug!("Requeueing email log ID: {} as {}", log_id, new_job.message_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        conn.transaction(|conn| {
+            diesel::update(emaillog::table.find(log_id))
+                .set(emaillog::message_id.eq(Some(&new_job.message_id)))
+                .execute(conn)?;
+
+            diesel::insert_into(email_queue::table)
+                .values(&new_job)
+                .get_result::<EmailJob>(conn)
+        })
+    }
+
+    fn claim_email_job(&self, worker_id: &str) -> Result<Option<EmailJob>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let now = chrono::Utc::now();
+
+        // SKIP LOCKED lets several workers poll the same table without handing out a job twice.
+        // One job per claim, so the lease clock starts when the worker actually picks it up
+        conn.transaction(|conn| {
+            let job_id: Option<i64> = email_queue::table
+                .filter(email_queue::status.eq("Pending"))
+                .filter(email_queue::available_at.le(now))
+                .order(email_queue::available_at.asc())
+                .select(email_queue::id)
+                .for_update()
+                .skip_locked()
+                .first(conn)
+                .optional()?;
+
+            let Some(job_id) = job_id else {
+                return Ok(None);
+            };
+
+            log::debug!("Worker {} claiming email job {}", worker_id, job_id);
+            diesel::update(email_queue::table.find(job_id))
+                .set((
+                    email_queue::status.eq("Processing"),
+                    email_queue::attempts.eq(email_queue::attempts + 1),
+                    email_queue::locked_at.eq(Some(now)),
+                    email_queue::locked_by.eq(Some(worker_id)),
+                    email_queue::updated_at.eq(now),
+                ))
+                .get_result::<EmailJob>(conn)
+                .map(Some)
+        })
+    }
+
+    fn reserve_idempotency_key(
+        &self,
+        new_key: NewIdempotencyKey,
+        expired_before: DateTime<Utc>,
+        lease_expired_before: DateTime<Utc>,
+    ) -> Result<(IdempotencyKey, bool), diesel::result::Error> {
+        log::debug!(
+            "Reserving idempotency key {} for company: {}",
+            new_key.key,
+            new_key.company_id
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        // The unique (company, key) constraint decides between concurrent requests with the same key:
+        // whoever inserts first owns it, everyone else gets the existing row back. Expired keys, and
+        // reservations still without a response once their lease has run out, are free to take
+        conn.transaction(|conn| {
+            diesel::delete(
+                idempotency_keys::table
+                    .filter(idempotency_keys::company_id.eq(new_key.company_id))
+                    .filter(idempotency_keys::key.eq(&new_key.key))
+                    .filter(
+                        idempotency_keys::created_at.lt(expired_before).or(idempotency_keys::response
+                            .is_null()
+                            .and(idempotency_keys::created_at.lt(lease_expired_before))),
+                    ),
+            )
+            .execute(conn)?;
+
+            let reserved = diesel::insert_into(idempotency_keys::table)
+                .values(&new_key)
+                .on_conflict_do_nothing()
+                .get_result::<IdempotencyKey>(conn)
+                .optional()?;
+            if let Some(reserved) = reserved {
+                return Ok((reserved, true));
+            }
+
+            let existing = idempotency_keys::table
+                .filter(idempotency_keys::company_id.eq(new_key.company_id))
+                .filter(idempotency_keys::key.eq(&new_key.key))
+                .first::<IdempotencyKey>(conn)?;
+            Ok((existing, false))
+        })
+    }
+
+    fn get_completed_idempotency_key(
+        &self,
+        company_id: i64,
+        key: &str,
+        expired_before: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyKey>, diesel::result::Error> {
+        log::debug!("Looking up idempotency key {} for company: {}", key, company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        idempotency_keys::table
+            .filter(idempotency_keys::company_id.eq(company_id))
+            .filter(idempotency_keys::key.eq(key))
+            .filter(idempotency_keys::response.is_not_null())
+            .filter(idempotency_keys::created_at.ge(expired_before))
+            .first::<IdempotencyKey>(&mut conn)
+            .optional()
+    }
+
+    fn complete_idempotency_key(
+        &self,
+        key_id: i64,
+        message_id: &str,
+        response: serde_json::Value,
+    ) -> Result<IdempotencyKey, diesel::result::Error> {
+        log::debug!("Completing idempotency key ID: {}", key_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        diesel::update(idempotency_keys::table.find(key_id))
+            .set((
+                idempotency_keys::message_id.eq(Some(message_id)),
+                idempotency_keys::response.eq(Some(response)),
+            ))
+            .get_result::<IdempotencyKey>(&mut conn)
+    }
+
+    fn release_idempotency_key(&self, key_id: i64) -> Result<usize, diesel::result::Error> {
+        log::debug!("Releasing idempotency key ID: {}", key_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        diesel::delete(idempotency_keys::table.find(key_id)).execute(&mut conn)
+    }
+
+    fn purge_expired_idempotency_keys(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<usize, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        diesel::delete(idempotency_keys::table.filter(idempotency_keys::created_at.lt(expired_before)))
+            .execute(&mut conn)
+    }
+
+    fn release_scheduled_email_jobs(&self, limit: i64) -> Result<Vec<EmailJob>, diesel::result::Error> {
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        let now = chrono::Utc::now();
+
+        conn.transaction(|conn| {
+            let job_ids: Vec<i64> = email_queue::table
+                .filter(email_queue::status.eq("Scheduled"))
+                .filter(email_queue::available_at.le(now))
+                .order(email_queue::available_at.asc())
+                .limit(limit)
+                .select(email_queue::id)
+                .for_update()
+                .skip_locked()
+                .load(conn)?;
+
+            if job_ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            log::debug!("Releasing {} scheduled email jobs", job_ids.len());
+            let jobs = diesel::update(email_queue::table.filter(email_queue::id.eq_any(&job_ids)))
+                .set((
+                    email_queue::status.eq("Pending"),
+                    email_queue::updated_at.eq(now),
+                ))
+                .get_results::<EmailJob>(conn)?;
+
+            let message_ids: Vec<&str> = jobs.iter().map(|job| job.message_id.as_str()).collect();
+            diesel::update(
+                emaillog::table
+                    .filter(emaillog::message_id.eq_any(&message_ids))
+                    .filter(emaillog::status.eq("Scheduled")),
+            )
+            .set(emaillog::status.eq("Queued"))
+            .execute(conn)?;
+
+            Ok(jobs)
+        })
+    }
+
+    fn cancel_scheduled_email(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<i64, diesel::result::Error> {
+        log::debug!("Cancelling scheduled email {} for company: {}", message_id, company_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        // Locking the job keeps the scheduler from releasing it while it is being cancelled;
+        // NotFound means the message is not (or no longer) scheduled
+        conn.transaction(|conn| {
+            let job_id: i64 = email_queue::table
+                .filter(email_queue::message_id.eq(message_id))
+                .filter(email_queue::company_id.eq(company_id))
+                .filter(email_queue::status.eq("Scheduled"))
+                .select(email_queue::id)
+                .for_update()
+                .first(conn)?;
+
+            diesel::update(email_queue::table.find(job_id))
+                .set((
+                    email_queue::status.eq("Cancelled"),
+                    email_queue::updated_at.eq(chrono::Utc::now()),
+                ))
+                .execute(conn)?;
+
+            diesel::update(
+                emaillog::table
+                    .filter(emaillog::message_id.eq(message_id))
+                    .filter(emaillog::company_id.eq(company_id)),
+            )
+            .set(emaillog::status.eq("Cancelled"))
+            .execute(conn)?;
+
+            Self::refund_message_credits(conn, company_id, message_id, "Cancelled before sending")
+        })
+    }
+
+    fn reschedule_email(
+        &self,
+        message_id: &str,
+        company_id: i64,
+        send_at: DateTime<Utc>,
+    ) -> Result<EmailJob, diesel::result::Error> {
+        log::debug!(
+            "Rescheduling email {} for company {} to {}",
+            message_id,
+            company_id,
+            send_at
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+
+        conn.transaction(|conn| {
+            let job = diesel::update(
+                email_queue::table
+                    .filter(email_queue::message_id.eq(message_id))
+                    .filter(email_queue::company_id.eq(company_id))
+                    .filter(email_queue::status.eq("Scheduled")),
+            )
+            .set((
+                email_queue::available_at.eq(send_at),
+                email_queue::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<EmailJob>(conn)?;
+
+            diesel::update(
+                emaillog::table
+                    .filter(emaillog::message_id.eq(message_id))
+                    .filter(emaillog::company_id.eq(company_id)),
+            )
+            .set(emaillog::scheduled_at.eq(Some(send_at)))
+            .execute(conn)?;
+
+            Ok(job)
+        })
+    }
+
+    // complete/fail/retry only touch a job still locked by `worker_id`: once its lease has expired
+    // and another worker has claimed it, the update matches nothing and returns NotFound
+    fn complete_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+    ) -> Result<EmailJob, diesel::result::Error> {
+        log::debug!("Completing email job: {}", job_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(
+            email_queue::table
+                .find(job_id)
+                .filter(email_queue::locked_by.eq(worker_id)),
+        )
+            .set((
+                email_queue::status.eq("Completed"),
+                email_queue::locked_at.eq(None::<DateTime<Utc>>),
+                email_queue::locked_by.eq(None::<String>),
+                email_queue::last_error.eq(None::<String>),
+                email_queue::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<EmailJob>(&mut conn)
+    }
+
+    fn fail_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+        error: &str,
+    ) -> Result<EmailJob, diesel::result::Error> {
+        log::debug!("Marking email job {} as failed", job_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(
+            email_queue::table
+                .find(job_id)
+                .filter(email_queue::locked_by.eq(worker_id)),
+        )
+            .set((
+                email_queue::status.eq("Failed"),
+                email_queue::locked_at.eq(None::<DateTime<Utc>>),
+                email_queue::locked_by.eq(None::<String>),
+                email_queue::last_error.eq(Some(error)),
+                email_queue::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<EmailJob>(&mut conn)
+    }
+
+    fn retry_email_job(
+        &self,
+        job_id: i64,
+        worker_id: &str,
+        available_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<EmailJob, diesel::result::Error> {
+        log::debug!("Rescheduling email job {} for {}", job_id, available_at);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(
+            email_queue::table
+                .find(job_id)
+                .filter(email_queue::locked_by.eq(worker_id)),
+        )
+            .set((
+                email_queue::status.eq("Pending"),
+                email_queue::available_at.eq(available_at),
+                email_queue::locked_at.eq(None::<DateTime<Utc>>),
+                email_queue::locked_by.eq(None::<String>),
+                email_queue::last_error.eq(Some(error)),
+                email_queue::updated_at.eq(chrono::Utc::now()),
+            ))
+            .get_result::<EmailJob>(&mut conn)
+    }
+
+    fn record_email_log_attempt(
+        &self,
+        message_id: &str,
+        attempt: &EmailLogAttempt,
+    ) -> Result<usize, diesel::result::Error> {
+        log::debug!(
+            "Recording attempt {} for message: {} ({:?})",
+            attempt.attempts,
+            message_id,
+            attempt.status
+        );
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(emaillog::table.filter(emaillog::message_id.eq(message_id)))
+            .set(attempt)
+            .execute(&mut conn)
+    }
+
+    fn release_stale_email_jobs(
+        &self,
+        locked_before: DateTime<Utc>,
+    ) -> Result<usize, diesel::result::Error> {
+        log::debug!("Releasing email jobs locked before {}", locked_before);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::update(
+            email_queue::table
+                .filter(email_queue::status.eq("Processing"))
+                .filter(email_queue::locked_at.lt(locked_before)),
+        )
+        .set((
+            email_queue::status.eq("Pending"),
+            email_queue::locked_at.eq(None::<DateTime<Utc>>),
+            email_queue::locked_by.eq(None::<String>),
+            email_queue::updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(&mut conn)
+    }
+
+    fn get_orphaned_queued_email_logs(&self) -> Result<Vec<EmailLog>, diesel::result::Error> {
+        log::debug!("Fetching queued email logs without a queue job");
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        emaillog::table
+            .filter(emaillog::status.eq(Some("Queued".to_string())))
+            .filter(diesel::dsl::not(diesel::dsl::exists(
+                email_queue::table.filter(email_queue::message_id.nullable().eq(emaillog::message_id)),
+            )))
+            .order(emaillog::created_at.asc())
+            .load::<EmailLog>(&mut conn)
+    }
+
+    fn get_email_logs_by_message_id(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<Vec<EmailLog>, diesel::result::Error> {
+        log::debug!("Fetching email logs for message: {}", message_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        emaillog::table
+            .filter(emaillog::message_id.eq(message_id))
+            .filter(emaillog::company_id.eq(company_id))
+            .order(emaillog::id.asc())
+            .load::<EmailLog>(&mut conn)
+    }
+
+    fn create_email_event(&self, new_event: NewEmailEvent) -> Result<EmailEvent, diesel::result::Error> {
+        log::debug!("Recording {} event for message: {}", new_event.event, new_event.message_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        diesel::insert_into(email_events::table)
+            .values(&new_event)
+            .get_result(&mut conn)
+    }
+
+    fn get_email_events_by_message_id(
+        &self,
+        message_id: &str,
+        company_id: i64,
+    ) -> Result<Vec<EmailEvent>, diesel::result::Error> {
+        log::debug!("Fetching email events for message: {}", message_id);
+        let mut conn = self.pool.get().expect("Failed to get DB connection");
+        email_events::table
+            .filter(email_events::message_id.eq(message_id))
+            .filter(email_events::company_id.eq(company_id))
+            .order((email_events::created_at.asc(), email_events::id.asc()))
+            .load::<EmailEvent>(&mut conn)
+    }
 }
@@ -0,0 +1,16 @@
+use crate::controllers::billing_controller::BillingController;
+use crate::middleware::auth::jwt_validator;
+use actix_web::web::{self, ServiceConfig};
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+pub fn register_billing_routes(cfg: &mut ServiceConfig) {
+    let auth = HttpAuthentication::bearer(jwt_validator);
+    cfg.service(
+        web::scope("/billing")
+            .wrap(auth)
+            .route("/usage", web::get().to(BillingController::get_usage))
+            .route("/plan", web::get().to(BillingController::get_plan))
+            .route("/plan", web::put().to(BillingController::change_plan))
+            .route("/top-ups", web::post().to(BillingController::purchase_top_up)),
+    );
+}
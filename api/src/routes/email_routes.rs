@@ -1,9 +1,13 @@
 use crate::controllers::email_controller::EmailController;
+use crate::middleware::auth::jwt_validator;
 use actix_web::web::{self, ServiceConfig};
+use actix_web_httpauth::middleware::HttpAuthentication;
 
 pub fn register_email_routes(cfg: &mut ServiceConfig) {
+    let auth = HttpAuthentication::bearer(jwt_validator);
     cfg.service(
         web::scope("/email")
+            .wrap(auth)
             .route("/send", web::post().to(EmailController::send_email))
             .route("/recent", web::get().to(EmailController::get_recent_emails))
             .route("/status/{message_id}", web::get().to(EmailController::get_email_status)),
@@ -10,3 +10,6 @@ pub mod user_routes;
 pub mod smtp_routes;
 pub mod public_email_routes;
 pub mod settings_routes;
+pub mod webhooks_routes;
+pub mod billing_routes;
+pub mod routing_rules_routes;
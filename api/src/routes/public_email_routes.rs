@@ -1,9 +1,30 @@
 use crate::controllers::public_email_controller::PublicEmailController;
+use crate::middleware::credits::CreditEnforcement;
 use actix_web::web;
 
 pub fn register_public_email_routes(cfg: &mut web::ServiceConfig) {
+    // Sized for the largest per-company limit; the handler enforces the company's own. Attachments
+    // arrive base64-encoded, so allow for the encoding overhead on top of the message limit
+    let max_payload = PublicEmailController::max_message_bytes_ceiling() as usize * 4 / 3 + 64 * 1024;
+    let max_batch_payload = crate::utils::utils::get_env("MAX_BATCH_PAYLOAD_BYTES", "52428800")
+        .parse()
+        .unwrap_or(52_428_800);
+
     cfg.service(
         web::scope("/v1")
+            .wrap(CreditEnforcement)
+            .app_data(web::JsonConfig::default().limit(max_payload))
             .route("/email/send", web::post().to(PublicEmailController::send_email))
+            .service(
+                web::resource("/email/batch")
+                    .app_data(web::JsonConfig::default().limit(max_batch_payload))
+                    .route(web::post().to(PublicEmailController::send_batch)),
+            )
+            .route("/email/{message_id}", web::get().to(PublicEmailController::get_email_status))
+            .route("/email/{message_id}/cancel", web::post().to(PublicEmailController::cancel_email))
+            .route(
+                "/email/{message_id}/reschedule",
+                web::post().to(PublicEmailController::reschedule_email),
+            )
     );
-}
\ No newline at end of file
+}
@@ -0,0 +1,17 @@
+use crate::controllers::routing_rules_controller::RoutingRulesController;
+use crate::middleware::auth::jwt_validator;
+use actix_web::web;
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+pub fn register_routing_rules_routes(cfg: &mut web::ServiceConfig) {
+    let auth = HttpAuthentication::bearer(jwt_validator);
+    cfg.service(
+        web::scope("/smtp-routing-rules")
+            .wrap(auth)
+            .route("", web::get().to(RoutingRulesController::get_routing_rules))
+            .route("", web::post().to(RoutingRulesController::create_routing_rule))
+            .route("/{id}", web::get().to(RoutingRulesController::get_routing_rule))
+            .route("/{id}", web::put().to(RoutingRulesController::update_routing_rule))
+            .route("/{id}", web::delete().to(RoutingRulesController::delete_routing_rule)),
+    );
+}
@@ -13,5 +13,6 @@ pub fn register_smtp_routes(cfg: &mut web::ServiceConfig) {
             .route("/{id}", web::put().to(SmtpController::update_smtp_profile))
             .route("/{id}", web::delete().to(SmtpController::delete_smtp_profile))
             .route("/{id}/set-default", web::patch().to(SmtpController::set_default_smtp_profile))
+            .route("/{id}/test", web::post().to(SmtpController::test_smtp_profile))
     );
 }
\ No newline at end of file
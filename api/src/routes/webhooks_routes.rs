@@ -0,0 +1,23 @@
+use crate::controllers::webhooks_controller::WebhooksController;
+use crate::middleware::auth::jwt_validator;
+use actix_web::web;
+use actix_web_httpauth::middleware::HttpAuthentication;
+
+pub fn register_webhooks_routes(cfg: &mut web::ServiceConfig) {
+    let auth = HttpAuthentication::bearer(jwt_validator);
+    cfg.service(
+        web::scope("/webhooks")
+            .wrap(auth)
+            .route("", web::get().to(WebhooksController::get_webhooks))
+            .route("", web::post().to(WebhooksController::create_webhook))
+            .route("/{id}", web::get().to(WebhooksController::get_webhook))
+            .route("/{id}", web::put().to(WebhooksController::update_webhook))
+            .route("/{id}", web::delete().to(WebhooksController::delete_webhook))
+            .route("/{id}/rotate-secret", web::post().to(WebhooksController::rotate_webhook_secret))
+            .route("/{id}/deliveries", web::get().to(WebhooksController::get_webhook_deliveries))
+            .route(
+                "/{id}/deliveries/{delivery_id}/replay",
+                web::post().to(WebhooksController::replay_webhook_delivery),
+            )
+    );
+}
@@ -73,6 +73,23 @@ diesel::table! {
         pricing_tier -> Varchar,
         api_credits -> Int8,
         credits_reset_date -> Timestamptz,
+        max_message_bytes -> Nullable<Int8>,
+        topup_credits -> Int8,
+    }
+}
+
+diesel::table! {
+    credit_ledger (id) {
+        id -> Int8,
+        company_id -> Int8,
+        #[max_length = 255]
+        message_id -> Nullable<Varchar>,
+        amount -> Int8,
+        #[max_length = 50]
+        entry_type -> Varchar,
+        reason -> Nullable<Text>,
+        created_at -> Timestamptz,
+        topup_amount -> Int8,
     }
 }
 
@@ -120,6 +137,42 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    email_events (id) {
+        id -> Int8,
+        #[max_length = 255]
+        message_id -> Varchar,
+        company_id -> Int8,
+        #[max_length = 50]
+        event -> Varchar,
+        smtp_code -> Nullable<Int4>,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    email_queue (id) {
+        id -> Int8,
+        #[max_length = 255]
+        message_id -> Varchar,
+        company_id -> Int8,
+        smtp_profile_id -> Nullable<Int8>,
+        payload -> Jsonb,
+        #[max_length = 50]
+        status -> Varchar,
+        attempts -> Int4,
+        available_at -> Timestamptz,
+        locked_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        locked_by -> Nullable<Varchar>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        api_key_id -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     emaillog (id) {
         id -> Int8,
@@ -134,6 +187,40 @@ diesel::table! {
         status -> Nullable<Varchar>,
         created_at -> Timestamptz,
         company_id -> Int8,
+        attempts -> Int4,
+        last_smtp_code -> Nullable<Int4>,
+        next_retry_at -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        #[max_length = 255]
+        message_id -> Nullable<Varchar>,
+        #[max_length = 10]
+        recipient_type -> Varchar,
+        scheduled_at -> Nullable<Timestamptz>,
+        smtp_profile_id -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    smtp_routing_rules (id) {
+        id -> Int8,
+        company_id -> Int8,
+        smtp_profile_id -> Int8,
+        #[max_length = 255]
+        name -> Varchar,
+        priority -> Int4,
+        #[max_length = 255]
+        recipient_domain -> Nullable<Varchar>,
+        #[max_length = 255]
+        from_address -> Nullable<Varchar>,
+        template_id -> Nullable<Int8>,
+        #[max_length = 100]
+        tag -> Nullable<Varchar>,
+        #[max_length = 100]
+        category -> Nullable<Varchar>,
+        api_key_id -> Nullable<Int8>,
+        is_active -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -151,6 +238,13 @@ diesel::table! {
         is_default -> Bool,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        #[max_length = 20]
+        tls_mode -> Varchar,
+        ca_certificate -> Nullable<Text>,
+        #[max_length = 255]
+        tls_hostname -> Nullable<Varchar>,
+        priority -> Int4,
+        weight -> Int4,
     }
 }
 
@@ -170,6 +264,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    idempotency_keys (id) {
+        id -> Int8,
+        company_id -> Int8,
+        #[max_length = 255]
+        key -> Varchar,
+        #[max_length = 64]
+        request_hash -> Varchar,
+        #[max_length = 255]
+        message_id -> Nullable<Varchar>,
+        response -> Nullable<Jsonb>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     industries (id) {
         id -> Int8,
@@ -179,6 +288,30 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    payments (id) {
+        id -> Int8,
+        company_id -> Int8,
+        #[max_length = 50]
+        kind -> Varchar,
+        #[max_length = 255]
+        description -> Varchar,
+        amount_cents -> Int8,
+        #[max_length = 3]
+        currency -> Varchar,
+        credits -> Int8,
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 255]
+        provider_reference -> Nullable<Varchar>,
+        #[max_length = 50]
+        status -> Varchar,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     team_members (id) {
         id -> Int8,
@@ -191,6 +324,37 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    usage_snapshots (id) {
+        id -> Int8,
+        company_id -> Int8,
+        period_start -> Timestamptz,
+        period_end -> Timestamptz,
+        #[max_length = 50]
+        pricing_tier -> Varchar,
+        credits_allocated -> Int8,
+        credits_used -> Int8,
+        credits_remaining -> Int8,
+        emails_sent -> Int8,
+        emails_failed -> Int8,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    usage_daily (id) {
+        id -> Int8,
+        company_id -> Int8,
+        day -> Date,
+        api_key_id -> Nullable<Int8>,
+        smtp_profile_id -> Nullable<Int8>,
+        emails_sent -> Int8,
+        recipients -> Int8,
+        attachment_bytes -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int8,
@@ -246,6 +410,33 @@ diesel::table! {
         #[max_length = 255]
         status -> Nullable<Varchar>,
         company_id -> Int8,
+        #[max_length = 255]
+        secret -> Nullable<Varchar>,
+        consecutive_failures -> Int4,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int8,
+        company_id -> Int8,
+        #[max_length = 255]
+        event_id -> Varchar,
+        #[max_length = 50]
+        event -> Varchar,
+        payload -> Jsonb,
+        attempt -> Int4,
+        #[max_length = 50]
+        status -> Varchar,
+        response_status -> Nullable<Int4>,
+        response_body -> Nullable<Text>,
+        error -> Nullable<Text>,
+        latency_ms -> Nullable<Int4>,
+        next_attempt_at -> Timestamptz,
+        locked_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -256,17 +447,35 @@ diesel::joinable!(auth_permission -> django_content_type (content_type_id));
 diesel::joinable!(authtoken_token -> users (user_id));
 diesel::joinable!(companies -> industries (industry_id));
 diesel::joinable!(companies -> users (owner_id));
+diesel::joinable!(credit_ledger -> companies (company_id));
 diesel::joinable!(django_admin_log -> django_content_type (content_type_id));
 diesel::joinable!(django_admin_log -> users (user_id));
+diesel::joinable!(email_events -> companies (company_id));
+diesel::joinable!(email_queue -> api_keys (api_key_id));
+diesel::joinable!(email_queue -> companies (company_id));
+diesel::joinable!(email_queue -> smtpprofiles (smtp_profile_id));
 diesel::joinable!(emaillog -> companies (company_id));
+diesel::joinable!(emaillog -> smtpprofiles (smtp_profile_id));
+diesel::joinable!(idempotency_keys -> companies (company_id));
+diesel::joinable!(payments -> companies (company_id));
+diesel::joinable!(smtp_routing_rules -> api_keys (api_key_id));
+diesel::joinable!(smtp_routing_rules -> companies (company_id));
+diesel::joinable!(smtp_routing_rules -> smtpprofiles (smtp_profile_id));
+diesel::joinable!(smtp_routing_rules -> templates (template_id));
 diesel::joinable!(smtpprofiles -> companies (company_id));
 diesel::joinable!(templates -> companies (company_id));
 diesel::joinable!(team_members -> companies (company_id));
 diesel::joinable!(team_members -> users (user_id));
+diesel::joinable!(usage_daily -> api_keys (api_key_id));
+diesel::joinable!(usage_daily -> companies (company_id));
+diesel::joinable!(usage_daily -> smtpprofiles (smtp_profile_id));
+diesel::joinable!(usage_snapshots -> companies (company_id));
 diesel::joinable!(users_groups -> auth_group (group_id));
 diesel::joinable!(users_groups -> users (user_id));
 diesel::joinable!(users_user_permissions -> auth_permission (permission_id));
 diesel::joinable!(users_user_permissions -> users (user_id));
+diesel::joinable!(webhook_deliveries -> companies (company_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
 diesel::joinable!(webhooks -> companies (company_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
@@ -276,17 +485,26 @@ diesel::allow_tables_to_appear_in_same_query!(
     auth_permission,
     authtoken_token,
     companies,
+    credit_ledger,
     django_admin_log,
     django_content_type,
     django_migrations,
     django_session,
+    email_events,
+    email_queue,
     emaillog,
+    idempotency_keys,
     industries,
+    payments,
+    smtp_routing_rules,
     smtpprofiles,
     team_members,
     templates,
+    usage_daily,
+    usage_snapshots,
     users,
     users_groups,
     users_user_permissions,
+    webhook_deliveries,
     webhooks,
 );
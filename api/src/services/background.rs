@@ -0,0 +1,56 @@
+use crate::repositories::RepositoryFactory;
+use crate::services::{billing, email_queue, webhooks};
+use std::future::Future;
+use std::time::Duration;
+
+// Pause before a job that panicked is started again, so a persistent fault doesn't spin
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+// Background jobs get their own runtime so blocking Diesel calls never stall the HTTP workers
+pub fn spawn_background_jobs(repo_factory: RepositoryFactory) {
+    std::thread::Builder::new()
+        .name("mailnow-background".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build background runtime");
+
+            runtime.block_on(async {
+                let scheduler_repo_factory = repo_factory.clone();
+                tokio::join!(
+                    email_queue::start_email_workers(repo_factory.clone()),
+                    webhooks::start_webhook_workers(repo_factory.clone()),
+                    supervise("credit reset scheduler", move || {
+                        billing::start_credit_reset_scheduler(scheduler_repo_factory.clone())
+                    }),
+                );
+            });
+        })
+        .expect("Failed to spawn background thread");
+}
+
+// Runs a long-lived job in its own task and starts it again if it panics, so one failing job
+// can't take the rest of the background runtime down with it
+pub async fn supervise<F, Fut>(name: &'static str, job: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        match tokio::spawn(job()).await {
+            Ok(()) => {
+                log::warn!("Background job '{}' stopped", name);
+                return;
+            }
+            Err(e) if e.is_panic() => {
+                log::error!("Background job '{}' panicked, restarting in {:?}", name, RESTART_DELAY);
+            }
+            Err(e) => {
+                log::error!("Background job '{}' was cancelled: {}", name, e);
+                return;
+            }
+        }
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
@@ -0,0 +1,591 @@
+use crate::errors::AppError;
+use crate::models::users::{Company, NewPayment, Payment};
+use crate::repositories::users::{PlanCreditChange, UserRepository, UserRepositoryImpl};
+use crate::repositories::RepositoryFactory;
+use crate::services::payments::{ChargeRequest, PaymentProvider, PaymentReceipt};
+use crate::utils::pricing::{remaining_cycle_fraction, PricingTier, TopUpPack};
+use crate::utils::utils::get_env;
+use chrono::Utc;
+use std::time::Duration;
+
+pub const CURRENCY: &str = "usd";
+
+// Resets every non-enterprise company whose `credits_reset_date` has passed, snapshotting the
+// closing period's usage as it goes. Calls on /v1 still reset lazily in between polls
+pub async fn start_credit_reset_scheduler(repo_factory: RepositoryFactory) {
+    let poll_interval = Duration::from_secs(
+        get_env("CREDIT_RESET_POLL_SECS", "300").parse().unwrap_or(300),
+    );
+    let batch_size: i64 = get_env("CREDIT_RESET_BATCH_SIZE", "100").parse().unwrap_or(100);
+    log::info!("Starting credit reset scheduler (poll every {:?})", poll_interval);
+
+    let mut interval = tokio::time::interval(poll_interval);
+    loop {
+        interval.tick().await;
+        reset_due_companies(&repo_factory, batch_size);
+    }
+}
+
+fn reset_due_companies(repo_factory: &RepositoryFactory, batch_size: i64) {
+    let user_repo = repo_factory.create_user_repository();
+
+    loop {
+        let companies = match user_repo.get_companies_due_for_reset(batch_size) {
+            Ok(companies) => companies,
+            Err(e) => {
+                log::error!("Failed to load companies due for a credit reset: {:?}", e);
+                return;
+            }
+        };
+        if companies.is_empty() {
+            return;
+        }
+
+        let mut reset = 0;
+        for company in &companies {
+            match user_repo.reset_company_credits(company.id, &company.pricing_tier) {
+                Ok(company) => {
+                    reset += 1;
+                    log::info!(
+                        "Reset API credits for company {} ({}), next reset {}",
+                        company.id,
+                        company.pricing_tier,
+                        company.credits_reset_date
+                    );
+                }
+                // Reset by a request in the meantime
+                Err(diesel::result::Error::NotFound) => {}
+                Err(e) => log::error!("Failed to reset credits for company {}: {:?}", company.id, e),
+            }
+        }
+
+        // Nothing moved forward, so another pass would only load the same rows again
+        if reset == 0 {
+            return;
+        }
+    }
+}
+
+// The storage plan changes and top-ups need, kept narrow so both flows can run against an
+// in-memory store in tests
+pub trait BillingStore {
+    fn create_payment(&self, new_payment: NewPayment) -> Result<Payment, diesel::result::Error>;
+    fn update_payment_status(
+        &self,
+        payment_id: i64,
+        status: &str,
+        provider_reference: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<Payment, diesel::result::Error>;
+    fn change_pricing_tier(
+        &self,
+        company_id: i64,
+        from_tier: &str,
+        to_tier: &str,
+        credit_change: PlanCreditChange,
+        payment: Option<(i64, &str)>,
+    ) -> Result<Company, diesel::result::Error>;
+    fn add_topup_credits(
+        &self,
+        company_id: i64,
+        payment_id: i64,
+        provider_reference: &str,
+    ) -> Result<Company, diesel::result::Error>;
+}
+
+impl BillingStore for UserRepositoryImpl {
+    fn create_payment(&self, new_payment: NewPayment) -> Result<Payment, diesel::result::Error> {
+        UserRepository::create_payment(self, new_payment)
+    }
+
+    fn update_payment_status(
+        &self,
+        payment_id: i64,
+        status: &str,
+        provider_reference: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<Payment, diesel::result::Error> {
+        UserRepository::update_payment_status(self, payment_id, status, provider_reference, error)
+    }
+
+    fn change_pricing_tier(
+        &self,
+        company_id: i64,
+        from_tier: &str,
+        to_tier: &str,
+        credit_change: PlanCreditChange,
+        payment: Option<(i64, &str)>,
+    ) -> Result<Company, diesel::result::Error> {
+        UserRepository::change_pricing_tier(self, company_id, from_tier, to_tier, credit_change, payment)
+    }
+
+    fn add_topup_credits(
+        &self,
+        company_id: i64,
+        payment_id: i64,
+        provider_reference: &str,
+    ) -> Result<Company, diesel::result::Error> {
+        UserRepository::add_topup_credits(self, company_id, payment_id, provider_reference)
+    }
+}
+
+#[derive(Debug)]
+pub struct PlanChange {
+    pub previous_tier: String,
+    pub company: Company,
+    pub amount_cents: i64,
+    pub payment: Option<Payment>,
+}
+
+// Plan changes take effect immediately. An upgrade is charged the price difference for the rest
+// of the cycle and granted the matching share of the extra credits; a downgrade is not refunded
+// and caps the monthly balance at the new allowance. Top-up credits are left alone either way.
+// `company` should be freshly reset so the change is priced against the current cycle
+pub async fn change_plan(
+    store: &impl BillingStore,
+    payment_provider: &dyn PaymentProvider,
+    company: &Company,
+    target: PricingTier,
+) -> Result<PlanChange, AppError> {
+    let current = PricingTier::from_str(&company.pricing_tier);
+    if current.to_string() == target.to_string() {
+        return Err(AppError::Conflict(format!(
+            "Company is already on the {} plan",
+            target.to_string()
+        )));
+    }
+    let (Some(current_price), Some(target_price)) =
+        (current.monthly_price_cents(), target.monthly_price_cents())
+    else {
+        return Err(AppError::Forbidden(
+            "Enterprise plans are managed by our sales team".to_string(),
+        ));
+    };
+
+    let (credit_change, amount_cents) = if target_price > current_price {
+        let fraction = remaining_cycle_fraction(company.credits_reset_date);
+        let credits = (target.monthly_credits() - current.monthly_credits()) as f64 * fraction;
+        let amount = (target_price - current_price) as f64 * fraction;
+        (PlanCreditChange::Grant(credits.round() as i64), amount.round() as i64)
+    } else {
+        (PlanCreditChange::Cap(target.monthly_credits()), 0)
+    };
+
+    let from_tier = current.to_string();
+    let to_tier = target.to_string();
+    let payment = if amount_cents > 0 {
+        let description = format!("Upgrade from {} to {}", from_tier, to_tier);
+        Some(
+            take_payment(
+                store,
+                payment_provider,
+                company.id,
+                "PlanChange",
+                description,
+                amount_cents,
+                0,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    let updated = store.change_pricing_tier(
+        company.id,
+        &from_tier,
+        &to_tier,
+        credit_change,
+        payment
+            .as_ref()
+            .map(|(payment, receipt)| (payment.id, receipt.reference.as_str())),
+    );
+    let updated = match updated {
+        Ok(updated) => updated,
+        Err(e) => {
+            if let Some((payment, receipt)) = &payment {
+                refund_payment(store, payment_provider, payment, receipt).await;
+            }
+            return Err(match e {
+                diesel::result::Error::NotFound => AppError::Conflict(
+                    "The plan was changed by another request; please retry".to_string(),
+                ),
+                e => e.into(),
+            });
+        }
+    };
+    log::info!("Company {} changed plan from {} to {}", company.id, from_tier, to_tier);
+
+    Ok(PlanChange {
+        previous_tier: from_tier,
+        company: updated,
+        amount_cents,
+        payment: payment.map(|(payment, _)| payment),
+    })
+}
+
+pub async fn purchase_top_up(
+    store: &impl BillingStore,
+    payment_provider: &dyn PaymentProvider,
+    company: &Company,
+    pack: TopUpPack,
+) -> Result<(Payment, Company), AppError> {
+    if PricingTier::from_str(&company.pricing_tier).is_unlimited() {
+        return Err(AppError::Validation(
+            "Enterprise plans have unlimited credits".to_string(),
+        ));
+    }
+
+    let (payment, receipt) = take_payment(
+        store,
+        payment_provider,
+        company.id,
+        "TopUp",
+        format!("Top-up pack {} ({} credits)", pack.name(), pack.credits()),
+        pack.price_cents(),
+        pack.credits(),
+    )
+    .await?;
+
+    let updated = match store.add_topup_credits(company.id, payment.id, &receipt.reference) {
+        Ok(updated) => updated,
+        Err(e) => {
+            refund_payment(store, payment_provider, &payment, &receipt).await;
+            return Err(e.into());
+        }
+    };
+    log::info!(
+        "Company {} bought top-up pack {} ({} credits)",
+        company.id,
+        pack.name(),
+        pack.credits()
+    );
+
+    Ok((payment, updated))
+}
+
+// Records the payment as Pending before calling the provider, so a charge is never taken
+// without a row to reconcile it against
+async fn take_payment(
+    store: &impl BillingStore,
+    payment_provider: &dyn PaymentProvider,
+    company_id: i64,
+    kind: &str,
+    description: String,
+    amount_cents: i64,
+    credits: i64,
+) -> Result<(Payment, PaymentReceipt), AppError> {
+    let now = Utc::now();
+    let payment = store.create_payment(NewPayment {
+        company_id,
+        kind: kind.to_string(),
+        description: description.clone(),
+        amount_cents,
+        currency: CURRENCY.to_string(),
+        credits,
+        provider: payment_provider.name().to_string(),
+        status: "Pending".to_string(),
+        created_at: now,
+        updated_at: now,
+    })?;
+
+    let charge = ChargeRequest {
+        company_id,
+        amount_cents,
+        currency: CURRENCY.to_string(),
+        description,
+        idempotency_key: format!("payment_{}", payment.id),
+    };
+    match payment_provider.charge(&charge).await {
+        Ok(receipt) => Ok((payment, receipt)),
+        Err(e) => {
+            log::warn!("Payment {} for company {} failed: {}", payment.id, company_id, e);
+            store.update_payment_status(payment.id, "Failed", None, Some(&e.to_string()))?;
+            Err(e.into())
+        }
+    }
+}
+
+// Gives the money back when the charge went through but the change it paid for could not be
+// applied. A failed refund leaves the payment Succeeded with the error for manual follow-up
+async fn refund_payment(
+    store: &impl BillingStore,
+    payment_provider: &dyn PaymentProvider,
+    payment: &Payment,
+    receipt: &PaymentReceipt,
+) {
+    let (status, error) = match payment_provider
+        .refund(&receipt.reference, payment.amount_cents)
+        .await
+    {
+        Ok(()) => ("Refunded", None),
+        Err(e) => {
+            log::error!("Failed to refund payment {}: {}", payment.id, e);
+            ("Succeeded", Some(format!("Refund failed: {}", e)))
+        }
+    };
+
+    if let Err(e) = store.update_payment_status(
+        payment.id,
+        status,
+        Some(&receipt.reference),
+        error.as_deref(),
+    ) {
+        log::error!("Failed to update payment {}: {:?}", payment.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::payments::{
+        DisabledPaymentProvider, FakeOutcome, FakePaymentProvider, PaymentError,
+    };
+    use std::cell::RefCell;
+
+    struct MemoryStore {
+        company: RefCell<Company>,
+        payments: RefCell<Vec<Payment>>,
+        // Simulates another request changing the plan between pricing and applying it
+        lose_tier_race: bool,
+    }
+
+    impl MemoryStore {
+        fn new(pricing_tier: &str, api_credits: i64) -> Self {
+            MemoryStore {
+                company: RefCell::new(Company {
+                    id: 1,
+                    company_name: "Acme".to_string(),
+                    company_address: None,
+                    website: None,
+                    sending_domain: None,
+                    default_from_name: None,
+                    default_from_email: None,
+                    owner_id: 1,
+                    industry_id: None,
+                    pricing_tier: pricing_tier.to_string(),
+                    api_credits,
+                    credits_reset_date: Utc::now() + chrono::Duration::days(15),
+                    max_message_bytes: None,
+                    topup_credits: 0,
+                }),
+                payments: RefCell::new(Vec::new()),
+                lose_tier_race: false,
+            }
+        }
+
+        fn company(&self) -> Company {
+            self.company.borrow().clone()
+        }
+
+        fn payment_statuses(&self) -> Vec<String> {
+            self.payments.borrow().iter().map(|p| p.status.clone()).collect()
+        }
+    }
+
+    impl BillingStore for MemoryStore {
+        fn create_payment(&self, new_payment: NewPayment) -> Result<Payment, diesel::result::Error> {
+            let mut payments = self.payments.borrow_mut();
+            let payment = Payment {
+                id: payments.len() as i64 + 1,
+                company_id: new_payment.company_id,
+                kind: new_payment.kind,
+                description: new_payment.description,
+                amount_cents: new_payment.amount_cents,
+                currency: new_payment.currency,
+                credits: new_payment.credits,
+                provider: new_payment.provider,
+                provider_reference: None,
+                status: new_payment.status,
+                error: None,
+                created_at: new_payment.created_at,
+                updated_at: new_payment.updated_at,
+            };
+            payments.push(payment.clone());
+            Ok(payment)
+        }
+
+        fn update_payment_status(
+            &self,
+            payment_id: i64,
+            status: &str,
+            provider_reference: Option<&str>,
+            error: Option<&str>,
+        ) -> Result<Payment, diesel::result::Error> {
+            let mut payments = self.payments.borrow_mut();
+            let payment = payments
+                .iter_mut()
+                .find(|p| p.id == payment_id)
+                .ok_or(diesel::result::Error::NotFound)?;
+            payment.status = status.to_string();
+            payment.provider_reference = provider_reference.map(str::to_string);
+            payment.error = error.map(str::to_string);
+            Ok(payment.clone())
+        }
+
+        fn change_pricing_tier(
+            &self,
+            _company_id: i64,
+            from_tier: &str,
+            to_tier: &str,
+            credit_change: PlanCreditChange,
+            payment: Option<(i64, &str)>,
+        ) -> Result<Company, diesel::result::Error> {
+            if self.lose_tier_race || self.company.borrow().pricing_tier != from_tier {
+                return Err(diesel::result::Error::NotFound);
+            }
+            {
+                let mut company = self.company.borrow_mut();
+                company.pricing_tier = to_tier.to_string();
+                company.api_credits = match credit_change {
+                    PlanCreditChange::Grant(credits) => company.api_credits + credits,
+                    PlanCreditChange::Cap(credits) => company.api_credits.min(credits),
+                };
+            }
+            if let Some((payment_id, reference)) = payment {
+                self.update_payment_status(payment_id, "Succeeded", Some(reference), None)?;
+            }
+            Ok(self.company())
+        }
+
+        fn add_topup_credits(
+            &self,
+            _company_id: i64,
+            payment_id: i64,
+            provider_reference: &str,
+        ) -> Result<Company, diesel::result::Error> {
+            let credits = self
+                .payments
+                .borrow()
+                .iter()
+                .find(|p| p.id == payment_id && p.status == "Pending")
+                .map(|p| p.credits)
+                .ok_or(diesel::result::Error::NotFound)?;
+            self.update_payment_status(payment_id, "Succeeded", Some(provider_reference), None)?;
+            self.company.borrow_mut().topup_credits += credits;
+            Ok(self.company())
+        }
+    }
+
+    #[tokio::test]
+    async fn top_up_adds_credits_once_the_charge_succeeds() {
+        let store = MemoryStore::new("developer", 100);
+        let provider = FakePaymentProvider::new(FakeOutcome::Approve);
+
+        let (payment, company) =
+            purchase_top_up(&store, &provider, &store.company(), TopUpPack::Small).await.unwrap();
+
+        assert_eq!(payment.amount_cents, 1_000);
+        assert_eq!(company.topup_credits, 5_000);
+        assert_eq!(company.api_credits, 100);
+        assert_eq!(store.payment_statuses(), ["Succeeded"]);
+    }
+
+    #[tokio::test]
+    async fn declined_top_up_adds_nothing() {
+        let store = MemoryStore::new("free", 100);
+        let provider = FakePaymentProvider::new(FakeOutcome::Decline);
+
+        let result = purchase_top_up(&store, &provider, &store.company(), TopUpPack::Large).await;
+
+        assert!(matches!(result, Err(AppError::Payment(PaymentError::Declined(_)))));
+        assert_eq!(store.company().topup_credits, 0);
+        assert_eq!(store.payment_statuses(), ["Failed"]);
+    }
+
+    #[tokio::test]
+    async fn enterprise_cannot_buy_top_ups() {
+        let store = MemoryStore::new("enterprise", 0);
+        let provider = FakePaymentProvider::new(FakeOutcome::Approve);
+
+        let result = purchase_top_up(&store, &provider, &store.company(), TopUpPack::Small).await;
+
+        assert!(matches!(result, Err(AppError::Validation(_))));
+        assert!(store.payment_statuses().is_empty());
+    }
+
+    #[tokio::test]
+    async fn upgrade_charges_and_grants_the_rest_of_the_cycle() {
+        let store = MemoryStore::new("free", 400);
+        let provider = FakePaymentProvider::new(FakeOutcome::Approve);
+
+        let change = change_plan(&store, &provider, &store.company(), PricingTier::Developer)
+            .await
+            .unwrap();
+
+        assert_eq!(change.previous_tier, "free");
+        assert_eq!(change.company.pricing_tier, "developer");
+        // About half the cycle is left, so about half the price and half the extra 9,000 credits
+        assert!((900..=1_100).contains(&change.amount_cents), "{}", change.amount_cents);
+        let granted = change.company.api_credits - 400;
+        assert!((4_000..=5_000).contains(&granted), "{}", granted);
+        assert_eq!(change.payment.unwrap().amount_cents, change.amount_cents);
+        assert_eq!(store.payment_statuses(), ["Succeeded"]);
+    }
+
+    #[tokio::test]
+    async fn downgrade_is_free_and_caps_the_balance() {
+        let store = MemoryStore::new("developer", 8_000);
+        let provider = FakePaymentProvider::new(FakeOutcome::Decline);
+
+        let change = change_plan(&store, &provider, &store.company(), PricingTier::Free)
+            .await
+            .unwrap();
+
+        assert_eq!(change.amount_cents, 0);
+        assert!(change.payment.is_none());
+        assert_eq!(change.company.api_credits, 1_000);
+        assert!(store.payment_statuses().is_empty());
+    }
+
+    #[tokio::test]
+    async fn declined_upgrade_keeps_the_plan() {
+        let store = MemoryStore::new("free", 400);
+        let provider = FakePaymentProvider::new(FakeOutcome::Decline);
+
+        let result = change_plan(&store, &provider, &store.company(), PricingTier::Developer).await;
+
+        assert!(matches!(result, Err(AppError::Payment(PaymentError::Declined(_)))));
+        assert_eq!(store.company().pricing_tier, "free");
+        assert_eq!(store.payment_statuses(), ["Failed"]);
+    }
+
+    #[tokio::test]
+    async fn upgrade_lost_to_a_concurrent_change_is_refunded() {
+        let mut store = MemoryStore::new("free", 400);
+        store.lose_tier_race = true;
+        let provider = FakePaymentProvider::new(FakeOutcome::Approve);
+
+        let result = change_plan(&store, &provider, &store.company(), PricingTier::Developer).await;
+
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+        assert_eq!(store.payment_statuses(), ["Refunded"]);
+    }
+
+    #[tokio::test]
+    async fn same_plan_and_enterprise_changes_are_rejected() {
+        let store = MemoryStore::new("developer", 0);
+        let provider = FakePaymentProvider::new(FakeOutcome::Approve);
+
+        let same = change_plan(&store, &provider, &store.company(), PricingTier::Developer).await;
+        assert!(matches!(same, Err(AppError::Conflict(_))));
+
+        let enterprise =
+            change_plan(&store, &provider, &store.company(), PricingTier::Enterprise).await;
+        assert!(matches!(enterprise, Err(AppError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn unconfigured_provider_refuses_paid_changes() {
+        let store = MemoryStore::new("free", 0);
+        let provider = DisabledPaymentProvider;
+
+        let upgrade = change_plan(&store, &provider, &store.company(), PricingTier::Developer).await;
+        assert!(matches!(upgrade, Err(AppError::Payment(PaymentError::Unavailable(_)))));
+
+        let top_up = purchase_top_up(&store, &provider, &store.company(), TopUpPack::Small).await;
+        assert!(matches!(top_up, Err(AppError::Payment(PaymentError::Unavailable(_)))));
+        assert_eq!(store.company().pricing_tier, "free");
+        assert_eq!(store.company().topup_credits, 0);
+    }
+}
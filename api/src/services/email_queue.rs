@@ -0,0 +1,520 @@
+use crate::models::users::{
+    EmailJob, EmailLog, EmailLogAttempt, NewEmailEvent, NewEmailJob, NewUsageDaily,
+};
+use crate::repositories::{users::UserRepository, RepositoryFactory};
+use crate::services::background::supervise;
+use crate::services::email_service::{EmailService, OutboundEmail, SendError};
+use crate::services::smtp_failover;
+use crate::services::webhooks::{self, email_event_data};
+use crate::utils::utils::get_env;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    pub workers: usize,
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    pub lease: Duration,
+    pub retry_schedule: Vec<Duration>,
+}
+
+impl QueueConfig {
+    pub fn from_env() -> Self {
+        QueueConfig {
+            workers: get_env("EMAIL_QUEUE_WORKERS", "2").parse().unwrap_or(2),
+            poll_interval: Duration::from_millis(
+                get_env("EMAIL_QUEUE_POLL_MS", "1000").parse().unwrap_or(1000),
+            ),
+            batch_size: get_env("EMAIL_QUEUE_BATCH_SIZE", "10").parse().unwrap_or(10),
+            lease: Duration::from_secs(
+                get_env("EMAIL_QUEUE_LEASE_SECS", "300").parse().unwrap_or(300),
+            ),
+            retry_schedule: parse_retry_schedule(&get_env(
+                "EMAIL_RETRY_SCHEDULE",
+                "60,300,1800,7200,21600",
+            )),
+        }
+    }
+
+    // Delay before the next attempt, or None once the schedule is exhausted
+    pub fn retry_delay(&self, attempts: i32) -> Option<Duration> {
+        let index = usize::try_from(attempts).ok()?.checked_sub(1)?;
+        self.retry_schedule.get(index).copied()
+    }
+}
+
+// Comma-separated delays in seconds, e.g. "60,300,1800"
+pub fn parse_retry_schedule(value: &str) -> Vec<Duration> {
+    value
+        .split(',')
+        .filter_map(|delay| delay.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .collect()
+}
+
+pub fn new_email_job(
+    message_id: &str,
+    company_id: i64,
+    smtp_profile_id: i64,
+    email: &OutboundEmail,
+    send_at: Option<DateTime<Utc>>,
+    api_key_id: Option<i64>,
+) -> NewEmailJob {
+    let now = Utc::now();
+    // Scheduled jobs are left alone by the workers until the scheduler releases them
+    let status = if send_at.is_some() { "Scheduled" } else { "Pending" };
+    NewEmailJob {
+        message_id: message_id.to_string(),
+        company_id,
+        smtp_profile_id: Some(smtp_profile_id),
+        payload: serde_json::to_value(email).unwrap(),
+        status: status.to_string(),
+        attempts: 0,
+        available_at: send_at.unwrap_or(now),
+        created_at: now,
+        updated_at: now,
+        api_key_id,
+    }
+}
+
+// Timeline-only events; the others share their names with webhook events
+pub const EVENT_DEFERRED: &str = "deferred";
+pub const EVENT_SCHEDULED: &str = "scheduled";
+pub const EVENT_CANCELLED: &str = "cancelled";
+
+// Status as shown to API clients: the log's "Success" reads as "sent"
+pub fn public_status(status: Option<&str>) -> String {
+    match status {
+        Some("Success") => "sent".to_string(),
+        Some(status) => status.to_lowercase(),
+        None => "unknown".to_string(),
+    }
+}
+
+// How long an Idempotency-Key and its stored response are honoured
+pub fn idempotency_key_ttl() -> chrono::Duration {
+    chrono::Duration::hours(get_env("IDEMPOTENCY_KEY_TTL_HOURS", "24").parse().unwrap_or(24))
+}
+
+// How long a reservation whose request never stored a response blocks the key. A request that
+// crashed between reserving and completing would otherwise hold it for the whole TTL
+pub fn idempotency_key_lease() -> chrono::Duration {
+    chrono::Duration::minutes(get_env("IDEMPOTENCY_KEY_LEASE_MINUTES", "5").parse().unwrap_or(5))
+}
+
+pub fn record_email_event(
+    user_repo: &impl UserRepository,
+    company_id: i64,
+    message_id: &str,
+    event: &str,
+    error: Option<&SendError>,
+) {
+    let new_event = NewEmailEvent {
+        message_id: message_id.to_string(),
+        company_id,
+        event: event.to_string(),
+        smtp_code: error.and_then(|e| e.smtp_code()).map(i32::from),
+        detail: error.map(|e| e.message().to_string()),
+        created_at: Utc::now(),
+    };
+
+    if let Err(e) = user_repo.create_email_event(new_event) {
+        log::error!("Failed to record {} event for message {}: {:?}", event, message_id, e);
+    }
+}
+
+pub async fn start_email_workers(repo_factory: RepositoryFactory) {
+    let config = QueueConfig::from_env();
+    log::info!(
+        "Starting {} email queue workers (poll every {:?})",
+        config.workers,
+        config.poll_interval
+    );
+
+    recover_on_boot(&repo_factory, &config);
+
+    for index in 0..config.workers {
+        let worker_id = format!("worker-{}-{}", std::process::id(), index);
+        let repo_factory = repo_factory.clone();
+        let config = config.clone();
+        tokio::spawn(supervise("email queue worker", move || {
+            run_worker(worker_id.clone(), repo_factory.clone(), config.clone())
+        }));
+    }
+    let scheduler_factory = repo_factory.clone();
+    let scheduler_config = config.clone();
+    tokio::spawn(supervise("email scheduler", move || {
+        run_scheduler(scheduler_factory.clone(), scheduler_config.clone())
+    }));
+
+    supervise("email queue maintenance", move || {
+        run_maintenance(repo_factory.clone(), config.clone())
+    })
+    .await;
+}
+
+// Keeps releasing jobs whose worker died mid-send, and drops idempotency keys past their window
+async fn run_maintenance(repo_factory: RepositoryFactory, config: QueueConfig) {
+    let mut interval = tokio::time::interval(config.lease);
+    loop {
+        interval.tick().await;
+        release_stale_jobs(&repo_factory, &config);
+        purge_idempotency_keys(&repo_factory);
+    }
+}
+
+fn recover_on_boot(repo_factory: &RepositoryFactory, config: &QueueConfig) {
+    release_stale_jobs(repo_factory, config);
+
+    // Rows queued before the durable queue existed have no job; give them one
+    let user_repo = repo_factory.create_user_repository();
+    let orphaned = match user_repo.get_orphaned_queued_email_logs() {
+        Ok(logs) => logs,
+        Err(e) => {
+            log::error!("Failed to load orphaned queued emails: {:?}", e);
+            return;
+        }
+    };
+
+    for email_log in orphaned {
+        requeue_orphaned_log(repo_factory, email_log);
+    }
+}
+
+fn release_stale_jobs(repo_factory: &RepositoryFactory, config: &QueueConfig) {
+    let user_repo = repo_factory.create_user_repository();
+    let locked_before = Utc::now() - chrono::Duration::from_std(config.lease).unwrap();
+
+    match user_repo.release_stale_email_jobs(locked_before) {
+        Ok(0) => {}
+        Ok(count) => log::warn!("Released {} stale email jobs back to the queue", count),
+        Err(e) => log::error!("Failed to release stale email jobs: {:?}", e),
+    }
+}
+
+fn purge_idempotency_keys(repo_factory: &RepositoryFactory) {
+    let user_repo = repo_factory.create_user_repository();
+
+    match user_repo.purge_expired_idempotency_keys(Utc::now() - idempotency_key_ttl()) {
+        Ok(0) => {}
+        Ok(count) => log::debug!("Purged {} expired idempotency keys", count),
+        Err(e) => log::error!("Failed to purge expired idempotency keys: {:?}", e),
+    }
+}
+
+fn requeue_orphaned_log(repo_factory: &RepositoryFactory, email_log: EmailLog) {
+    let user_repo = repo_factory.create_user_repository();
+
+    let smtp_profile = match user_repo.get_default_smtp_profile(email_log.company_id) {
+        Ok(profile) => profile,
+        Err(e) => {
+            log::error!(
+                "No default SMTP profile to resume email log ID {}: {:?}",
+                email_log.id,
+                e
+            );
+            if let Err(e) = user_repo.update_email_log_status(email_log.id, "Failed") {
+                log::error!("Failed to update email log status for ID {}: {:?}", email_log.id, e);
+            }
+            return;
+        }
+    };
+
+    let email = OutboundEmail {
+        is_html: email_log.body.trim_start().starts_with('<'),
+        from: email_log.from_email,
+        to: vec![email_log.to_email],
+        cc: Vec::new(),
+        bcc: Vec::new(),
+        reply_to: None,
+        subject: email_log.subject,
+        content: email_log.body,
+        text: None,
+        attachments: Vec::new(),
+    };
+    let message_id = format!("msg_{}", Uuid::new_v4().simple());
+    let new_job = new_email_job(
+        &message_id,
+        email_log.company_id,
+        smtp_profile.id,
+        &email,
+        None,
+        None,
+    );
+
+    match user_repo.requeue_email_log(email_log.id, new_job) {
+        Ok(_) => log::info!("Resumed queued email log ID: {}", email_log.id),
+        Err(e) => log::error!("Failed to resume email log ID {}: {:?}", email_log.id, e),
+    }
+}
+
+// Moves scheduled messages into the queue once their send time arrives
+async fn run_scheduler(repo_factory: RepositoryFactory, config: QueueConfig) {
+    loop {
+        let user_repo = repo_factory.create_user_repository();
+        let jobs = match user_repo.release_scheduled_email_jobs(config.batch_size) {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                log::error!("Failed to release scheduled email jobs: {:?}", e);
+                Vec::new()
+            }
+        };
+
+        if jobs.is_empty() {
+            tokio::time::sleep(config.poll_interval).await;
+            continue;
+        }
+
+        for job in jobs {
+            log::info!("Scheduled email {} released to the queue", job.message_id);
+            record_email_event(&user_repo, job.company_id, &job.message_id, webhooks::EVENT_QUEUED, None);
+            let event_data = match serde_json::from_value::<OutboundEmail>(job.payload) {
+                Ok(email) => email_event_data(&job.message_id, &email),
+                Err(_) => serde_json::json!({ "message_id": job.message_id }),
+            };
+            webhooks::dispatch_event(&repo_factory, job.company_id, webhooks::EVENT_QUEUED, event_data);
+        }
+    }
+}
+
+async fn run_worker(worker_id: String, repo_factory: RepositoryFactory, config: QueueConfig) {
+    log::debug!("Email queue worker {} started", worker_id);
+    let email_service = EmailService::new();
+
+    loop {
+        let user_repo = repo_factory.create_user_repository();
+        let job = match user_repo.claim_email_job(&worker_id) {
+            Ok(job) => job,
+            Err(e) => {
+                log::error!("Worker {} failed to claim an email job: {:?}", worker_id, e);
+                None
+            }
+        };
+
+        match job {
+            Some(job) => process_job(&repo_factory, &email_service, &config, &worker_id, job).await,
+            None => tokio::time::sleep(config.poll_interval).await,
+        }
+    }
+}
+
+async fn process_job(
+    repo_factory: &RepositoryFactory,
+    email_service: &EmailService,
+    config: &QueueConfig,
+    worker_id: &str,
+    job: EmailJob,
+) {
+    let user_repo = repo_factory.create_user_repository();
+
+    let email = serde_json::from_value::<OutboundEmail>(job.payload.clone());
+    let (result, smtp_profile_id) = match &email {
+        Ok(email) => send_with_failover(&user_repo, email_service, &job, email).await,
+        Err(e) => (Err(SendError::permanent(format!("Invalid job payload: {}", e))), None),
+    };
+    let mut event_data = match &email {
+        Ok(email) => email_event_data(&job.message_id, email),
+        Err(_) => serde_json::json!({ "message_id": job.message_id }),
+    };
+    event_data["attempts"] = job.attempts.into();
+
+    match result {
+        Ok(_) => {
+            record_attempt(&user_repo, &job, smtp_profile_id, "Success", None, None);
+            log_job_update(&job, "complete", user_repo.complete_email_job(job.id, worker_id));
+            log::info!("Email sent successfully for message: {}", job.message_id);
+            if let Ok(email) = &email {
+                record_usage(&user_repo, &job, smtp_profile_id, email);
+            }
+            record_email_event(&user_repo, job.company_id, &job.message_id, webhooks::EVENT_SENT, None);
+            webhooks::dispatch_event(repo_factory, job.company_id, webhooks::EVENT_SENT, event_data);
+        }
+        Err(error) => {
+            let retry_delay = if error.is_transient() {
+                config.retry_delay(job.attempts)
+            } else {
+                None
+            };
+
+            match retry_delay {
+                Some(delay) => {
+                    let next_retry_at = Utc::now() + chrono::Duration::from_std(delay).unwrap();
+                    record_attempt(
+                        &user_repo,
+                        &job,
+                        smtp_profile_id,
+                        "Deferred",
+                        Some(&error),
+                        Some(next_retry_at),
+                    );
+                    record_email_event(&user_repo, job.company_id, &job.message_id, EVENT_DEFERRED, Some(&error));
+                    log_job_update(
+                        &job,
+                        "reschedule",
+                        user_repo.retry_email_job(job.id, worker_id, next_retry_at, error.message()),
+                    );
+                    log::warn!(
+                        "Email {} deferred after attempt {}, retrying at {}: {}",
+                        job.message_id,
+                        job.attempts,
+                        next_retry_at,
+                        error
+                    );
+                }
+                None => {
+                    record_attempt(&user_repo, &job, smtp_profile_id, "Failed", Some(&error), None);
+                    log_job_update(&job, "fail", user_repo.fail_email_job(job.id, worker_id, error.message()));
+                    log::error!(
+                        "Failed to send email {} after {} attempts: {}",
+                        job.message_id,
+                        job.attempts,
+                        error
+                    );
+                    if error.is_platform_failure() {
+                        refund_credits(&user_repo, &job, &error);
+                    }
+                    let event = webhooks::failure_event(&error);
+                    record_email_event(&user_repo, job.company_id, &job.message_id, event, Some(&error));
+                    event_data["smtp_code"] = error.smtp_code().into();
+                    event_data["error"] = error.message().into();
+                    webhooks::dispatch_event(repo_factory, job.company_id, event, event_data);
+                }
+            }
+        }
+    }
+}
+
+// Tries the company's profiles in failover order, starting with the one the message was queued
+// with. Connection and other transient failures count against a profile's circuit and move on to
+// the next; a permanent rejection is final. Returns the outcome with the last profile tried
+async fn send_with_failover(
+    user_repo: &impl UserRepository,
+    email_service: &EmailService,
+    job: &EmailJob,
+    email: &OutboundEmail,
+) -> (Result<(), SendError>, Option<i64>) {
+    let profiles = match user_repo.get_smtp_profiles_by_company(job.company_id) {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            return (Err(SendError::transient(format!("Failed to load SMTP profiles: {}", e))), None)
+        }
+    };
+    if profiles.is_empty() {
+        return (Err(SendError::permanent("No SMTP profile configured")), None);
+    }
+
+    let mut last_attempt = None;
+    for smtp_profile in smtp_failover::failover_order(profiles, job.smtp_profile_id) {
+        if !smtp_failover::is_available(smtp_profile.id) {
+            log::debug!("Skipping SMTP profile {} while its circuit is open", smtp_profile.id);
+            continue;
+        }
+
+        match email_service.send_with_profile(&smtp_profile, email).await {
+            Ok(_) => {
+                smtp_failover::record_success(smtp_profile.id);
+                return (Ok(()), Some(smtp_profile.id));
+            }
+            Err(error) if error.is_transient() => {
+                smtp_failover::record_failure(smtp_profile.id);
+                log::warn!(
+                    "Email {} failed on SMTP profile {}, trying the next one: {}",
+                    job.message_id,
+                    smtp_profile.id,
+                    error
+                );
+                last_attempt = Some((error, smtp_profile.id));
+            }
+            Err(error) => return (Err(error), Some(smtp_profile.id)),
+        }
+    }
+
+    match last_attempt {
+        Some((error, smtp_profile_id)) => (Err(error), Some(smtp_profile_id)),
+        None => (
+            Err(SendError::transient(format!(
+                "All SMTP profiles for company {} are unavailable; retrying later",
+                job.company_id
+            ))),
+            None,
+        ),
+    }
+}
+
+fn record_usage(
+    user_repo: &impl UserRepository,
+    job: &EmailJob,
+    smtp_profile_id: Option<i64>,
+    email: &OutboundEmail,
+) {
+    let attachment_bytes: i64 = email
+        .attachments
+        .iter()
+        .filter_map(|attachment| attachment.decode().ok())
+        .map(|bytes| bytes.len() as i64)
+        .sum();
+    let now = Utc::now();
+    let usage = NewUsageDaily {
+        company_id: job.company_id,
+        day: now.date_naive(),
+        api_key_id: job.api_key_id,
+        smtp_profile_id,
+        emails_sent: 1,
+        recipients: (email.to.len() + email.cc.len() + email.bcc.len()) as i64,
+        attachment_bytes,
+        updated_at: now,
+    };
+
+    if let Err(e) = user_repo.record_usage(usage) {
+        log::error!("Failed to record usage for email {}: {:?}", job.message_id, e);
+    }
+}
+
+// A NotFound here means the lease ran out mid-send and another worker now holds the job
+fn log_job_update(job: &EmailJob, action: &str, result: Result<EmailJob, diesel::result::Error>) {
+    match result {
+        Ok(_) => {}
+        Err(diesel::result::Error::NotFound) => log::warn!(
+            "Could not {} email job {}: its lease expired and another worker claimed it",
+            action,
+            job.id
+        ),
+        Err(e) => log::error!("Failed to {} email job {}: {:?}", action, job.id, e),
+    }
+}
+
+fn refund_credits(user_repo: &impl UserRepository, job: &EmailJob, error: &SendError) {
+    match user_repo.refund_email_credits(job.company_id, &job.message_id, error.message()) {
+        Ok(0) => {}
+        Ok(credits) => log::info!("Refunded {} credits for failed email {}", credits, job.message_id),
+        Err(e) => log::error!("Failed to refund credits for email {}: {:?}", job.message_id, e),
+    }
+}
+
+fn record_attempt(
+    user_repo: &impl UserRepository,
+    job: &EmailJob,
+    smtp_profile_id: Option<i64>,
+    status: &str,
+    error: Option<&SendError>,
+    next_retry_at: Option<chrono::DateTime<Utc>>,
+) {
+    let attempt = EmailLogAttempt {
+        status: Some(status.to_string()),
+        attempts: job.attempts,
+        last_smtp_code: error.and_then(|e| e.smtp_code()).map(i32::from),
+        next_retry_at,
+        last_error: error.map(|e| e.message().to_string()),
+        smtp_profile_id,
+    };
+
+    if let Err(e) = user_repo.record_email_log_attempt(&job.message_id, &attempt) {
+        log::error!(
+            "Failed to update email log status for message {}: {:?}",
+            job.message_id,
+            e
+        );
+    }
+}
@@ -1,7 +1,235 @@
 use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Message, Tokio1Executor,
+    message::{header::ContentType, Attachment, MultiPart, SinglePart},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Certificate, Tls, TlsParameters},
+        response::Response,
+        AsyncSmtpTransportBuilder,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
+use crate::models::users::SmtpProfile;
+use crate::services::smtp_pool;
+use crate::utils::html::html_to_text;
+use crate::utils::utils::one_or_many;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use thiserror::Error;
+
+// A message as accepted by the public API; this is also the queue job payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboundEmail {
+    pub from: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub content: String,
+    pub is_html: bool,
+    // Plain-text alternative for HTML content; generated from the HTML when absent
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<EmailAttachment>,
+}
+
+// A base64-encoded file; with a content_id it is an inline part referenced from the HTML as cid:<id>
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailAttachment {
+    pub filename: String,
+    pub content: String,
+    pub content_type: Option<String>,
+    pub content_id: Option<String>,
+}
+
+impl EmailAttachment {
+    pub fn decode(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        STANDARD.decode(self.content.trim())
+    }
+
+    pub fn mime_type(&self) -> Result<ContentType, lettre::message::header::ContentTypeErr> {
+        ContentType::parse(
+            self.content_type
+                .as_deref()
+                .unwrap_or("application/octet-stream"),
+        )
+    }
+
+    fn to_part(&self) -> Result<SinglePart, SendError> {
+        let bytes = self.decode().map_err(SendError::permanent)?;
+        let content_type = self.mime_type().map_err(SendError::permanent)?;
+
+        let attachment = match &self.content_id {
+            Some(content_id) => Attachment::new_inline(content_id.clone()),
+            None => Attachment::new(self.filename.clone()),
+        };
+        Ok(attachment.body(bytes, content_type))
+    }
+}
+
+enum MessageBody {
+    Single(SinglePart),
+    Multi(MultiPart),
+}
+
+impl MessageBody {
+    // Wraps the current body as the first part of a new multipart container
+    fn wrap(self, builder: lettre::message::MultiPartBuilder, parts: Vec<SinglePart>) -> Self {
+        let mut multipart = match self {
+            MessageBody::Single(part) => builder.singlepart(part),
+            MessageBody::Multi(part) => builder.multipart(part),
+        };
+        for part in parts {
+            multipart = multipart.singlepart(part);
+        }
+        MessageBody::Multi(multipart)
+    }
+}
+
+// Whether a failed send is worth retrying; 4xx replies and network/TLS trouble are transient, 5xx are not
+#[derive(Error, Debug)]
+pub enum SendError {
+    #[error("Transient send failure: {message}")]
+    Transient { code: Option<u16>, message: String },
+
+    #[error("Permanent send failure: {message}")]
+    Permanent { code: Option<u16>, message: String },
+}
+
+impl SendError {
+    pub fn permanent(message: impl ToString) -> Self {
+        SendError::Permanent {
+            code: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn transient(message: impl ToString) -> Self {
+        SendError::Transient {
+            code: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SendError::Transient { .. })
+    }
+
+    pub fn smtp_code(&self) -> Option<u16> {
+        match self {
+            SendError::Transient { code, .. } | SendError::Permanent { code, .. } => *code,
+        }
+    }
+
+    // Failures on our side (connectivity, configuration, our own SMTP credentials) rather than
+    // a verdict on the message or its recipients
+    pub fn is_platform_failure(&self) -> bool {
+        match self.smtp_code() {
+            None => true,
+            Some(code) => matches!(code, 421 | 454 | 530 | 534 | 535),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            SendError::Transient { message, .. } | SendError::Permanent { message, .. } => message,
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        let code = e.status().map(u16::from);
+        let message = e.to_string();
+
+        if e.is_permanent() || e.is_client() {
+            SendError::Permanent { code, message }
+        } else {
+            // Transient replies, timeouts, TLS and connection failures
+            SendError::Transient { code, message }
+        }
+    }
+}
+
+// How a transport secures its connection, as stored in `smtpprofiles.tls_mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsMode {
+    // Plaintext on connect, then a required STARTTLS upgrade (usually port 587)
+    #[default]
+    StartTls,
+    // TLS from the first byte (usually port 465)
+    ImplicitTls,
+    // STARTTLS when the server offers it, plaintext otherwise
+    Opportunistic,
+    // Plaintext only, for trusted internal relays
+    None,
+}
+
+impl TlsMode {
+    pub const ALL: [TlsMode; 4] = [
+        TlsMode::StartTls,
+        TlsMode::ImplicitTls,
+        TlsMode::Opportunistic,
+        TlsMode::None,
+    ];
+
+    pub fn parse(mode: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == mode)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TlsMode::StartTls => "starttls",
+            TlsMode::ImplicitTls => "implicit_tls",
+            TlsMode::Opportunistic => "opportunistic",
+            TlsMode::None => "none",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    pub mode: TlsMode,
+    // PEM certificates trusted on top of the public roots
+    pub ca_certificate: Option<String>,
+    // Name the server certificate is verified against, when it differs from the host we dial
+    pub hostname: Option<String>,
+}
+
+impl TlsSettings {
+    pub fn from_profile(smtp_profile: &SmtpProfile) -> Result<Self, String> {
+        Ok(Self {
+            mode: TlsMode::parse(&smtp_profile.tls_mode)
+                .ok_or_else(|| format!("Unknown TLS mode '{}'", smtp_profile.tls_mode))?,
+            ca_certificate: smtp_profile.ca_certificate.clone(),
+            hostname: smtp_profile.tls_hostname.clone(),
+        })
+    }
+
+    fn parameters(&self, smtp_server: &str) -> Result<TlsParameters, lettre::transport::smtp::Error> {
+        let domain = self.hostname.as_deref().unwrap_or(smtp_server).to_string();
+        let mut builder = TlsParameters::builder(domain);
+        if let Some(pem) = &self.ca_certificate {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+        }
+        builder.build()
+    }
+
+    pub fn tls(&self, smtp_server: &str) -> Result<Tls, lettre::transport::smtp::Error> {
+        Ok(match self.mode {
+            TlsMode::StartTls => Tls::Required(self.parameters(smtp_server)?),
+            TlsMode::ImplicitTls => Tls::Wrapper(self.parameters(smtp_server)?),
+            TlsMode::Opportunistic => Tls::Opportunistic(self.parameters(smtp_server)?),
+            TlsMode::None => Tls::None,
+        })
+    }
+}
 
 pub struct EmailService;
 
@@ -17,12 +245,46 @@ impl EmailService {
         smtp_password: &str,
         smtp_port: u16,
     ) -> Result<AsyncSmtpTransport<Tokio1Executor>, Box<dyn std::error::Error + Send + Sync>> {
+        let mailer = Self::mailer_builder(
+            smtp_server,
+            smtp_username,
+            smtp_password,
+            smtp_port,
+            &TlsSettings::default(),
+        )?
+        .build();
+        Ok(mailer)
+    }
+
+    pub fn mailer_builder(
+        smtp_server: &str,
+        smtp_username: &str,
+        smtp_password: &str,
+        smtp_port: u16,
+        tls: &TlsSettings,
+    ) -> Result<AsyncSmtpTransportBuilder, Box<dyn std::error::Error + Send + Sync>> {
         let creds = Credentials::new(smtp_username.to_string(), smtp_password.to_string());
-        let mailer = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp_server)?
+        Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(smtp_server)
+            .tls(tls.tls(smtp_server)?)
             .credentials(creds)
-            .port(smtp_port)
-            .build();
-        Ok(mailer)
+            .port(smtp_port))
+    }
+
+    // A customer profile's transport dials `addr`, resolved and checked up front, while TLS still
+    // verifies the certificate against the profile's host name (or its TLS hostname)
+    pub fn profile_mailer_builder(
+        smtp_profile: &SmtpProfile,
+        addr: SocketAddr,
+    ) -> Result<AsyncSmtpTransportBuilder, Box<dyn std::error::Error + Send + Sync>> {
+        let tls = TlsSettings::from_profile(smtp_profile)?;
+        let creds = Credentials::new(
+            smtp_profile.smtp_username.clone(),
+            smtp_profile.smtp_password.clone(),
+        );
+        Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(addr.ip().to_string())
+            .tls(tls.tls(&smtp_profile.smtp_server)?)
+            .credentials(creds)
+            .port(addr.port()))
     }
 
     pub async fn send_email(
@@ -36,28 +298,130 @@ impl EmailService {
         subject: &str,
         content: &str,
         is_html: bool,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<(), SendError> {
+        let email = OutboundEmail {
+            from: from.to_string(),
+            to: vec![to.to_string()],
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            reply_to: None,
+            subject: subject.to_string(),
+            content: content.to_string(),
+            is_html,
+            text: None,
+            attachments: Vec::new(),
+        };
+
+        self.deliver(smtp_server, smtp_username, smtp_password, smtp_port, &email)
+            .await
+    }
+
+    // Sends over the profile's pooled transport, so bursts reuse authenticated connections.
+    // Returns the server's reply to the message
+    pub async fn send_with_profile(
+        &self,
+        smtp_profile: &SmtpProfile,
+        email: &OutboundEmail,
+    ) -> Result<Response, SendError> {
+        let mailer = smtp_pool::transport_for(smtp_profile).await?;
+        Self::send_message(&mailer, email).await
+    }
+
+    async fn deliver(
+        &self,
+        smtp_server: &str,
+        smtp_username: &str,
+        smtp_password: &str,
+        smtp_port: Option<u16>,
+        email: &OutboundEmail,
+    ) -> Result<(), SendError> {
         let port = smtp_port.unwrap_or(587);
-        let mailer = Self::create_mailer(smtp_server, smtp_username, smtp_password, port)?;
+        let mailer = Self::create_mailer(smtp_server, smtp_username, smtp_password, port)
+            .map_err(SendError::permanent)?;
+
+        // Internal mail (verification, invites) goes out as the SMTP account itself; only the
+        // public API sets its own From
+        let email = OutboundEmail {
+            from: smtp_username.to_string(),
+            ..email.clone()
+        };
+        Self::send_message(&mailer, &email).await.map(|_| ())
+    }
+
+    pub async fn send_message(
+        mailer: &AsyncSmtpTransport<Tokio1Executor>,
+        email: &OutboundEmail,
+    ) -> Result<Response, SendError> {
+        let message = Self::build_message(email)?;
+
+        mailer.send(message).await.map_err(|e| {
+            log::error!("Failed to send email: {}", e);
+            SendError::from(e)
+        })
+    }
+
+    fn build_message(email: &OutboundEmail) -> Result<Message, SendError> {
+        let mut builder = Message::builder()
+            .from(email.from.parse().map_err(SendError::permanent)?)
+            .subject(&email.subject);
+
+        for to in &email.to {
+            builder = builder.to(to.parse().map_err(SendError::permanent)?);
+        }
+        for cc in &email.cc {
+            builder = builder.cc(cc.parse().map_err(SendError::permanent)?);
+        }
+        // Bcc recipients only go on the envelope; lettre drops the header when formatting
+        for bcc in &email.bcc {
+            builder = builder.bcc(bcc.parse().map_err(SendError::permanent)?);
+        }
+        if let Some(reply_to) = &email.reply_to {
+            builder = builder.reply_to(reply_to.parse().map_err(SendError::permanent)?);
+        }
 
-        let content_type = if is_html {
-            ContentType::TEXT_HTML
+        let message = match Self::build_body(email)? {
+            MessageBody::Single(part) => builder.singlepart(part),
+            MessageBody::Multi(part) => builder.multipart(part),
+        };
+        message.map_err(SendError::permanent)
+    }
+
+    // multipart/mixed( multipart/related( multipart/alternative( text, html ), inline images ), attachments )
+    fn build_body(email: &OutboundEmail) -> Result<MessageBody, SendError> {
+        let mut body = if email.is_html {
+            let text = email
+                .text
+                .clone()
+                .unwrap_or_else(|| html_to_text(&email.content));
+            MessageBody::Multi(MultiPart::alternative_plain_html(
+                text,
+                email.content.clone(),
+            ))
         } else {
-            ContentType::TEXT_PLAIN
+            MessageBody::Single(SinglePart::plain(email.content.clone()))
         };
 
-        let email = Message::builder()
-            .from(smtp_username.parse()?)
-            .to(to.parse()?)
-            .subject(subject)
-            .header(content_type)
-            .body(content.to_string())?;
+        let (inline, attached): (Vec<&EmailAttachment>, Vec<&EmailAttachment>) = email
+            .attachments
+            .iter()
+            .partition(|attachment| attachment.content_id.is_some());
 
-        let err = mailer.send(email).await;
-        if let Err(e) = err {
-            println!("Failed to send email: {}", e);
-            return Err(Box::new(e));
+        if !inline.is_empty() {
+            let parts = inline
+                .into_iter()
+                .map(EmailAttachment::to_part)
+                .collect::<Result<Vec<_>, _>>()?;
+            body = body.wrap(MultiPart::related(), parts);
         }
-        Ok(())
+
+        if !attached.is_empty() {
+            let parts = attached
+                .into_iter()
+                .map(EmailAttachment::to_part)
+                .collect::<Result<Vec<_>, _>>()?;
+            body = body.wrap(MultiPart::mixed(), parts);
+        }
+
+        Ok(body)
     }
 }
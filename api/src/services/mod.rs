@@ -1 +1,10 @@
-pub mod email_service;
\ No newline at end of file
+pub mod background;
+pub mod billing;
+pub mod email_queue;
+pub mod email_service;
+pub mod payments;
+pub mod smtp_diagnostics;
+pub mod smtp_failover;
+pub mod smtp_pool;
+pub mod smtp_routing;
+pub mod webhooks;
\ No newline at end of file
@@ -0,0 +1,161 @@
+use crate::utils::utils::get_env;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct ChargeRequest {
+    pub company_id: i64,
+    pub amount_cents: i64,
+    pub currency: String,
+    pub description: String,
+    // Our payment id, so a retried charge is never billed twice
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentReceipt {
+    pub reference: String,
+}
+
+#[derive(Error, Debug)]
+pub enum PaymentError {
+    #[error("Payment declined: {0}")]
+    Declined(String),
+
+    #[error("Payment provider unavailable: {0}")]
+    Unavailable(String),
+}
+
+// Everything billing needs from a card processor. Handlers take it as `web::Data<dyn PaymentProvider>`
+// so the provider can be swapped for the local fake
+pub trait PaymentProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn charge<'a>(
+        &'a self,
+        request: &'a ChargeRequest,
+    ) -> BoxFuture<'a, Result<PaymentReceipt, PaymentError>>;
+
+    fn refund<'a>(
+        &'a self,
+        reference: &'a str,
+        amount_cents: i64,
+    ) -> BoxFuture<'a, Result<(), PaymentError>>;
+}
+
+// What the fake answers to every charge, so both the happy path and failures can be exercised locally
+#[derive(Debug, Clone, Copy)]
+pub enum FakeOutcome {
+    Approve,
+    Decline,
+    Unavailable,
+}
+
+// Settles charges in-process without talking to a card processor
+pub struct FakePaymentProvider {
+    outcome: FakeOutcome,
+}
+
+impl FakePaymentProvider {
+    pub fn new(outcome: FakeOutcome) -> Self {
+        Self { outcome }
+    }
+}
+
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn charge<'a>(
+        &'a self,
+        request: &'a ChargeRequest,
+    ) -> BoxFuture<'a, Result<PaymentReceipt, PaymentError>> {
+        Box::pin(async move {
+            match self.outcome {
+                FakeOutcome::Approve => {}
+                FakeOutcome::Decline => {
+                    return Err(PaymentError::Declined("card declined".to_string()))
+                }
+                FakeOutcome::Unavailable => {
+                    return Err(PaymentError::Unavailable("fake provider is down".to_string()))
+                }
+            }
+
+            log::info!(
+                "Fake payment provider charged company {} {} {} for '{}' ({})",
+                request.company_id,
+                request.amount_cents,
+                request.currency,
+                request.description,
+                request.idempotency_key
+            );
+            Ok(PaymentReceipt {
+                reference: format!("fake_{}", Uuid::new_v4().simple()),
+            })
+        })
+    }
+
+    fn refund<'a>(
+        &'a self,
+        reference: &'a str,
+        amount_cents: i64,
+    ) -> BoxFuture<'a, Result<(), PaymentError>> {
+        Box::pin(async move {
+            log::info!("Fake payment provider refunded {} on {}", amount_cents, reference);
+            Ok(())
+        })
+    }
+}
+
+// Stands in when no provider is configured: every charge is refused, so billing writes fail
+// closed instead of handing out plans and credits for free
+pub struct DisabledPaymentProvider;
+
+impl PaymentProvider for DisabledPaymentProvider {
+    fn name(&self) -> &'static str {
+        "disabled"
+    }
+
+    fn charge<'a>(
+        &'a self,
+        _request: &'a ChargeRequest,
+    ) -> BoxFuture<'a, Result<PaymentReceipt, PaymentError>> {
+        Box::pin(async {
+            Err(PaymentError::Unavailable("no payment provider is configured".to_string()))
+        })
+    }
+
+    fn refund<'a>(
+        &'a self,
+        _reference: &'a str,
+        _amount_cents: i64,
+    ) -> BoxFuture<'a, Result<(), PaymentError>> {
+        Box::pin(async {
+            Err(PaymentError::Unavailable("no payment provider is configured".to_string()))
+        })
+    }
+}
+
+// The fake is only used when asked for by name; with PAYMENT_PROVIDER unset, paid changes are
+// refused
+pub fn payment_provider_from_env() -> Arc<dyn PaymentProvider> {
+    match get_env("PAYMENT_PROVIDER", "").as_str() {
+        "" => {
+            log::warn!("PAYMENT_PROVIDER is not set; plan upgrades and top-ups are disabled");
+            Arc::new(DisabledPaymentProvider)
+        }
+        "fake" => {
+            let outcome = match get_env("FAKE_PAYMENT_OUTCOME", "approve").as_str() {
+                "decline" => FakeOutcome::Decline,
+                "unavailable" => FakeOutcome::Unavailable,
+                _ => FakeOutcome::Approve,
+            };
+            log::warn!("Using the fake payment provider ({:?}); no real charges are made", outcome);
+            Arc::new(FakePaymentProvider::new(outcome))
+        }
+        provider => panic!("Unsupported PAYMENT_PROVIDER '{}'", provider),
+    }
+}
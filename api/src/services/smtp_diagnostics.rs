@@ -0,0 +1,514 @@
+use crate::models::users::SmtpProfile;
+use crate::services::email_service::{EmailService, OutboundEmail, TlsMode, TlsSettings};
+use crate::utils::network::resolve_smtp_host;
+use crate::utils::utils::get_env;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lettre::transport::smtp::extension::ClientId;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+// RFC 5321 allows 512 bytes per reply line; the slack covers servers that stretch it, while a
+// server that never sends a newline can't make us buffer without bound
+const MAX_REPLY_LINE_BYTES: usize = 2048;
+const MAX_REPLY_LINES: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticStep {
+    pub step: &'static str,
+    pub success: bool,
+    pub duration_ms: u64,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiagnosticReport {
+    pub success: bool,
+    pub smtp_server: String,
+    pub smtp_port: i32,
+    pub tls_mode: String,
+    pub steps: Vec<DiagnosticStep>,
+}
+
+// A test message to send once the connection checks pass
+pub struct TestMessage {
+    pub from: String,
+    pub to: String,
+}
+
+// A multi-line SMTP reply
+#[derive(Debug)]
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn text(&self) -> String {
+        format!("{} {}", self.code, self.lines.join(" "))
+    }
+
+    // EHLO keywords, e.g. "STARTTLS" or "AUTH PLAIN LOGIN"; the first line is the greeting
+    fn capabilities(&self) -> Vec<String> {
+        self.lines.iter().skip(1).map(|line| line.to_uppercase()).collect()
+    }
+}
+
+struct Passed<T> {
+    value: T,
+    detail: String,
+    data: Option<serde_json::Value>,
+}
+
+impl<T> Passed<T> {
+    fn new(value: T, detail: impl Into<String>) -> Self {
+        Self {
+            value,
+            detail: detail.into(),
+            data: None,
+        }
+    }
+
+    fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+struct Diagnostics {
+    timeout: Duration,
+    steps: Vec<DiagnosticStep>,
+}
+
+impl Diagnostics {
+    // Runs one step under the per-step timeout and records how it went; later steps only run
+    // while this returns Some
+    async fn step<T>(
+        &mut self,
+        step: &'static str,
+        check: impl Future<Output = Result<Passed<T>, String>>,
+    ) -> Option<T> {
+        let started = Instant::now();
+        let result = tokio::time::timeout(self.timeout, check)
+            .await
+            .unwrap_or_else(|_| Err(format!("Timed out after {}s", self.timeout.as_secs())));
+        let duration_ms = started.elapsed().as_millis() as u64;
+
+        match result {
+            Ok(passed) => {
+                self.steps.push(DiagnosticStep {
+                    step,
+                    success: true,
+                    duration_ms,
+                    detail: passed.detail,
+                    data: passed.data,
+                });
+                Some(passed.value)
+            }
+            Err(detail) => {
+                self.steps.push(DiagnosticStep {
+                    step,
+                    success: false,
+                    duration_ms,
+                    detail,
+                    data: None,
+                });
+                None
+            }
+        }
+    }
+}
+
+// Walks through a connection the way the sending transport makes it: DNS, TCP connect, TLS as the
+// profile's mode asks for, EHLO and AUTH, then optionally sends a real message over the transport
+pub async fn test_smtp_profile(
+    smtp_profile: &SmtpProfile,
+    test_message: Option<TestMessage>,
+) -> DiagnosticReport {
+    let mut diagnostics = Diagnostics {
+        timeout: Duration::from_secs(get_env("SMTP_TEST_TIMEOUT_SECS", "10").parse().unwrap_or(10)),
+        steps: Vec::new(),
+    };
+
+    let connected = run_checks(&mut diagnostics, smtp_profile).await;
+    if let (Some(addr), Some(test_message)) = (connected, test_message) {
+        diagnostics
+            .step("send", send_test_message(smtp_profile, addr, test_message))
+            .await;
+    }
+
+    DiagnosticReport {
+        success: diagnostics.steps.iter().all(|step| step.success),
+        smtp_server: smtp_profile.smtp_server.clone(),
+        smtp_port: smtp_profile.smtp_port,
+        tls_mode: smtp_profile.tls_mode.clone(),
+        steps: diagnostics.steps,
+    }
+}
+
+// Returns the address that passed every check, for the test message to be sent to
+async fn run_checks(
+    diagnostics: &mut Diagnostics,
+    smtp_profile: &SmtpProfile,
+) -> Option<SocketAddr> {
+    let settings = match TlsSettings::from_profile(smtp_profile) {
+        Ok(settings) => settings,
+        Err(e) => {
+            diagnostics.step::<()>("config", async { Err(e) }).await;
+            return None;
+        }
+    };
+    let host = smtp_profile.smtp_server.as_str();
+    let port = smtp_profile.smtp_port as u16;
+    let hello = ClientId::default().to_string();
+
+    let addrs = diagnostics.step("dns", resolve(host, port)).await?;
+    let (tcp, addr) = diagnostics.step("connect", connect(addrs)).await?;
+
+    if settings.mode == TlsMode::ImplicitTls {
+        let tls = diagnostics.step("tls", handshake(tcp, &settings, host)).await?;
+        let mut stream = BufReader::new(tls);
+        diagnostics.step("greeting", greeting(&mut stream)).await?;
+        return authenticate(diagnostics, &mut stream, smtp_profile, &hello)
+            .await
+            .then_some(addr);
+    }
+
+    let mut stream = BufReader::new(tcp);
+    diagnostics.step("greeting", greeting(&mut stream)).await?;
+    let capabilities = diagnostics.step("ehlo", ehlo(&mut stream, &hello)).await?;
+
+    let offers_starttls = capabilities.iter().any(|c| c == "STARTTLS");
+    let authenticated = match settings.mode {
+        TlsMode::None => authenticate_with(diagnostics, &mut stream, smtp_profile, capabilities).await,
+        TlsMode::Opportunistic if !offers_starttls => {
+            diagnostics
+                .step("tls", async {
+                    Ok(Passed::new((), "Server does not offer STARTTLS; continuing without TLS"))
+                })
+                .await;
+            authenticate_with(diagnostics, &mut stream, smtp_profile, capabilities).await
+        }
+        _ => {
+            let upgrade = async {
+                if !offers_starttls {
+                    return Err("Server does not offer STARTTLS".to_string());
+                }
+                let reply = command(&mut stream, "STARTTLS").await?;
+                if reply.code != 220 {
+                    return Err(format!("STARTTLS refused: {}", reply.text()));
+                }
+                // Nothing may be buffered past the 220, or it would be lost with the plaintext reader
+                handshake(stream.into_inner(), &settings, host).await
+            };
+            let tls = diagnostics.step("tls", upgrade).await?;
+            authenticate(diagnostics, &mut BufReader::new(tls), smtp_profile, &hello).await
+        }
+    };
+    authenticated.then_some(addr)
+}
+
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    diagnostics: &mut Diagnostics,
+    stream: &mut BufReader<S>,
+    smtp_profile: &SmtpProfile,
+    hello: &str,
+) -> bool {
+    let Some(capabilities) = diagnostics.step("ehlo", ehlo(stream, hello)).await else {
+        return false;
+    };
+    authenticate_with(diagnostics, stream, smtp_profile, capabilities).await
+}
+
+async fn authenticate_with<S: AsyncRead + AsyncWrite + Unpin>(
+    diagnostics: &mut Diagnostics,
+    stream: &mut BufReader<S>,
+    smtp_profile: &SmtpProfile,
+    capabilities: Vec<String>,
+) -> bool {
+    let authenticated = diagnostics
+        .step("auth", auth(stream, smtp_profile, &capabilities))
+        .await
+        .is_some();
+    let _ = command(stream, "QUIT").await;
+    authenticated
+}
+
+// Held to the same address policy as sending, so a profile that passes here can also send
+async fn resolve(host: &str, port: u16) -> Result<Passed<Vec<SocketAddr>>, String> {
+    let addrs = resolve_smtp_host(host, port).await?;
+
+    let ips: Vec<String> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+    Ok(Passed::new(addrs, format!("Resolved {} to {}", host, ips.join(", ")))
+        .with_data(json!({ "addresses": ips })))
+}
+
+async fn connect(addrs: Vec<SocketAddr>) -> Result<Passed<(TcpStream, SocketAddr)>, String> {
+    let mut errors = Vec::new();
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(Passed::new((stream, addr), format!("Connected to {}", addr))),
+            Err(e) => errors.push(format!("{}: {}", addr, e)),
+        }
+    }
+    Err(format!("Could not connect ({})", errors.join("; ")))
+}
+
+fn tls_connector(settings: &TlsSettings) -> Result<TlsConnector, String> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(pem) = &settings.ca_certificate {
+        for cert in CertificateDer::pem_slice_iter(pem.as_bytes()) {
+            let cert = cert.map_err(|e| format!("Invalid CA certificate: {}", e))?;
+            roots
+                .add(cert)
+                .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+        }
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| e.to_string())?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+async fn handshake(
+    tcp: TcpStream,
+    settings: &TlsSettings,
+    host: &str,
+) -> Result<Passed<TlsStream<TcpStream>>, String> {
+    let name = settings.hostname.as_deref().unwrap_or(host).to_string();
+    let server_name =
+        ServerName::try_from(name.clone()).map_err(|e| format!("Invalid TLS hostname: {}", e))?;
+
+    let tls = tls_connector(settings)?
+        .connect(server_name, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake failed: {}", e))?;
+
+    let (_, connection) = tls.get_ref();
+    let version = connection
+        .protocol_version()
+        .map(|v| format!("{:?}", v))
+        .unwrap_or_default();
+    let cipher_suite = connection
+        .negotiated_cipher_suite()
+        .map(|s| format!("{:?}", s.suite()))
+        .unwrap_or_default();
+    let chain = connection.peer_certificates().unwrap_or_default();
+    let chain_length = chain.len();
+    let fingerprint = chain
+        .first()
+        .map(|cert| hex::encode(Sha256::digest(cert.as_ref())))
+        .unwrap_or_default();
+
+    let detail = format!("{} ({}); certificate verified for {}", version, cipher_suite, name);
+    Ok(Passed::new(tls, detail).with_data(json!({
+        "version": version,
+        "cipher_suite": cipher_suite,
+        "certificate": {
+            "verified_for": name,
+            "chain_length": chain_length,
+            "sha256_fingerprint": fingerprint,
+        },
+    })))
+}
+
+async fn greeting<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+) -> Result<Passed<()>, String> {
+    let reply = read_reply(stream).await?;
+    if reply.code != 220 {
+        return Err(format!("Unexpected greeting: {}", reply.text()));
+    }
+    Ok(Passed::new((), reply.text()))
+}
+
+async fn ehlo<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    hello: &str,
+) -> Result<Passed<Vec<String>>, String> {
+    let reply = command(stream, &format!("EHLO {}", hello)).await?;
+    if reply.code != 250 {
+        return Err(format!("EHLO rejected: {}", reply.text()));
+    }
+
+    let capabilities = reply.capabilities();
+    let detail = format!("Server supports {}", capabilities.join(", "));
+    Ok(Passed::new(capabilities.clone(), detail).with_data(json!({ "capabilities": capabilities })))
+}
+
+// Tries the same mechanisms as the sending transport, PLAIN before LOGIN
+async fn auth<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    smtp_profile: &SmtpProfile,
+    capabilities: &[String],
+) -> Result<Passed<()>, String> {
+    let mechanisms: Vec<&str> = capabilities
+        .iter()
+        .find_map(|c| c.strip_prefix("AUTH "))
+        .map(|m| m.split_whitespace().collect())
+        .unwrap_or_default();
+
+    let reply = if mechanisms.contains(&"PLAIN") {
+        let token = STANDARD.encode(format!(
+            "\0{}\0{}",
+            smtp_profile.smtp_username, smtp_profile.smtp_password
+        ));
+        (command(stream, &format!("AUTH PLAIN {}", token)).await?, "PLAIN")
+    } else if mechanisms.contains(&"LOGIN") {
+        let mut reply = command(stream, "AUTH LOGIN").await?;
+        if reply.code == 334 {
+            reply = command(stream, &STANDARD.encode(&smtp_profile.smtp_username)).await?;
+        }
+        if reply.code == 334 {
+            reply = command(stream, &STANDARD.encode(&smtp_profile.smtp_password)).await?;
+        }
+        (reply, "LOGIN")
+    } else if mechanisms.is_empty() {
+        return Err("Server does not offer AUTH on this connection".to_string());
+    } else {
+        return Err(format!(
+            "No supported AUTH mechanism (server offers {})",
+            mechanisms.join(", ")
+        ));
+    };
+
+    let (reply, mechanism) = reply;
+    let data = json!({ "mechanism": mechanism, "offered": mechanisms });
+    if reply.code != 235 {
+        return Err(format!("AUTH {} failed: {}", mechanism, reply.text()));
+    }
+    Ok(Passed::new((), format!("AUTH {} accepted: {}", mechanism, reply.text())).with_data(data))
+}
+
+async fn send_test_message(
+    smtp_profile: &SmtpProfile,
+    addr: SocketAddr,
+    test_message: TestMessage,
+) -> Result<Passed<()>, String> {
+    let email = OutboundEmail {
+        from: test_message.from,
+        to: vec![test_message.to.clone()],
+        cc: Vec::new(),
+        bcc: Vec::new(),
+        reply_to: None,
+        subject: "MailNow SMTP profile test".to_string(),
+        content: format!(
+            "This is a test message sent through the SMTP profile for {}.",
+            smtp_profile.smtp_server
+        ),
+        is_html: false,
+        text: None,
+        attachments: Vec::new(),
+    };
+
+    // A one-off transport to the address the checks passed against, so the test neither borrows a
+    // connection from the profile's pool nor leaves one behind in it
+    let mailer = EmailService::profile_mailer_builder(smtp_profile, addr)
+        .map_err(|e| e.to_string())?
+        .build();
+    let response = EmailService::send_message(&mailer, &email)
+        .await
+        .map_err(|e| e.to_string())?;
+    let reply = format!(
+        "{} {}",
+        response.code(),
+        response.message().collect::<Vec<_>>().join(" ")
+    );
+    Ok(Passed::new((), format!("Sent to {}: {}", test_message.to, reply)))
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    line: &str,
+) -> Result<Reply, String> {
+    stream
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .map_err(|e| format!("Connection lost: {}", e))?;
+    stream.flush().await.map_err(|e| format!("Connection lost: {}", e))?;
+    read_reply(stream).await
+}
+
+async fn read_reply<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+) -> Result<Reply, String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = (&mut *stream)
+            .take(MAX_REPLY_LINE_BYTES as u64 + 1)
+            .read_line(&mut line)
+            .await
+            .map_err(|e| format!("Connection lost: {}", e))?;
+        if read == 0 {
+            return Err("Server closed the connection".to_string());
+        }
+        if read > MAX_REPLY_LINE_BYTES {
+            return Err(format!("Reply line longer than {} bytes", MAX_REPLY_LINE_BYTES));
+        }
+        if lines.len() == MAX_REPLY_LINES {
+            return Err(format!("Reply longer than {} lines", MAX_REPLY_LINES));
+        }
+
+        let line = line.trim_end();
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| format!("Malformed reply: {}", line))?;
+        lines.push(line.get(4..).unwrap_or("").to_string());
+        // "250-..." continues the reply, "250 ..." ends it
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn reply_to(server_output: &[u8]) -> Result<Reply, String> {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        server.write_all(server_output).await.unwrap();
+        drop(server);
+        read_reply(&mut BufReader::new(client)).await
+    }
+
+    #[tokio::test]
+    async fn reads_multi_line_replies() {
+        let reply = reply_to(b"250-mail.example.com\r\n250-STARTTLS\r\n250 AUTH PLAIN\r\n").await.unwrap();
+        assert_eq!(reply.code, 250);
+        assert_eq!(reply.capabilities(), vec!["STARTTLS", "AUTH PLAIN"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_overlong_lines() {
+        let mut output = b"220 ".to_vec();
+        output.extend(std::iter::repeat_n(b'a', MAX_REPLY_LINE_BYTES * 4));
+        assert!(reply_to(&output).await.unwrap_err().contains("longer than"));
+    }
+
+    #[tokio::test]
+    async fn rejects_endless_continuations() {
+        let output = b"250-x\r\n".repeat(MAX_REPLY_LINES + 1);
+        assert!(reply_to(&output).await.unwrap_err().contains("longer than"));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::models::users::SmtpProfile;
+use crate::repositories::users::UserRepository;
+use crate::utils::utils::get_env;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref CIRCUITS: Mutex<HashMap<i64, Circuit>> = Mutex::new(HashMap::new());
+    static ref FAILURE_THRESHOLD: u32 =
+        get_env("SMTP_CIRCUIT_FAILURE_THRESHOLD", "5").parse().unwrap_or(5);
+    static ref COOLDOWN: Duration = Duration::from_secs(
+        get_env("SMTP_CIRCUIT_COOLDOWN_SECS", "60").parse().unwrap_or(60),
+    );
+}
+
+// Health of one profile as seen by this process
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    // Set while the circuit is open; the profile is skipped until then
+    open_until: Option<Instant>,
+}
+
+// A profile is usable while its circuit is closed, and again for a trial send once the cool-down
+// has passed. A failed trial re-opens the circuit straight away
+pub fn is_available(profile_id: i64) -> bool {
+    match CIRCUITS.lock().unwrap().get(&profile_id).and_then(|c| c.open_until) {
+        Some(open_until) => Instant::now() >= open_until,
+        None => true,
+    }
+}
+
+pub fn record_success(profile_id: i64) {
+    if CIRCUITS.lock().unwrap().remove(&profile_id).is_some_and(|c| c.open_until.is_some()) {
+        log::info!("SMTP profile {} recovered, closing its circuit", profile_id);
+    }
+}
+
+pub fn record_failure(profile_id: i64) {
+    let mut circuits = CIRCUITS.lock().unwrap();
+    let circuit = circuits.entry(profile_id).or_default();
+    circuit.consecutive_failures += 1;
+
+    if circuit.consecutive_failures >= *FAILURE_THRESHOLD {
+        circuit.open_until = Some(Instant::now() + *COOLDOWN);
+        log::warn!(
+            "SMTP profile {} failed {} times in a row, pausing it for {:?}",
+            profile_id,
+            circuit.consecutive_failures,
+            *COOLDOWN
+        );
+    }
+}
+
+// Weight every profile gets until the company sets its own
+pub const DEFAULT_WEIGHT: i32 = 1;
+
+// Order in which a company's profiles are tried: lowest priority first. Until the company changes
+// any weight, the default profile leads its priority and the others stand by in random order, as
+// mail always went through the default before failover existed. Once weights are set, profiles in
+// a priority are shuffled by weight, so a profile with twice the weight is picked first twice as
+// often, and being the default only breaks ties. Weight 0 profiles only take traffic when the
+// others are down. `preferred` (the profile a message was queued with) goes to the front
+pub fn failover_order(profiles: Vec<SmtpProfile>, preferred: Option<i64>) -> Vec<SmtpProfile> {
+    let weighted = profiles.iter().any(|profile| profile.weight != DEFAULT_WEIGHT);
+    let mut rng = rand::thread_rng();
+    // Weighted random sampling without replacement (Efraimidis-Spirakis): the higher key wins
+    let mut keyed: Vec<(f64, SmtpProfile)> = profiles
+        .into_iter()
+        .map(|profile| {
+            let key = if profile.weight > 0 {
+                rng.gen::<f64>().powf(1.0 / profile.weight as f64)
+            } else {
+                -1.0
+            };
+            (key, profile)
+        })
+        .collect();
+
+    keyed.sort_by(|(key_a, a), (key_b, b)| {
+        let by_default = b.is_default.cmp(&a.is_default);
+        let by_key = key_b.total_cmp(key_a);
+        (Some(a.id) != preferred)
+            .cmp(&(Some(b.id) != preferred))
+            .then(a.priority.cmp(&b.priority))
+            .then(if weighted { by_key.then(by_default) } else { by_default.then(by_key) })
+    });
+    keyed.into_iter().map(|(_, profile)| profile).collect()
+}
+
+// Profile to queue a new message with: the first healthy one in failover order, or the first one
+// outright when every circuit is open so the worker can retry it later. None if the company has
+// no profiles at all
+pub fn select_profile(
+    user_repo: &impl UserRepository,
+    company_id: i64,
+) -> Result<Option<SmtpProfile>, diesel::result::Error> {
+    let ordered = failover_order(user_repo.get_smtp_profiles_by_company(company_id)?, None);
+    let healthy = ordered.iter().position(|profile| is_available(profile.id)).unwrap_or(0);
+    Ok(ordered.into_iter().nth(healthy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: i64, priority: i32, weight: i32, is_default: bool) -> SmtpProfile {
+        SmtpProfile {
+            id,
+            company_id: 1,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            smtp_server: format!("smtp{}.example.com", id),
+            smtp_port: 587,
+            is_default,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            tls_mode: "starttls".to_string(),
+            ca_certificate: None,
+            tls_hostname: None,
+            priority,
+            weight,
+        }
+    }
+
+    fn ids(profiles: Vec<SmtpProfile>) -> Vec<i64> {
+        profiles.into_iter().map(|profile| profile.id).collect()
+    }
+
+    #[test]
+    fn lower_priority_goes_first() {
+        let order = failover_order(vec![profile(1, 2, 100, true), profile(2, 1, 1, false)], None);
+        assert_eq!(ids(order), vec![2, 1]);
+    }
+
+    #[test]
+    fn preferred_profile_leads() {
+        let order = failover_order(vec![profile(1, 0, 1, true), profile(2, 5, 1, false)], Some(2));
+        assert_eq!(ids(order), vec![2, 1]);
+    }
+
+    #[test]
+    fn default_profile_leads_until_weights_are_set() {
+        for _ in 0..50 {
+            let profiles = vec![profile(1, 0, 1, false), profile(2, 0, 1, true), profile(3, 0, 1, false)];
+            assert_eq!(failover_order(profiles, None)[0].id, 2);
+        }
+    }
+
+    #[test]
+    fn weight_zero_profiles_come_last_with_the_default_first_among_them() {
+        let profiles = vec![profile(1, 0, 0, false), profile(2, 0, 0, true), profile(3, 0, 1, false)];
+        assert_eq!(ids(failover_order(profiles, None)), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn default_does_not_override_weight() {
+        let mut default_first = 0;
+        for _ in 0..2000 {
+            let profiles = vec![profile(1, 0, 1, true), profile(2, 0, 3, false)];
+            if failover_order(profiles, None)[0].id == 1 {
+                default_first += 1;
+            }
+        }
+        // Weight 1 against 3 leads a quarter of the time; it would always lead if the default won
+        assert!((300..700).contains(&default_first), "{}", default_first);
+    }
+}
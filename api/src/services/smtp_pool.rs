@@ -0,0 +1,92 @@
+use crate::models::users::SmtpProfile;
+use crate::services::email_service::{EmailService, SendError};
+use crate::utils::network::{check_smtp_addrs, lookup};
+use crate::utils::utils::get_env;
+use chrono::{DateTime, Utc};
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, Tokio1Executor};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref TRANSPORTS: RwLock<HashMap<i64, PooledTransport>> = RwLock::new(HashMap::new());
+    static ref POOL_CONFIG: PoolConfig = PoolConfig::new()
+        .max_size(get_env("SMTP_POOL_MAX_SIZE", "10").parse().unwrap_or(10))
+        .min_idle(get_env("SMTP_POOL_MIN_IDLE", "0").parse().unwrap_or(0))
+        .idle_timeout(Duration::from_secs(
+            get_env("SMTP_POOL_IDLE_TIMEOUT_SECS", "60").parse().unwrap_or(60),
+        ));
+    static ref RESOLVE_INTERVAL: Duration = Duration::from_secs(
+        get_env("SMTP_RESOLVE_INTERVAL_SECS", "300").parse().unwrap_or(300),
+    );
+}
+
+// A transport keeps its own pool of authenticated connections; clones share that pool
+struct PooledTransport {
+    // The profile version the transport was built from
+    updated_at: DateTime<Utc>,
+    // The checked address the transport dials, and when the host was last looked up
+    addr: SocketAddr,
+    resolved_at: Instant,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+// Returns the shared transport for a profile, building it on first use. A profile whose row has
+// changed since (including edits made outside the API) gets a fresh transport. The host is looked
+// up again every SMTP_RESOLVE_INTERVAL_SECS; the pool survives as long as it still resolves to the
+// address being dialled
+pub async fn transport_for(
+    smtp_profile: &SmtpProfile,
+) -> Result<AsyncSmtpTransport<Tokio1Executor>, SendError> {
+    let cached = TRANSPORTS
+        .read()
+        .unwrap()
+        .get(&smtp_profile.id)
+        .filter(|pooled| pooled.updated_at == smtp_profile.updated_at)
+        .map(|pooled| (pooled.addr, pooled.resolved_at, pooled.transport.clone()));
+    if let Some((_, resolved_at, transport)) = &cached {
+        if resolved_at.elapsed() < *RESOLVE_INTERVAL {
+            return Ok(transport.clone());
+        }
+    }
+
+    let host = smtp_profile.smtp_server.as_str();
+    let addrs = lookup(host, smtp_profile.smtp_port as u16)
+        .await
+        .map_err(SendError::transient)?;
+    check_smtp_addrs(host, &addrs).map_err(SendError::permanent)?;
+
+    let (addr, transport) = match cached {
+        Some((addr, _, transport)) if addrs.contains(&addr) => (addr, transport),
+        _ => {
+            let addr = addrs[0];
+            let transport = EmailService::profile_mailer_builder(smtp_profile, addr)
+                .map_err(SendError::permanent)?
+                .pool_config(POOL_CONFIG.clone())
+                .build();
+            log::debug!("Built pooled SMTP transport for profile {} to {}", smtp_profile.id, addr);
+            (addr, transport)
+        }
+    };
+
+    TRANSPORTS.write().unwrap().insert(
+        smtp_profile.id,
+        PooledTransport {
+            updated_at: smtp_profile.updated_at,
+            addr,
+            resolved_at: Instant::now(),
+            transport: transport.clone(),
+        },
+    );
+    Ok(transport)
+}
+
+// Drops a profile's transport after it is updated or deleted; its idle connections close once
+// in-flight sends holding a clone finish
+pub fn invalidate(profile_id: i64) {
+    if TRANSPORTS.write().unwrap().remove(&profile_id).is_some() {
+        log::debug!("Invalidated pooled SMTP transport for profile {}", profile_id);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::models::users::SmtpRoutingRule;
+use crate::repositories::users::UserRepository;
+use crate::services::smtp_failover;
+
+// What routing rules look at on an outgoing message
+#[derive(Debug)]
+pub struct RoutedMessage<'a> {
+    pub recipients: Vec<&'a str>,
+    pub from: &'a str,
+    pub template_id: Option<i64>,
+    pub tag: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub api_key_id: i64,
+}
+
+// Picks the SMTP profile for each message a company sends: the first active rule that matches,
+// otherwise the company's default in failover order. Loaded once per request so a batch does not
+// re-read the rules for every item
+pub struct SmtpRouter {
+    rules: Vec<SmtpRoutingRule>,
+    fallback_profile_id: i64,
+}
+
+impl SmtpRouter {
+    // None when the company has no SMTP profile to send with
+    pub fn for_company(
+        user_repo: &impl UserRepository,
+        company_id: i64,
+    ) -> Result<Option<Self>, diesel::result::Error> {
+        let fallback = match smtp_failover::select_profile(user_repo, company_id)? {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+        let rules = user_repo
+            .get_routing_rules_by_company(company_id)?
+            .into_iter()
+            .filter(|rule| rule.is_active)
+            .collect();
+
+        Ok(Some(SmtpRouter {
+            rules,
+            fallback_profile_id: fallback.id,
+        }))
+    }
+
+    pub fn route(&self, message: &RoutedMessage) -> i64 {
+        match self.rules.iter().find(|rule| matches(rule, message)) {
+            Some(rule) => {
+                log::debug!("Routing rule {} sends via SMTP profile {}", rule.id, rule.smtp_profile_id);
+                rule.smtp_profile_id
+            }
+            None => self.fallback_profile_id,
+        }
+    }
+}
+
+// Every criterion set on the rule has to hold. Addresses, domains, tags and categories compare
+// case-insensitively
+fn matches(rule: &SmtpRoutingRule, message: &RoutedMessage) -> bool {
+    let same = |expected: &Option<String>, actual: Option<&str>| match expected {
+        Some(expected) => actual.is_some_and(|actual| actual.eq_ignore_ascii_case(expected)),
+        None => true,
+    };
+
+    let domain_matches = match &rule.recipient_domain {
+        Some(domain) => {
+            !message.recipients.is_empty()
+                && message.recipients.iter().all(|recipient| {
+                    recipient
+                        .rsplit_once('@')
+                        .is_some_and(|(_, recipient_domain)| recipient_domain.eq_ignore_ascii_case(domain))
+                })
+        }
+        None => true,
+    };
+
+    domain_matches
+        && same(&rule.from_address, Some(message.from))
+        && rule.template_id.is_none_or(|id| message.template_id == Some(id))
+        && same(&rule.tag, message.tag)
+        && same(&rule.category, message.category)
+        && rule.api_key_id.is_none_or(|id| message.api_key_id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn any_rule() -> SmtpRoutingRule {
+        SmtpRoutingRule {
+            id: 1,
+            company_id: 1,
+            smtp_profile_id: 10,
+            name: "rule".to_string(),
+            priority: 0,
+            recipient_domain: None,
+            from_address: None,
+            template_id: None,
+            tag: None,
+            category: None,
+            api_key_id: None,
+            is_active: true,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    fn message<'a>(recipients: Vec<&'a str>) -> RoutedMessage<'a> {
+        RoutedMessage {
+            recipients,
+            from: "news@example.com",
+            template_id: Some(7),
+            tag: Some("Bulk"),
+            category: None,
+            api_key_id: 3,
+        }
+    }
+
+    #[test]
+    fn recipient_domain_must_match_every_recipient() {
+        let rule = SmtpRoutingRule {
+            recipient_domain: Some("gmail.com".to_string()),
+            ..any_rule()
+        };
+        assert!(matches(&rule, &message(vec!["a@gmail.com", "b@GMAIL.com"])));
+        assert!(!matches(&rule, &message(vec!["a@gmail.com", "b@yahoo.com"])));
+        assert!(!matches(&rule, &message(vec![])));
+        assert!(!matches(&rule, &message(vec!["a@mail.gmail.com"])));
+    }
+
+    #[test]
+    fn text_criteria_ignore_case() {
+        let rule = SmtpRoutingRule {
+            from_address: Some("NEWS@example.com".to_string()),
+            tag: Some("bulk".to_string()),
+            ..any_rule()
+        };
+        assert!(matches(&rule, &message(vec!["a@example.com"])));
+    }
+
+    #[test]
+    fn every_criterion_set_has_to_hold() {
+        let rule = SmtpRoutingRule {
+            template_id: Some(7),
+            api_key_id: Some(4),
+            ..any_rule()
+        };
+        assert!(!matches(&rule, &message(vec!["a@example.com"])));
+
+        let rule = SmtpRoutingRule {
+            category: Some("receipts".to_string()),
+            ..any_rule()
+        };
+        assert!(!matches(&rule, &message(vec!["a@example.com"])));
+    }
+
+    #[test]
+    fn first_matching_rule_wins_over_the_fallback() {
+        let router = SmtpRouter {
+            rules: vec![
+                SmtpRoutingRule {
+                    smtp_profile_id: 20,
+                    tag: Some("transactional".to_string()),
+                    ..any_rule()
+                },
+                SmtpRoutingRule {
+                    smtp_profile_id: 30,
+                    tag: Some("bulk".to_string()),
+                    ..any_rule()
+                },
+            ],
+            fallback_profile_id: 99,
+        };
+        assert_eq!(router.route(&message(vec!["a@example.com"])), 30);
+
+        let untagged = RoutedMessage {
+            tag: None,
+            ..message(vec!["a@example.com"])
+        };
+        assert_eq!(router.route(&untagged), 99);
+    }
+}
@@ -0,0 +1,477 @@
+use crate::models::core::{NewWebhookDelivery, Webhook, WebhookDelivery, WebhookDeliveryResult};
+use crate::repositories::{CoreRepository, RepositoryFactory};
+use crate::services::email_queue::parse_retry_schedule;
+use crate::services::email_service::{OutboundEmail, SendError};
+use crate::utils::network::{is_development, is_public_ip, resolve_public};
+use crate::utils::utils::get_env;
+use api::webhook_signature::{generate_secret, sign_payload, SIGNATURE_HEADER};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub const EVENT_QUEUED: &str = "queued";
+pub const EVENT_SENT: &str = "sent";
+pub const EVENT_FAILED: &str = "failed";
+pub const EVENT_BOUNCED: &str = "bounced";
+pub const WEBHOOK_EVENTS: &[&str] = &[EVENT_QUEUED, EVENT_SENT, EVENT_FAILED, EVENT_BOUNCED];
+
+pub const STATUS_ACTIVE: &str = "Active";
+
+pub const DELIVERY_PENDING: &str = "Pending";
+pub const DELIVERY_SUCCEEDED: &str = "Succeeded";
+pub const DELIVERY_FAILED: &str = "Failed";
+
+const MAX_RESPONSE_BODY_CHARS: usize = 2048;
+
+lazy_static::lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(
+            get_env("WEBHOOK_TIMEOUT_SECS", "10").parse().unwrap_or(10),
+        ))
+        .user_agent("MailNow-Webhooks/1.0")
+        // Redirects and DNS answers are where an endpoint that passed `check_destination` could
+        // still be steered to an internal address
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Failed to build webhook HTTP client");
+}
+
+// Resolves webhook hosts at connect time and refuses any that point at a non-public address
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolve_public(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// Webhook URLs must be https (http is allowed in development) and must not reach loopback, private
+// or link-local addresses
+pub async fn check_destination(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|_| format!("Invalid webhook URL '{}'", url))?;
+    match parsed.scheme() {
+        "https" => {}
+        "http" if is_development() => {}
+        _ => return Err("Webhook URL must use https".to_string()),
+    }
+
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("Invalid webhook URL '{}'", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    // IP literals never go through the resolver, so they are checked here; IPv6 ones keep brackets
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) if is_public_ip(ip) => Ok(()),
+        Ok(ip) => Err(format!("{} is not a public address", ip)),
+        Err(_) => resolve_public(host, port).await.map(|_| ()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    pub lease: Duration,
+    pub retry_schedule: Vec<Duration>,
+    pub disable_after: i32,
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> Self {
+        WebhookConfig {
+            poll_interval: Duration::from_millis(
+                get_env("WEBHOOK_POLL_MS", "1000").parse().unwrap_or(1000),
+            ),
+            batch_size: get_env("WEBHOOK_BATCH_SIZE", "20").parse().unwrap_or(20),
+            lease: Duration::from_secs(
+                get_env("WEBHOOK_LEASE_SECS", "300").parse().unwrap_or(300),
+            ),
+            retry_schedule: parse_retry_schedule(&get_env(
+                "WEBHOOK_RETRY_SCHEDULE",
+                "30,120,600,1800,3600,7200",
+            )),
+            disable_after: get_env("WEBHOOK_DISABLE_AFTER", "25").parse().unwrap_or(25),
+        }
+    }
+
+    // Delay before the next attempt, or None once the schedule is exhausted
+    pub fn retry_delay(&self, attempt: i32) -> Option<Duration> {
+        let index = usize::try_from(attempt).ok()?.checked_sub(1)?;
+        self.retry_schedule.get(index).copied()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub id: String,
+    pub event: String,
+    pub created_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl WebhookEvent {
+    pub fn new(event: &str, data: serde_json::Value) -> Self {
+        WebhookEvent {
+            id: format!("evt_{}", Uuid::new_v4().simple()),
+            event: event.to_string(),
+            created_at: Utc::now(),
+            data,
+        }
+    }
+}
+
+pub fn email_event_data(message_id: &str, email: &OutboundEmail) -> serde_json::Value {
+    serde_json::json!({
+        "message_id": message_id,
+        "from": email.from,
+        "to": email.to,
+        "cc": email.cc,
+        "bcc": email.bcc,
+        "subject": email.subject,
+    })
+}
+
+// Permanent rejections of the mailbox itself count as bounces; anything else is a failure
+pub fn failure_event(error: &SendError) -> &'static str {
+    match error.smtp_code() {
+        Some(550..=554) if !error.is_transient() => EVENT_BOUNCED,
+        _ => EVENT_FAILED,
+    }
+}
+
+// Webhooks without an events list receive everything
+pub fn is_subscribed(webhook: &Webhook, event: &str) -> bool {
+    match &webhook.events {
+        None => true,
+        Some(events) if events.is_empty() => true,
+        Some(events) => events.iter().flatten().any(|e| e == event),
+    }
+}
+
+// Records a pending delivery for every subscriber; the webhook worker sends them
+pub fn dispatch_event(
+    repo_factory: &RepositoryFactory,
+    company_id: i64,
+    event: &str,
+    data: serde_json::Value,
+) {
+    let core_repo = repo_factory.create_core_repository();
+    let webhooks = match core_repo.get_active_webhooks_by_company(company_id) {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            log::error!("Failed to load webhooks for company {}: {:?}", company_id, e);
+            return;
+        }
+    };
+
+    let webhook_event = WebhookEvent::new(event, data);
+    let new_deliveries: Vec<NewWebhookDelivery> = webhooks
+        .iter()
+        .filter(|webhook| is_subscribed(webhook, event))
+        .map(|webhook| {
+            new_delivery(
+                webhook.id,
+                company_id,
+                &webhook_event.id,
+                &webhook_event.event,
+                serde_json::to_value(&webhook_event).unwrap(),
+                1,
+                Utc::now(),
+            )
+        })
+        .collect();
+    if new_deliveries.is_empty() {
+        return;
+    }
+
+    if let Err(e) = core_repo.create_webhook_deliveries(new_deliveries) {
+        log::error!("Failed to record {} event {}: {:?}", event, webhook_event.id, e);
+    }
+}
+
+// Sends the same event again as a fresh first attempt
+pub fn replay_delivery(
+    repo_factory: &RepositoryFactory,
+    delivery: &WebhookDelivery,
+) -> Result<WebhookDelivery, diesel::result::Error> {
+    let core_repo = repo_factory.create_core_repository();
+    let replay = new_delivery(
+        delivery.webhook_id,
+        delivery.company_id,
+        &delivery.event_id,
+        &delivery.event,
+        delivery.payload.clone(),
+        1,
+        Utc::now(),
+    );
+    let mut created = core_repo.create_webhook_deliveries(vec![replay])?;
+    Ok(created.remove(0))
+}
+
+fn new_delivery(
+    webhook_id: i64,
+    company_id: i64,
+    event_id: &str,
+    event: &str,
+    payload: serde_json::Value,
+    attempt: i32,
+    next_attempt_at: DateTime<Utc>,
+) -> NewWebhookDelivery {
+    NewWebhookDelivery {
+        webhook_id,
+        company_id,
+        event_id: event_id.to_string(),
+        event: event.to_string(),
+        payload,
+        attempt,
+        status: DELIVERY_PENDING.to_string(),
+        next_attempt_at,
+        created_at: Utc::now(),
+    }
+}
+
+pub async fn start_webhook_workers(repo_factory: RepositoryFactory) {
+    let config = WebhookConfig::from_env();
+    log::info!(
+        "Starting webhook delivery worker (poll every {:?})",
+        config.poll_interval
+    );
+
+    let mut last_release = std::time::Instant::now();
+    release_stale_deliveries(&repo_factory, &config);
+
+    loop {
+        if last_release.elapsed() >= config.lease {
+            release_stale_deliveries(&repo_factory, &config);
+            last_release = std::time::Instant::now();
+        }
+
+        let core_repo = repo_factory.create_core_repository();
+        let deliveries = match core_repo.claim_webhook_deliveries(config.batch_size) {
+            Ok(deliveries) => deliveries,
+            Err(e) => {
+                log::error!("Failed to claim webhook deliveries: {:?}", e);
+                Vec::new()
+            }
+        };
+
+        if deliveries.is_empty() {
+            tokio::time::sleep(config.poll_interval).await;
+            continue;
+        }
+
+        // One slow receiver shouldn't hold up the rest of the batch
+        let handles: Vec<_> = deliveries
+            .into_iter()
+            .map(|delivery| {
+                let repo_factory = repo_factory.clone();
+                let config = config.clone();
+                tokio::spawn(async move { process_delivery(&repo_factory, &config, delivery).await })
+            })
+            .collect();
+        for handle in handles {
+            if let Err(e) = handle.await {
+                log::error!("Webhook delivery task panicked: {:?}", e);
+            }
+        }
+    }
+}
+
+fn release_stale_deliveries(repo_factory: &RepositoryFactory, config: &WebhookConfig) {
+    let core_repo = repo_factory.create_core_repository();
+    let locked_before = Utc::now() - chrono::Duration::from_std(config.lease).unwrap();
+
+    match core_repo.release_stale_webhook_deliveries(locked_before) {
+        Ok(0) => {}
+        Ok(count) => log::warn!("Released {} stale webhook deliveries", count),
+        Err(e) => log::error!("Failed to release stale webhook deliveries: {:?}", e),
+    }
+}
+
+async fn process_delivery(
+    repo_factory: &RepositoryFactory,
+    config: &WebhookConfig,
+    delivery: WebhookDelivery,
+) {
+    let core_repo = repo_factory.create_core_repository();
+    let webhook = match core_repo.get_webhook_by_id(delivery.webhook_id, delivery.company_id) {
+        Ok(webhook) => webhook,
+        Err(e) => {
+            log::error!("Webhook {} for delivery {} unavailable: {:?}", delivery.webhook_id, delivery.id, e);
+            // A deleted webhook has nowhere left to deliver to; anything else may clear up
+            let retry = !matches!(e, diesel::result::Error::NotFound);
+            abandon_delivery(&core_repo, config, &delivery, "Webhook is unavailable", retry);
+            return;
+        }
+    };
+    let Some(secret) = signing_secret(repo_factory, &webhook) else {
+        abandon_delivery(&core_repo, config, &delivery, "Webhook has no signing secret", true);
+        return;
+    };
+
+    // Sign the exact bytes we send so receivers can verify the raw body
+    let body = serde_json::to_vec(&delivery.payload).unwrap();
+    let signature = sign_payload(&secret, Utc::now().timestamp(), &body);
+    let started = Instant::now();
+
+    // Checked on every attempt so webhooks saved before an address was blocked stop reaching it
+    let response = match check_destination(&webhook.url).await {
+        Err(e) => Err(e),
+        Ok(()) => HTTP_CLIENT
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-MailNow-Event", &delivery.event)
+            .header("X-MailNow-Event-Id", &delivery.event_id)
+            .header("X-MailNow-Delivery-Attempt", delivery.attempt.to_string())
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string()),
+    };
+
+    let mut result = WebhookDeliveryResult {
+        status: DELIVERY_FAILED.to_string(),
+        response_status: None,
+        response_body: None,
+        error: None,
+        latency_ms: None,
+        completed_at: Some(Utc::now()),
+    };
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            result.response_status = Some(i32::from(status.as_u16()));
+            result.response_body = response
+                .text()
+                .await
+                .ok()
+                .map(|text| text.chars().take(MAX_RESPONSE_BODY_CHARS).collect());
+            if status.is_success() {
+                result.status = DELIVERY_SUCCEEDED.to_string();
+            } else {
+                result.error = Some(format!("Receiver responded with {}", status));
+            }
+        }
+        Err(e) => result.error = Some(e),
+    }
+    result.latency_ms = Some(i32::try_from(started.elapsed().as_millis()).unwrap_or(i32::MAX));
+    result.completed_at = Some(Utc::now());
+
+    if let Err(e) = core_repo.complete_webhook_delivery(delivery.id, &result) {
+        log::error!("Failed to record webhook delivery {}: {:?}", delivery.id, e);
+    }
+
+    if result.status == DELIVERY_SUCCEEDED {
+        log::debug!("Webhook {} accepted event {}", webhook.id, delivery.event_id);
+        if let Err(e) = core_repo.record_webhook_success(webhook.id) {
+            log::error!("Failed to update webhook {} health: {:?}", webhook.id, e);
+        }
+        return;
+    }
+
+    log::warn!(
+        "Webhook {} delivery {} of event {} failed on attempt {}: {}",
+        webhook.id,
+        delivery.id,
+        delivery.event_id,
+        delivery.attempt,
+        result.error.as_deref().unwrap_or("unknown error")
+    );
+    record_failure(&core_repo, config, &webhook);
+    schedule_retry(&core_repo, config, &delivery);
+}
+
+fn record_failure(core_repo: &impl CoreRepository, config: &WebhookConfig, webhook: &Webhook) {
+    let updated = match core_repo.record_webhook_failure(webhook.id) {
+        Ok(updated) => updated,
+        Err(e) => {
+            log::error!("Failed to update webhook {} health: {:?}", webhook.id, e);
+            return;
+        }
+    };
+
+    if updated.is_active && updated.consecutive_failures >= config.disable_after {
+        match core_repo.disable_webhook(webhook.id) {
+            Ok(_) => log::warn!(
+                "Disabled webhook {} after {} consecutive failed deliveries",
+                webhook.id,
+                updated.consecutive_failures
+            ),
+            Err(e) => log::error!("Failed to disable webhook {}: {:?}", webhook.id, e),
+        }
+    }
+}
+
+// Closes out a delivery that could not be attempted, so it doesn't sit in Processing forever
+fn abandon_delivery(
+    core_repo: &impl CoreRepository,
+    config: &WebhookConfig,
+    delivery: &WebhookDelivery,
+    error: &str,
+    retry: bool,
+) {
+    let result = WebhookDeliveryResult {
+        status: DELIVERY_FAILED.to_string(),
+        response_status: None,
+        response_body: None,
+        error: Some(error.to_string()),
+        latency_ms: None,
+        completed_at: Some(Utc::now()),
+    };
+    if let Err(e) = core_repo.complete_webhook_delivery(delivery.id, &result) {
+        log::error!("Failed to record webhook delivery {}: {:?}", delivery.id, e);
+    }
+    if retry {
+        schedule_retry(core_repo, config, delivery);
+    }
+}
+
+// Retries are queued even for a webhook that was just disabled, so re-enabling it catches up
+fn schedule_retry(core_repo: &impl CoreRepository, config: &WebhookConfig, delivery: &WebhookDelivery) {
+    let Some(delay) = config.retry_delay(delivery.attempt) else {
+        log::error!(
+            "Giving up on event {} for webhook {} after {} attempts",
+            delivery.event_id,
+            delivery.webhook_id,
+            delivery.attempt
+        );
+        return;
+    };
+
+    let retry = new_delivery(
+        delivery.webhook_id,
+        delivery.company_id,
+        &delivery.event_id,
+        &delivery.event,
+        delivery.payload.clone(),
+        delivery.attempt + 1,
+        Utc::now() + chrono::Duration::from_std(delay).unwrap(),
+    );
+    if let Err(e) = core_repo.create_webhook_deliveries(vec![retry]) {
+        log::error!("Failed to schedule retry of delivery {}: {:?}", delivery.id, e);
+    }
+}
+
+// Webhooks created before signing existed get a secret on their first delivery
+fn signing_secret(repo_factory: &RepositoryFactory, webhook: &Webhook) -> Option<String> {
+    if let Some(secret) = &webhook.secret {
+        return Some(secret.clone());
+    }
+
+    let core_repo = repo_factory.create_core_repository();
+    match core_repo.update_webhook_secret(webhook.id, &generate_secret()) {
+        Ok(updated) => updated.secret,
+        Err(e) => {
+            log::error!("Failed to create signing secret for webhook {}: {:?}", webhook.id, e);
+            None
+        }
+    }
+}
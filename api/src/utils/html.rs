@@ -0,0 +1,229 @@
+// Plain-text rendering of HTML email bodies, used for the text/plain alternative part
+
+const BLOCK_TAGS: &[&str] = &[
+    "p", "div", "br", "h1", "h2", "h3", "h4", "h5", "h6", "li", "tr", "table", "ul", "ol",
+    "blockquote", "section", "article", "header", "footer", "hr",
+];
+const SKIPPED_TAGS: &[&str] = &["head", "style", "script", "title"];
+
+pub fn html_to_text(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skipping: Option<String> = None;
+    let mut link_href: Option<(String, usize)> = None;
+
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push_text(&mut output, &rest[..start]);
+        }
+
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        if let Some(skipped) = &skipping {
+            if closing && &name == skipped {
+                skipping = None;
+            }
+            continue;
+        }
+
+        if SKIPPED_TAGS.contains(&name.as_str()) && !closing && !tag.ends_with('/') {
+            skipping = Some(name);
+            continue;
+        }
+
+        match name.as_str() {
+            "a" if !closing => {
+                link_href = attribute(tag, "href").map(|href| (href, output.len()));
+            }
+            "a" => {
+                if let Some((href, label_start)) = link_href.take() {
+                    // A block tag inside the link can trim the output back past where it started
+                    let label = output.get(label_start..).unwrap_or("").trim().to_string();
+                    if !href.starts_with('#') && !href.starts_with("mailto:") && label != href {
+                        output.push_str(&format!(" ({})", href));
+                    }
+                }
+            }
+            "li" if !closing => {
+                push_newline(&mut output);
+                output.push_str("- ");
+            }
+            "hr" => {
+                push_newline(&mut output);
+                output.push_str("----------------------------------------");
+                push_newline(&mut output);
+            }
+            tag_name if BLOCK_TAGS.contains(&tag_name) => push_newline(&mut output),
+            _ => {}
+        }
+    }
+
+    if skipping.is_none() {
+        push_text(&mut output, rest);
+    }
+
+    collapse_blank_lines(&output)
+}
+
+fn push_text(output: &mut String, raw: &str) {
+    let decoded = decode_entities(raw);
+    for word in decoded.split_whitespace() {
+        if !output.is_empty() && !output.ends_with(['\n', ' ']) {
+            output.push(' ');
+        }
+        output.push_str(word);
+    }
+    // Keep the space between this run and the next inline element
+    if decoded.ends_with(char::is_whitespace) && !output.is_empty() && !output.ends_with('\n') {
+        output.push(' ');
+    }
+}
+
+fn push_newline(output: &mut String) {
+    while output.ends_with(' ') {
+        output.pop();
+    }
+    output.push('\n');
+}
+
+fn collapse_blank_lines(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blank_lines += 1;
+            if blank_lines > 1 || result.is_empty() {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+
+    result.trim_end().to_string()
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    // ASCII-only lowering keeps byte offsets valid for slicing `tag`
+    let lower = tag.to_ascii_lowercase();
+    let position = lower.find(&format!("{}=", name))?;
+    let value = &tag[position + name.len() + 1..];
+
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split(|c: char| c.is_whitespace()).next()?,
+    };
+    Some(decode_entities(value))
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let character = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" | "#39" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if entity.starts_with('#') => {
+                    entity[1..].parse::<u32>().ok().and_then(char::from_u32)
+                }
+                _ => None,
+            };
+            character.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((character, end)) => {
+                result.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_blocks_lists_and_links() {
+        let html = "<h1>Hello</h1><p>Visit <a href=\"https://example.com\">our site</a></p>\
+                    <ul><li>One</li><li>Two</li></ul>";
+        assert_eq!(
+            html_to_text(html),
+            "Hello\n\nVisit our site (https://example.com)\n\n- One\n\n- Two"
+        );
+    }
+
+    #[test]
+    fn skips_head_style_and_script() {
+        let html = "<html><head><title>T</title><style>p{}</style></head>\
+                    <body><script>alert(1)</script><p>Body</p></body></html>";
+        assert_eq!(html_to_text(html), "Body");
+    }
+
+    #[test]
+    fn omits_href_when_it_matches_the_label() {
+        let html = "<a href=\"https://example.com\">https://example.com</a> <a href=\"#top\">Top</a>";
+        assert_eq!(html_to_text(html), "https://example.com Top");
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(html_to_text("Fish &amp; chips &lt;3 &#233;&#x41; &bogus; &"), "Fish & chips <3 éA &bogus; &");
+    }
+
+    #[test]
+    fn block_tag_inside_a_link_does_not_panic() {
+        assert_eq!(html_to_text("x <i> </i><a href='u'><br></a>"), "x\n(u)");
+    }
+
+    #[test]
+    fn non_ascii_attributes_do_not_panic() {
+        assert_eq!(html_to_text("<a title=\"İİ\" href=\"https://e.com\">E</a>"), "E (https://e.com)");
+    }
+
+    #[test]
+    fn unterminated_tag_is_kept_as_text() {
+        assert_eq!(html_to_text("<p>a</p><b"), "a\n<b");
+    }
+}
@@ -3,4 +3,6 @@ pub mod logging;
 pub mod template;
 pub mod verification;
 pub mod redis_verification;
-pub mod pricing;
\ No newline at end of file
+pub mod pricing;
+pub mod html;
+pub mod network;
\ No newline at end of file
@@ -0,0 +1,158 @@
+use crate::utils::utils::get_env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// DEBUG=1 (the default) marks a development setup
+pub fn is_development() -> bool {
+    get_env("DEBUG", "1").trim() == "1"
+}
+
+// Whether a connection made on a customer's behalf (webhooks, SMTP profiles) may go to `ip`.
+// Loopback, private, link-local (which covers cloud metadata at 169.254.169.254), shared,
+// multicast and reserved ranges are refused so those features can't be used to probe our network
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_ipv4(mapped),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // 0.0.0.0/8 "this network"
+        || a == 0
+        // 100.64.0.0/10 carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // 192.0.0.0/24 protocol assignments
+        || (a == 192 && b == 0 && ip.octets()[2] == 0)
+        // 198.18.0.0/15 benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // 240.0.0.0/4 reserved
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // 2001:db8::/32 documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // 64:ff9b::/96 NAT64 and ::/96 IPv4-compatible can both reach IPv4 hosts
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b)
+        || ip.segments()[..6].iter().all(|segment| *segment == 0))
+}
+
+// Looks `host` up, failing when it has no addresses
+pub async fn lookup(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no addresses", host));
+    }
+    Ok(addrs)
+}
+
+// Fails if any of the addresses is not public, so a name that points at both a public and an
+// internal address can't be used to slip past the check
+pub fn check_public(host: &str, addrs: &[SocketAddr]) -> Result<(), String> {
+    match addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        Some(blocked) => Err(format!("{} resolves to {}, which is not a public address", host, blocked.ip())),
+        None => Ok(()),
+    }
+}
+
+pub async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs = lookup(host, port).await?;
+    check_public(host, &addrs)?;
+    Ok(addrs)
+}
+
+// SMTP profiles may name relays on our own network (what tls_mode "none" is for) only where the
+// deployment sets SMTP_ALLOW_INTERNAL_HOSTS=1; otherwise they get the same check as webhooks
+pub fn internal_smtp_hosts_allowed() -> bool {
+    get_env("SMTP_ALLOW_INTERNAL_HOSTS", "0").trim() == "1"
+}
+
+pub fn check_smtp_addrs(host: &str, addrs: &[SocketAddr]) -> Result<(), String> {
+    if internal_smtp_hosts_allowed() {
+        return Ok(());
+    }
+    check_public(host, addrs)
+}
+
+// Diagnostics and sending both dial one of these addresses rather than resolving the host again,
+// so the name can't be re-pointed between the check and the connection
+pub async fn resolve_smtp_host(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs = lookup(host, port).await?;
+    check_smtp_addrs(host, &addrs)?;
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public_ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn refuses_internal_ipv4_ranges() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn refuses_internal_ipv6_ranges() {
+        for ip in ["::1", "::", "fe80::1", "fd00::1", "::ffff:127.0.0.1", "::ffff:169.254.169.254", "64:ff9b::a9fe:a9fe"] {
+            assert!(!public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn allows_public_addresses() {
+        for ip in ["8.8.8.8", "1.1.1.1", "172.32.0.1", "2606:4700:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn check_public_refuses_any_internal_address() {
+        let public: SocketAddr = "8.8.8.8:25".parse().unwrap();
+        let private: SocketAddr = "10.0.0.5:25".parse().unwrap();
+        assert!(check_public("mail.example.com", &[public]).is_ok());
+        assert!(check_public("mail.example.com", &[public, private]).is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_public_refuses_loopback_names() {
+        assert!(resolve_public("localhost", 443).await.is_err());
+        assert!(resolve_public("127.0.0.1", 443).await.is_err());
+    }
+}
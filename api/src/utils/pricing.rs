@@ -1,4 +1,4 @@
-use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono::{DateTime, Datelike, Utc};
 
 #[derive(Debug, Clone)]
 pub enum PricingTier {
@@ -31,33 +31,128 @@ impl PricingTier {
             PricingTier::Enterprise => -1, // Unlimited
         }
     }
+
+    pub fn is_unlimited(&self) -> bool {
+        matches!(self, PricingTier::Enterprise)
+    }
+
+    // Strict counterpart to `from_str` for user input, which must not fall back to Free
+    pub fn parse(tier: &str) -> Option<Self> {
+        match tier.to_lowercase().as_str() {
+            "free" => Some(PricingTier::Free),
+            "developer" => Some(PricingTier::Developer),
+            "enterprise" => Some(PricingTier::Enterprise),
+            _ => None,
+        }
+    }
+
+    // Enterprise is priced by contract and can't be changed to or from through the API
+    pub fn monthly_price_cents(&self) -> Option<i64> {
+        match self {
+            PricingTier::Free => Some(0),
+            PricingTier::Developer => Some(2_000),
+            PricingTier::Enterprise => None,
+        }
+    }
+}
+
+// One-off credit packs; the credits they add survive monthly resets
+#[derive(Debug, Clone, Copy)]
+pub enum TopUpPack {
+    Small,
+    Medium,
+    Large,
+}
+
+impl TopUpPack {
+    pub const ALL: [TopUpPack; 3] = [TopUpPack::Small, TopUpPack::Medium, TopUpPack::Large];
+
+    pub fn parse(pack: &str) -> Option<Self> {
+        match pack.to_lowercase().as_str() {
+            "small" => Some(TopUpPack::Small),
+            "medium" => Some(TopUpPack::Medium),
+            "large" => Some(TopUpPack::Large),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TopUpPack::Small => "small",
+            TopUpPack::Medium => "medium",
+            TopUpPack::Large => "large",
+        }
+    }
+
+    pub fn credits(&self) -> i64 {
+        match self {
+            TopUpPack::Small => 5_000,
+            TopUpPack::Medium => 25_000,
+            TopUpPack::Large => 100_000,
+        }
+    }
+
+    pub fn price_cents(&self) -> i64 {
+        match self {
+            TopUpPack::Small => 1_000,
+            TopUpPack::Medium => 4_000,
+            TopUpPack::Large => 12_000,
+        }
+    }
+}
+
+// Share of the billing cycle ending at `reset_date` that is still to run, from 0.0 to 1.0
+pub fn remaining_cycle_fraction(reset_date: DateTime<Utc>) -> f64 {
+    let cycle_start = reset_date
+        .checked_sub_months(chrono::Months::new(1))
+        .unwrap_or(reset_date);
+    let cycle = (reset_date - cycle_start).num_seconds();
+    if cycle <= 0 {
+        return 0.0;
+    }
+    let remaining = (reset_date - Utc::now()).num_seconds().clamp(0, cycle);
+    remaining as f64 / cycle as f64
 }
 
 pub fn get_next_reset_date() -> DateTime<Utc> {
-    let now = Utc::now();
-    let next_month = if now.month() == 12 {
-        now.with_year(now.year() + 1)
-            .unwrap()
-            .with_month(1)
-            .unwrap()
-    } else {
-        now.with_month(now.month() + 1).unwrap()
-    };
-    next_month
+    next_reset_date_after(Utc::now())
+}
+
+// Midnight UTC on the first of the month after `now`
+fn next_reset_date_after(now: DateTime<Utc>) -> DateTime<Utc> {
+    let month_start = now
+        .date_naive()
         .with_day(1)
-        .unwrap()
-        .with_hour(0)
-        .unwrap()
-        .with_minute(0)
-        .unwrap()
-        .with_second(0)
-        .unwrap()
-        .with_nanosecond(0)
-        .unwrap()
-}
-
-pub fn should_reset_credits(last_reset: DateTime<Utc>) -> bool {
-    let now = Utc::now();
-    now.year() > last_reset.year()
-        || (now.year() == last_reset.year() && now.month() > last_reset.month())
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .expect("the first of the month is always a valid date")
+        .and_utc();
+    month_start
+        .checked_add_months(chrono::Months::new(1))
+        .expect("next reset date out of range")
+}
+
+// `credits_reset_date` holds the next reset, as set by onboarding and `reset_company_credits`
+pub fn should_reset_credits(reset_date: DateTime<Utc>) -> bool {
+    Utc::now() >= reset_date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn next_reset_is_the_first_of_the_following_month() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 31, 15, 30, 0).unwrap();
+        assert_eq!(next_reset_date_after(now), Utc.with_ymd_and_hms(2026, 11, 1, 0, 0, 0).unwrap());
+
+        let now = Utc.with_ymd_and_hms(2027, 1, 30, 0, 0, 0).unwrap();
+        assert_eq!(next_reset_date_after(now), Utc.with_ymd_and_hms(2027, 2, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn next_reset_rolls_over_the_year() {
+        let now = Utc.with_ymd_and_hms(2026, 12, 31, 23, 59, 59).unwrap();
+        assert_eq!(next_reset_date_after(now), Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap());
+    }
 }
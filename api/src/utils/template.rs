@@ -1,5 +1,6 @@
 use std::fs;
 use std::collections::HashMap;
+use serde_json::Value;
 
 pub fn load_template(template_name: &str, variables: HashMap<&str, &str>) -> Result<String, Box<dyn std::error::Error>> {
     let template_path = format!("templates/{}.html", template_name);
@@ -11,4 +12,512 @@ pub fn load_template(template_name: &str, variables: HashMap<&str, &str>) -> Res
     }
     
     Ok(content)
-}
\ No newline at end of file
+}
+
+// Templates stored per company and rendered with request variables:
+//   {{ name }}                  HTML-escaped value, required
+//   {{{ name }}}                raw value, required
+//   {{ name | default: "x" }}   value, or "x" when missing, null or empty
+//   {{#if name}}..{{else}}..{{/if}}
+//   {{#each items}}..{{this}}..{{@index}}..{{/each}}
+// Dotted paths (user.name) reach into objects; inside #each, names resolve against the item first.
+
+// Deeper nesting is refused at parse time so a hostile template can't exhaust the stack
+const MAX_BLOCK_DEPTH: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("Template syntax error: {0}")]
+    Syntax(String),
+
+    #[error("Missing template variables: {}", .0.join(", "))]
+    MissingVariables(Vec<String>),
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable {
+        path: String,
+        escape: bool,
+        default: Option<String>,
+    },
+    If {
+        path: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        path: String,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Variable { expression: String, escape: bool },
+    OpenIf(String),
+    OpenEach(String),
+    Else,
+    Close(String),
+}
+
+struct Frame<'a> {
+    value: &'a Value,
+    index: Option<usize>,
+}
+
+pub fn render_template(
+    source: &str,
+    variables: &Value,
+    escape_html: bool,
+) -> Result<String, TemplateError> {
+    let nodes = parse(source)?;
+    let mut output = String::with_capacity(source.len());
+    let mut missing = Vec::new();
+    let mut frames = vec![Frame {
+        value: variables,
+        index: None,
+    }];
+
+    render_nodes(&nodes, &mut frames, escape_html, &mut output, &mut missing);
+
+    if !missing.is_empty() {
+        return Err(TemplateError::MissingVariables(missing));
+    }
+    Ok(output)
+}
+
+// Checks the template syntax without rendering, used when templates are saved
+pub fn validate_template(source: &str) -> Result<(), TemplateError> {
+    parse(source).map(|_| ())
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+
+        let raw = rest[start..].starts_with("{{{");
+        let (open, close) = if raw { ("{{{", "}}}") } else { ("{{", "}}") };
+        let inner = &rest[start + open.len()..];
+        let end = inner.find(close).ok_or_else(|| {
+            TemplateError::Syntax(format!("unclosed tag starting with '{}'", excerpt(&rest[start..])))
+        })?;
+        let tag = inner[..end].trim();
+        rest = &inner[end + close.len()..];
+
+        if tag.is_empty() {
+            return Err(TemplateError::Syntax("empty tag".to_string()));
+        }
+
+        let token = if raw {
+            Token::Variable {
+                expression: tag.to_string(),
+                escape: false,
+            }
+        } else if let Some(path) = tag.strip_prefix("#if") {
+            Token::OpenIf(block_argument("#if", path)?)
+        } else if let Some(path) = tag.strip_prefix("#each") {
+            Token::OpenEach(block_argument("#each", path)?)
+        } else if tag == "else" {
+            Token::Else
+        } else if let Some(name) = tag.strip_prefix('/') {
+            Token::Close(name.trim().to_string())
+        } else if tag.starts_with('#') {
+            return Err(TemplateError::Syntax(format!("unknown block '{}'", tag)));
+        } else {
+            Token::Variable {
+                expression: tag.to_string(),
+                escape: true,
+            }
+        };
+        tokens.push(token);
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+fn block_argument(block: &str, argument: &str) -> Result<String, TemplateError> {
+    let path = argument.trim();
+    if path.is_empty() || !argument.starts_with(char::is_whitespace) {
+        return Err(TemplateError::Syntax(format!("{} requires a variable name", block)));
+    }
+    validate_path(path)?;
+    Ok(path.to_string())
+}
+
+fn validate_path(path: &str) -> Result<(), TemplateError> {
+    let valid = path == "this"
+        || path == "@index"
+        || path.split('.').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(TemplateError::Syntax(format!("invalid variable name '{}'", path)))
+    }
+}
+
+fn excerpt(text: &str) -> String {
+    text.chars().take(20).collect()
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, TemplateError> {
+    let tokens = tokenize(source)?;
+    let mut position = 0;
+    let (nodes, terminator) = parse_block(&tokens, &mut position, 0)?;
+
+    match terminator {
+        None => Ok(nodes),
+        Some(Token::Else) => Err(TemplateError::Syntax("{{else}} outside of {{#if}}".to_string())),
+        Some(Token::Close(name)) => Err(TemplateError::Syntax(format!(
+            "unexpected {{{{/{}}}}}",
+            name
+        ))),
+        Some(_) => unreachable!("parse_block only stops on else or close tags"),
+    }
+}
+
+// Parses nodes until the end of input or an {{else}} / {{/..}} tag, which is returned to the caller
+fn parse_block<'a>(
+    tokens: &'a [Token],
+    position: &mut usize,
+    depth: usize,
+) -> Result<(Vec<Node>, Option<&'a Token>), TemplateError> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.get(*position) {
+        *position += 1;
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.clone())),
+            Token::Variable { expression, escape } => {
+                let (path, default) = parse_expression(expression)?;
+                nodes.push(Node::Variable {
+                    path,
+                    escape: *escape,
+                    default,
+                });
+            }
+            Token::OpenIf(_) | Token::OpenEach(_) if depth >= MAX_BLOCK_DEPTH => {
+                return Err(TemplateError::Syntax(format!(
+                    "blocks nested more than {} deep",
+                    MAX_BLOCK_DEPTH
+                )));
+            }
+            Token::OpenIf(path) => {
+                let (then, terminator) = parse_block(tokens, position, depth + 1)?;
+                let otherwise = match terminator {
+                    Some(Token::Else) => {
+                        let (otherwise, terminator) = parse_block(tokens, position, depth + 1)?;
+                        expect_close("if", terminator)?;
+                        otherwise
+                    }
+                    terminator => {
+                        expect_close("if", terminator)?;
+                        Vec::new()
+                    }
+                };
+                nodes.push(Node::If {
+                    path: path.clone(),
+                    then,
+                    otherwise,
+                });
+            }
+            Token::OpenEach(path) => {
+                let (body, terminator) = parse_block(tokens, position, depth + 1)?;
+                expect_close("each", terminator)?;
+                nodes.push(Node::Each {
+                    path: path.clone(),
+                    body,
+                });
+            }
+            Token::Else | Token::Close(_) => return Ok((nodes, Some(token))),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn expect_close(block: &str, terminator: Option<&Token>) -> Result<(), TemplateError> {
+    match terminator {
+        Some(Token::Close(name)) if name == block => Ok(()),
+        Some(Token::Close(name)) => Err(TemplateError::Syntax(format!(
+            "{{{{#{}}}}} closed by {{{{/{}}}}}",
+            block, name
+        ))),
+        Some(_) => Err(TemplateError::Syntax(format!(
+            "unexpected {{{{else}}}} in {{{{#{}}}}}",
+            block
+        ))),
+        None => Err(TemplateError::Syntax(format!(
+            "missing {{{{/{}}}}}",
+            block
+        ))),
+    }
+}
+
+// "name" or "name | default: \"fallback\""
+fn parse_expression(expression: &str) -> Result<(String, Option<String>), TemplateError> {
+    let Some((path, filter)) = expression.split_once('|') else {
+        let path = expression.trim();
+        validate_path(path)?;
+        return Ok((path.to_string(), None));
+    };
+
+    let path = path.trim();
+    validate_path(path)?;
+
+    let argument = filter
+        .trim()
+        .strip_prefix("default")
+        .and_then(|rest| rest.trim_start().strip_prefix(':'))
+        .map(str::trim)
+        .ok_or_else(|| {
+            TemplateError::Syntax(format!("unknown filter in '{}'", expression.trim()))
+        })?;
+
+    let default = match argument.chars().next() {
+        Some(quote @ ('"' | '\'')) if argument.len() >= 2 && argument.ends_with(quote) => {
+            argument[1..argument.len() - 1].to_string()
+        }
+        _ => {
+            return Err(TemplateError::Syntax(format!(
+                "default value must be quoted in '{}'",
+                expression.trim()
+            )))
+        }
+    };
+
+    Ok((path.to_string(), Some(default)))
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    frames: &mut Vec<Frame<'_>>,
+    escape_html: bool,
+    output: &mut String,
+    missing: &mut Vec<String>,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable {
+                path,
+                escape,
+                default,
+            } => {
+                let value = resolve(frames, path);
+                let text = match (value.as_ref().and_then(value_to_string), default) {
+                    (Some(text), Some(default)) if text.is_empty() => default.clone(),
+                    (Some(text), _) => text,
+                    (None, Some(default)) => default.clone(),
+                    (None, None) => {
+                        if !missing.contains(path) {
+                            missing.push(path.clone());
+                        }
+                        continue;
+                    }
+                };
+
+                if *escape && escape_html {
+                    output.push_str(&escape_html_text(&text));
+                } else {
+                    output.push_str(&text);
+                }
+            }
+            Node::If {
+                path,
+                then,
+                otherwise,
+            } => {
+                let branch = if resolve(frames, path).is_some_and(|value| is_truthy(&value)) {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(branch, frames, escape_html, output, missing);
+            }
+            Node::Each { path, body } => {
+                let items = match resolve(frames, path) {
+                    Some(Value::Array(items)) => items,
+                    _ => continue,
+                };
+                for (index, item) in items.iter().enumerate() {
+                    // Frames borrow from the variables, so each item is rendered against its own stack
+                    let mut item_frames: Vec<Frame<'_>> = frames
+                        .iter()
+                        .map(|frame| Frame {
+                            value: frame.value,
+                            index: frame.index,
+                        })
+                        .collect();
+                    item_frames.push(Frame {
+                        value: item,
+                        index: Some(index),
+                    });
+                    render_nodes(body, &mut item_frames, escape_html, output, missing);
+                }
+            }
+        }
+    }
+}
+
+fn resolve(frames: &[Frame<'_>], path: &str) -> Option<Value> {
+    let current = frames.last()?;
+
+    if path == "@index" {
+        return current.index.map(Value::from);
+    }
+
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    let mut value = if first == "this" {
+        current.value
+    } else {
+        frames
+            .iter()
+            .rev()
+            .find_map(|frame| frame.value.get(first))?
+    };
+
+    for segment in segments {
+        value = match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => value.get(segment)?,
+        };
+    }
+
+    Some(value.clone())
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Array(_) | Value::Object(_) => Some(value.to_string()),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+fn escape_html_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, variables: Value) -> Result<String, TemplateError> {
+        render_template(source, &variables, true)
+    }
+
+    #[test]
+    fn substitutes_and_escapes_variables() {
+        let variables = json!({ "name": "<Ann & Bob>", "user": { "plan": "pro" } });
+        assert_eq!(
+            render("Hi {{ name }}, {{{ name }}} on {{user.plan}}", variables).unwrap(),
+            "Hi &lt;Ann &amp; Bob&gt;, <Ann & Bob> on pro"
+        );
+        assert_eq!(
+            render_template("{{ name }}", &json!({ "name": "<b>" }), false).unwrap(),
+            "<b>"
+        );
+    }
+
+    #[test]
+    fn reports_every_missing_variable_once() {
+        match render("{{ a }} {{ b }} {{ a }}", json!({})) {
+            Err(TemplateError::MissingVariables(missing)) => assert_eq!(missing, vec!["a", "b"]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_cover_missing_null_and_empty_values() {
+        let source = "{{ a | default: \"x\" }}{{ b | default: 'y' }}{{ c | default: \"z\" }}{{ d | default: \"w\" }}";
+        assert_eq!(render(source, json!({ "b": null, "c": "", "d": "set" })).unwrap(), "xyzset");
+    }
+
+    #[test]
+    fn renders_if_else_blocks() {
+        let source = "{{#if vip}}VIP{{else}}regular{{/if}}";
+        assert_eq!(render(source, json!({ "vip": true })).unwrap(), "VIP");
+        assert_eq!(render(source, json!({ "vip": 0 })).unwrap(), "regular");
+        assert_eq!(render(source, json!({})).unwrap(), "regular");
+    }
+
+    #[test]
+    fn renders_each_blocks_with_index_and_outer_names() {
+        let source = "{{#each items}}{{@index}}:{{this.sku}}@{{shop}} {{/each}}";
+        let variables = json!({ "shop": "s1", "items": [{ "sku": "a" }, { "sku": "b" }] });
+        assert_eq!(render(source, variables).unwrap(), "0:a@s1 1:b@s1 ");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        for source in [
+            "{{ name",
+            "{{}}",
+            "{{#if}}x{{/if}}",
+            "{{#if a}}x",
+            "{{#if a}}x{{/each}}",
+            "{{else}}",
+            "{{/if}}",
+            "{{#unless a}}{{/unless}}",
+            "{{ a | upper }}",
+            "{{ a | default: x }}",
+            "{{ a b }}",
+        ] {
+            assert!(matches!(validate_template(source), Err(TemplateError::Syntax(_))), "{}", source);
+        }
+    }
+
+    #[test]
+    fn caps_block_nesting_depth() {
+        let nested = |depth: usize| "{{#if a}}".repeat(depth) + &"{{/if}}".repeat(depth);
+        assert!(validate_template(&nested(MAX_BLOCK_DEPTH)).is_ok());
+        assert!(matches!(
+            validate_template(&nested(MAX_BLOCK_DEPTH + 1)),
+            Err(TemplateError::Syntax(_))
+        ));
+        // Deep enough to overflow the stack without the cap
+        assert!(validate_template(&nested(100_000)).is_err());
+    }
+}
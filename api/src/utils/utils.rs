@@ -1,5 +1,5 @@
 use actix_web::HttpResponse;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 pub fn get_env(key: &str, fallback: &str) -> String {
     match std::env::var(key) {
@@ -8,6 +8,24 @@ pub fn get_env(key: &str, fallback: &str) -> String {
     }
 }
 
+// accepts either a single string or a list of strings
+pub fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
 // create a reponse Object
 // lets derive some traits for serialization and deserialization
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -61,6 +79,7 @@ pub fn service_response(
         202 => HttpResponse::Accepted().json(response.to_json()),
         400 => HttpResponse::BadRequest().json(response.to_json()),
         401 => HttpResponse::Unauthorized().json(response.to_json()),
+        402 => HttpResponse::PaymentRequired().json(response.to_json()),
         403 => HttpResponse::Forbidden().json(response.to_json()),
         404 => HttpResponse::NotFound().json(response.to_json()),
         422 => HttpResponse::UnprocessableEntity().json(response.to_json()),
@@ -0,0 +1,178 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Webhook requests carry `X-MailNow-Signature: t=<unix seconds>,v1=<hex HMAC-SHA256>`,
+// where the HMAC is keyed with the webhook secret over "<t>.<raw request body>".
+// Receivers recompute it with `verify_signature` and reject stale timestamps to stop replays.
+
+pub const SIGNATURE_HEADER: &str = "X-MailNow-Signature";
+pub const DEFAULT_TOLERANCE_SECS: i64 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Signature header is malformed")]
+    Malformed,
+
+    #[error("Signature timestamp is outside the tolerance window")]
+    TimestampOutOfTolerance,
+
+    #[error("No signature matches the payload")]
+    Mismatch,
+}
+
+pub fn generate_secret() -> String {
+    format!("whsec_{}", hex::encode(rand::random::<[u8; 32]>()))
+}
+
+// Header value for a delivery made at `timestamp`
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("t={},v1={}", timestamp, hex::encode(compute_signature(secret, timestamp, body)))
+}
+
+pub fn verify_signature(
+    secret: &str,
+    header: &str,
+    body: &[u8],
+    tolerance_secs: i64,
+) -> Result<(), SignatureError> {
+    verify_signature_at(secret, header, body, tolerance_secs, chrono::Utc::now().timestamp())
+}
+
+pub fn verify_signature_at(
+    secret: &str,
+    header: &str,
+    body: &[u8],
+    tolerance_secs: i64,
+    now: i64,
+) -> Result<(), SignatureError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => {
+                timestamp = Some(value.parse::<i64>().map_err(|_| SignatureError::Malformed)?)
+            }
+            Some(("v1", value)) => signatures.push(value),
+            // Unknown schemes are ignored so new ones can be added alongside v1
+            Some(_) => {}
+            None => return Err(SignatureError::Malformed),
+        }
+    }
+
+    let timestamp = timestamp.ok_or(SignatureError::Malformed)?;
+    if signatures.is_empty() {
+        return Err(SignatureError::Malformed);
+    }
+    // abs_diff can't overflow, whatever timestamp the header claims
+    if now.abs_diff(timestamp) > tolerance_secs.max(0) as u64 {
+        return Err(SignatureError::TimestampOutOfTolerance);
+    }
+
+    let matches = signatures.iter().any(|signature| {
+        hex::decode(signature).is_ok_and(|expected| {
+            signing_mac(secret, timestamp, body)
+                .verify_slice(&expected)
+                .is_ok()
+        })
+    });
+    if matches {
+        Ok(())
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
+fn compute_signature(secret: &str, timestamp: i64, body: &[u8]) -> Vec<u8> {
+    signing_mac(secret, timestamp, body)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn signing_mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn accepts_its_own_signature() {
+        let header = sign_payload(SECRET, NOW, b"{}");
+        assert_eq!(verify_signature_at(SECRET, &header, b"{}", DEFAULT_TOLERANCE_SECS, NOW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_tampered_body_or_wrong_secret() {
+        let header = sign_payload(SECRET, NOW, b"{}");
+        assert_eq!(
+            verify_signature_at(SECRET, &header, b"{\"a\":1}", DEFAULT_TOLERANCE_SECS, NOW),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_signature_at("whsec_other", &header, b"{}", DEFAULT_TOLERANCE_SECS, NOW),
+            Err(SignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn accepts_any_matching_v1_and_ignores_unknown_schemes() {
+        let signed = sign_payload(SECRET, NOW, b"{}");
+        let header = format!("{},v1=00ff,v2=abc", signed);
+        assert_eq!(verify_signature_at(SECRET, &header, b"{}", DEFAULT_TOLERANCE_SECS, NOW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_the_tolerance() {
+        let stale = sign_payload(SECRET, NOW - DEFAULT_TOLERANCE_SECS - 1, b"{}");
+        assert_eq!(
+            verify_signature_at(SECRET, &stale, b"{}", DEFAULT_TOLERANCE_SECS, NOW),
+            Err(SignatureError::TimestampOutOfTolerance)
+        );
+        let future = sign_payload(SECRET, NOW + DEFAULT_TOLERANCE_SECS + 1, b"{}");
+        assert_eq!(
+            verify_signature_at(SECRET, &future, b"{}", DEFAULT_TOLERANCE_SECS, NOW),
+            Err(SignatureError::TimestampOutOfTolerance)
+        );
+    }
+
+    #[test]
+    fn extreme_timestamps_do_not_overflow() {
+        for timestamp in [i64::MIN, i64::MAX] {
+            let header = format!("t={},v1=00", timestamp);
+            assert_eq!(
+                verify_signature_at(SECRET, &header, b"{}", DEFAULT_TOLERANCE_SECS, NOW),
+                Err(SignatureError::TimestampOutOfTolerance)
+            );
+        }
+        let header = format!("t={},v1=00", NOW);
+        assert_eq!(
+            verify_signature_at(SECRET, &header, b"{}", DEFAULT_TOLERANCE_SECS, i64::MIN),
+            Err(SignatureError::TimestampOutOfTolerance)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in ["", "v1=00", "t=abc,v1=00", "t=1", "garbage"] {
+            assert_eq!(
+                verify_signature_at(SECRET, header, b"{}", DEFAULT_TOLERANCE_SECS, NOW),
+                Err(SignatureError::Malformed),
+                "{}",
+                header
+            );
+        }
+    }
+}